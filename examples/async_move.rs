@@ -0,0 +1,63 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use generic_cursors::refcell::{MoveDecision, RefCellRefMutStack};
+
+struct Node<'a> {
+    name: &'a str,
+    next: Option<Rc<RefCell<Node<'a>>>>,
+}
+
+/// Run a future that never waits to completion.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// Stands in for some asynchronous work, e.g. loading the node's contents.
+async fn visit(name: &str) {
+    println!("Visiting {name}");
+}
+
+fn main() {
+    let names = [String::from("a"), String::from("b"), String::from("c")];
+    let list = names.iter().rev().fold(None, |next, name| {
+        Some(Rc::new(RefCell::new(Node { name, next })))
+    });
+    let head = list.unwrap();
+
+    let mut stack = RefCellRefMutStack::new(&head).expect("not borrowed yet");
+    let mut visited = 0;
+    block_on(async {
+        loop {
+            let depth = stack.depth();
+            // The async closure borrows the top, and the decision borrows from it,
+            // without boxing the future or naming any lifetime.
+            let result = stack
+                .move_with_async_fn(async |node| {
+                    visit(node.name).await;
+                    visited += 1;
+                    match node.next.as_deref() {
+                        Some(next) => MoveDecision::Descend(next),
+                        None => MoveDecision::Stay,
+                    }
+                })
+                .await;
+            result.expect("no node is borrowed twice");
+            if stack.depth() == depth {
+                break;
+            }
+        }
+    });
+    println!("Visited {visited} nodes, stopped at {}", stack.top().name);
+}
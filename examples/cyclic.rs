@@ -1,6 +1,6 @@
 use std::{cell::RefCell, rc::Rc};
 
-use generic_cursors::refcell::RefCellRefMutStack;
+use generic_cursors::refcell::{MoveDecision, RefCellRefMutStack};
 
 #[derive(Debug, Clone)]
 pub struct CyclicDataStructure<T> {
@@ -41,21 +41,15 @@ fn main() {
     // Using a MutRefStack to descend *and then ascend* the data structure.
     // This cannot be done with regular mutable references.
     let mut stack = RefCellRefMutStack::new(&cycle_a).expect("not mutable borrowed yet");
+    let result = stack.drive(|node| {
+        println!("Stack currently at item with value: {}", node.data);
+        MoveDecision::<_, ()>::descend(node.next().expect("no node has no next"))
+    });
+    assert!(result.is_err(), "the list is cyclic");
+    println!("Found a cycle closing at depth {}!", stack.depth() + 1);
     println!("Stack currently at item with value: {}", stack.top().data);
     loop {
-        if let Err(_borrow_error) = stack
-            .descend_with(CyclicDataStructure::next)
-            .expect("no node has no next")
-        {
-            println!("Found a cycle!");
-            break;
-        }
-        println!("Descended successfully!");
-        println!("Stack currently at item with value: {}", stack.top().data);
-    }
-    println!("Stack currently at item with value: {}", stack.top().data);
-    loop {
-        if let None = stack.ascend() {
+        if stack.ascend().is_none() {
             println!("Reached the head of the linked list!");
             break;
         }
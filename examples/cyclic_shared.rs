@@ -0,0 +1,56 @@
+use std::{cell::RefCell, rc::Rc};
+
+use generic_cursors::refcell::RefCellRefStack;
+
+#[derive(Debug, Clone)]
+pub struct CyclicDataStructure<T> {
+    data: T,
+    next: Option<Rc<RefCell<Self>>>,
+}
+
+impl<T> CyclicDataStructure<T> {
+    fn next(&self) -> Option<&RefCell<Self>> {
+        self.next.as_deref()
+    }
+}
+
+fn main() {
+    let cycle_a = Rc::new(RefCell::new(CyclicDataStructure {
+        data: 0_u32,
+        next: None,
+    }));
+    let cycle_b = Rc::new(RefCell::new(CyclicDataStructure {
+        data: 1_u32,
+        next: Some(cycle_a.clone()),
+    }));
+    let unrelated = RefCell::new(CyclicDataStructure {
+        data: 2_u32,
+        next: None,
+    });
+    cycle_a.borrow_mut().next = Some(cycle_b.clone());
+
+    // A RefCellRefStack only takes shared borrows, so it can go around the cycle,
+    // while other shared borrows of the same nodes and mutable borrows of other nodes are alive.
+    let shared_b = cycle_b.borrow();
+    let mut unrelated = unrelated.borrow_mut();
+    let mut stack = RefCellRefStack::new(&cycle_a).expect("not mutably borrowed");
+    for _ in 0..5 {
+        let top = stack
+            .descend_with(CyclicDataStructure::next)
+            .expect("no node has no next")
+            .expect("no node is mutably borrowed");
+        println!("Stack currently at item with value: {}", top.data);
+    }
+    unrelated.data += shared_b.data;
+    println!("Unrelated item now has value: {}", unrelated.data);
+
+    // A clone of the stack shares its borrows.
+    let mut clone = stack.clone();
+    clone.ascend_while(|node| node.data != 0);
+    println!("Clone ascended to depth {}", clone.depth());
+    assert!(cycle_a.try_borrow_mut().is_err());
+    drop((stack, clone));
+
+    println!("(Breaking the cycle to prevent miri from complaining about memory leaks)");
+    cycle_a.borrow_mut().next.take();
+}
@@ -1,6 +1,6 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, TryLockError};
 
-use generic_cursors::mutex::MutexGuardStack;
+use generic_cursors::mutex::{DescendResult, MutexGuardStack, PoisonPolicy};
 
 #[derive(Debug, Clone)]
 pub struct CyclicDataStructure<T> {
@@ -32,26 +32,28 @@ fn main() {
             next: Some(current),
         }))
     }
-    cycle_root.lock().unwrap().next = Some(current);
+    cycle_root.lock().unwrap().insert_next(current);
 
     // Using a MutRefStack to descend *and then ascend* the data structure.
     // This cannot be done with regular mutable references.
     let mut stack = MutexGuardStack::new(&cycle_root).expect("not mutable borrowed yet");
     println!("Stack currently at item with value: {}", stack.top().data);
     loop {
-        if let Err(_borrow_error) = stack
-            .descend_with(CyclicDataStructure::next, false)
-            .expect("no node has no next")
-        {
-            println!("Found a cycle!");
-            break;
+        match stack.try_descend(CyclicDataStructure::next, PoisonPolicy::Fail) {
+            DescendResult::Descended(_) => {}
+            DescendResult::NoChild => unreachable!("no node has no next"),
+            DescendResult::LockFailed(TryLockError::WouldBlock) => {
+                println!("Found a cycle!");
+                break;
+            }
+            DescendResult::LockFailed(err) => panic!("{err}"),
         }
         println!("Descended successfully!");
         println!("Stack currently at item with value: {}", stack.top().data);
     }
     println!("Stack currently at item with value: {}", stack.top().data);
     loop {
-        if let None = stack.ascend() {
+        if stack.ascend().is_none() {
             println!("Reached the head of the linked list!");
             break;
         }
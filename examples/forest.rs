@@ -21,13 +21,13 @@ pub fn preorder_traverse<T, F: FnMut(&mut T, usize)>(tree: &mut Forest<T>, mut c
             depth: 0,
         },
     );
-    loop {
-        let Ok(_) = cursor.move_with(|element, state| {
+    while cursor
+        .move_with(|element, state| {
             if state.next_index >= element.len() {
                 MoveDecision::Ascend
             } else {
                 callback(&mut element[state.next_index].data, state.depth);
-                let decision = MoveDecision::Descend(
+                let decision = MoveDecision::descend_with_data(
                     &mut *element[state.next_index].children,
                     TraversalState {
                         next_index: 0,
@@ -37,10 +37,55 @@ pub fn preorder_traverse<T, F: FnMut(&mut T, usize)>(tree: &mut Forest<T>, mut c
                 state.next_index += 1;
                 decision
             }
-        }) else {
-            break;
-        };
+        })
+        .is_ok()
+    {}
+}
+
+/// Sum the nodes in preorder until (and excluding) the first node equal to `stop`.
+/// Each level's additional data holds the sum of the subtrees of its siblings visited so far,
+/// which is folded into the parent's when ascending.
+pub fn sum_until(tree: &mut Forest<u32>, stop: u32) -> u32 {
+    struct SumState {
+        next_index: usize,
+        sum: u32,
+    }
+    fn combine(child: SumState, parent: &mut SumState) {
+        parent.sum += child.sum;
     }
+    let mut cursor = MutRefStackWithData::new(
+        &mut *tree.roots,
+        SumState {
+            next_index: 0,
+            sum: 0,
+        },
+    );
+    let mut stopped = false;
+    while cursor
+        .move_with(|element, state| match element.get_mut(state.next_index) {
+            None => MoveDecision::AscendFold(Box::new(combine)),
+            Some(node) if node.data == stop => {
+                stopped = true;
+                MoveDecision::Stay
+            }
+            Some(node) => {
+                state.sum += node.data;
+                state.next_index += 1;
+                MoveDecision::descend_with_data(
+                    &mut *node.children,
+                    SumState {
+                        next_index: 0,
+                        sum: 0,
+                    },
+                )
+            }
+        })
+        .is_ok()
+        && !stopped
+    {}
+    // Stopping early leaves partial sums on the stack, so fold them all into the root's.
+    let (_, state) = cursor.ascend_while_fold(|_, _| true, combine);
+    state.sum
 }
 
 fn main() {
@@ -94,4 +139,7 @@ fn main() {
     preorder_traverse(&mut forest, |t, depth| {
         println!("{:depth$}{t}", "");
     });
+    println!();
+    println!("Sum of all nodes: {}", sum_until(&mut forest, u32::MAX));
+    println!("Sum of the nodes before 64: {}", sum_until(&mut forest, 64));
 }
@@ -12,7 +12,7 @@ impl<T> SimpleLinkedList<T> {
         self.child.as_deref_mut()
     }
     fn insert_child(&mut self, new_child: Box<Self>) -> Option<Box<Self>> {
-        std::mem::replace(&mut self.child, Some(new_child))
+        self.child.replace(new_child)
     }
 }
 
@@ -50,7 +50,7 @@ fn main() {
     let mut stack = MutRefStack::new(&mut the_t);
     println!("Stack currently at item with value: {}", stack.top().data);
     loop {
-        if let None = stack.descend_with(SimpleLinkedList::child_mut) {
+        if stack.descend_with(SimpleLinkedList::child_mut).is_none() {
             println!("Reached the end of the linked list!");
             break;
         }
@@ -59,7 +59,7 @@ fn main() {
     }
     println!("Stack currently at item with value: {}", stack.top().data);
     loop {
-        if let None = stack.ascend() {
+        if stack.ascend().is_none() {
             println!("Reached the head of the linked list!");
             break;
         }
@@ -0,0 +1,89 @@
+use generic_cursors::slice::{SliceChildren, SliceCursor};
+
+pub struct Forest<T> {
+    roots: Vec<ForestNode<T>>,
+}
+
+struct ForestNode<T> {
+    data: T,
+    children: Vec<ForestNode<T>>,
+}
+
+impl<T> SliceChildren for ForestNode<T> {
+    fn children_mut(&mut self) -> &mut [Self] {
+        &mut self.children
+    }
+}
+
+pub fn preorder_traverse<T, F: FnMut(&mut T, usize)>(tree: &mut Forest<T>, mut callback: F) {
+    let mut cursor = SliceCursor::new(&mut tree.roots);
+    // The index of the next element of the top to visit.
+    let mut next_index = 0;
+    loop {
+        if next_index < cursor.top().len() {
+            let depth = cursor.depth();
+            callback(&mut cursor.top_mut()[next_index].data, depth);
+            let Ok(_) = cursor.descend_element(next_index) else {
+                unreachable!();
+            };
+            next_index = 0;
+        } else if let Some((index, _)) = cursor.ascend() {
+            next_index = index + 1;
+        } else {
+            break;
+        }
+    }
+}
+
+fn main() {
+    let mut forest = Forest {
+        roots: vec![
+            ForestNode {
+                data: 0u32,
+                children: vec![
+                    ForestNode {
+                        data: 1u32,
+                        children: vec![],
+                    },
+                    ForestNode {
+                        data: 2u32,
+                        children: vec![],
+                    },
+                    ForestNode {
+                        data: 3u32,
+                        children: vec![],
+                    },
+                ],
+            },
+            ForestNode {
+                data: 4u32,
+                children: vec![],
+            },
+            ForestNode {
+                data: 5u32,
+                children: vec![],
+            },
+            ForestNode {
+                data: 6u32,
+                children: vec![ForestNode {
+                    data: 7u32,
+                    children: vec![ForestNode {
+                        data: 8u32,
+                        children: vec![ForestNode {
+                            data: 9u32,
+                            children: vec![],
+                        }],
+                    }],
+                }],
+            },
+        ],
+    };
+    preorder_traverse(&mut forest, |t, depth| {
+        println!("{:depth$}{t}", "");
+        *t *= *t;
+    });
+    println!();
+    preorder_traverse(&mut forest, |t, depth| {
+        println!("{:depth$}{t}", "");
+    });
+}
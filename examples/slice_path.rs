@@ -0,0 +1,60 @@
+use generic_cursors::slice::{SliceChildren, SliceCursor};
+
+struct Node {
+    name: &'static str,
+    children: Vec<Node>,
+}
+
+impl SliceChildren for Node {
+    fn children_mut(&mut self) -> &mut [Self] {
+        &mut self.children
+    }
+}
+
+fn node(name: &'static str, children: Vec<Node>) -> Node {
+    Node { name, children }
+}
+
+/// Find the first node named `name` in preorder, returning the path of indices to it.
+fn find(roots: &mut [Node], name: &str) -> Option<Vec<usize>> {
+    let mut cursor = SliceCursor::new(roots);
+    let mut next_index = 0;
+    loop {
+        if next_index < cursor.top().len() {
+            if cursor.top()[next_index].name == name {
+                let mut path = cursor.path().to_vec();
+                path.push(next_index);
+                return Some(path);
+            }
+            let Ok(_) = cursor.descend_element(next_index) else {
+                unreachable!();
+            };
+            next_index = 0;
+        } else if let Some((index, _)) = cursor.ascend() {
+            next_index = index + 1;
+        } else {
+            return None;
+        }
+    }
+}
+
+fn main() {
+    let mut forest = vec![
+        node(
+            "a",
+            vec![node("b", vec![]), node("c", vec![node("d", vec![])])],
+        ),
+        node("e", vec![node("f", vec![])]),
+    ];
+
+    let path = find(&mut forest, "d").unwrap();
+    println!("path to d: {path:?}");
+
+    // The cursor is gone, but the path is still meaningful, so a new cursor can replay it.
+    let (&last, parents) = path.split_last().unwrap();
+    let mut cursor = SliceCursor::new(&mut forest);
+    let siblings = cursor.descend_path(parents).unwrap();
+    siblings[last].name = "d (renamed)";
+
+    println!("{}", forest[0].children[1].children[0].name);
+}
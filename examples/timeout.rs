@@ -0,0 +1,57 @@
+use std::{
+    sync::{Arc, Barrier},
+    thread,
+    time::Duration,
+};
+
+use generic_cursors::guard::ParkingLotMutexGuardStack;
+use parking_lot::Mutex;
+
+struct Node {
+    data: u32,
+    next: Option<Arc<Mutex<Node>>>,
+}
+
+impl Node {
+    fn next(&mut self) -> Option<&Mutex<Node>> {
+        self.next.as_deref()
+    }
+}
+
+fn main() {
+    let mut head = None;
+    for data in (0..4).rev() {
+        head = Some(Arc::new(Mutex::new(Node { data, next: head })));
+    }
+    let head = head.unwrap();
+    let second = Arc::clone(head.lock().next.as_ref().unwrap());
+    let holding = Barrier::new(2);
+
+    thread::scope(|scope| {
+        // The slow thread holds the second node for a while.
+        scope.spawn(|| {
+            let mut stack = ParkingLotMutexGuardStack::new(&second).expect("not locked yet");
+            holding.wait();
+            println!("slow: holding the node with value {}", stack.top().data);
+            thread::sleep(Duration::from_millis(200));
+            stack.top_mut().data += 10;
+            println!("slow: releasing it");
+        });
+
+        // The impatient thread only waits a little for each node.
+        scope.spawn(|| {
+            holding.wait();
+            let mut stack = ParkingLotMutexGuardStack::new(&head).expect("not locked yet");
+            loop {
+                match stack.descend_with_timeout(Node::next, Duration::from_millis(20)) {
+                    None => break,
+                    Some(Ok(top)) => {
+                        println!("impatient: descended to the node with value {}", top.data)
+                    }
+                    Some(Err(_)) => println!("impatient: timed out, trying again"),
+                }
+            }
+            println!("impatient: reached the tail at depth {}", stack.depth());
+        });
+    });
+}
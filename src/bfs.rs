@@ -0,0 +1,97 @@
+use std::{collections::VecDeque, marker::PhantomData};
+
+/// A cursor that visits the nodes of a recursive data structure in breadth-first (level) order.
+///
+/// Unlike the other cursors, this does not ascend or descend: it only yields each node in turn.
+/// This is sound because at most one `&mut T` obtained from this cursor is live at a time,
+/// and the queued pointers all point into disjoint subtrees.
+/// The children of a node are only queued once the reference to that node has been released,
+/// so modifying a node's children while visiting it is fine.
+pub struct BfsCursor<'root, T, F> {
+    /// Ensures this cursor does not exceed the lifetime of its root.
+    lifetime: PhantomData<&'root mut T>,
+    /// The nodes that are yet to be visited.
+    pending: VecDeque<*mut T>,
+    /// The most recently visited node, whose children have not been queued yet.
+    last: Option<*mut T>,
+    children: F,
+}
+
+impl<'root, T, F> BfsCursor<'root, T, F>
+where
+    F: for<'node> FnMut(&'node mut T) -> &'node mut [T],
+{
+    /// Create a new BfsCursor from a mutable reference to the root
+    /// of a recursive data structure, and a closure returning the children of a node.
+    pub fn new(root: &'root mut T, children: F) -> Self {
+        Self {
+            lifetime: PhantomData,
+            pending: VecDeque::from([root as *mut T]),
+            last: None,
+            children,
+        }
+    }
+
+    /// Visit the next node in breadth-first order, or return None if every node has been visited.
+    // This cannot be `Iterator::next`, since the returned reference must be released before the next call.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&mut T> {
+        if let Some(last) = self.last.take() {
+            let children = (self.children)(unsafe { &mut *last });
+            self.pending
+                .extend(children.iter_mut().map(|child| child as *mut T));
+        }
+        let next = self.pending.pop_front()?;
+        self.last = Some(next);
+        Some(unsafe { &mut *next })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::Node;
+
+    /// The forest from the `forest` example, under a root with the value `100`.
+    fn forest() -> Node<u32> {
+        let mut root = Node::new(100);
+        let zero = root.push_child(Node::new(0));
+        for value in 1..=3 {
+            zero.push_child(Node::new(value));
+        }
+        root.push_child(Node::new(4));
+        root.push_child(Node::new(5));
+        root.push_child(Node::new(6))
+            .push_child(Node::new(7))
+            .push_child(Node::new(8))
+            .push_child(Node::new(9));
+        root
+    }
+
+    #[test]
+    fn level_order() {
+        let mut root = forest();
+        let mut cursor = BfsCursor::new(&mut root, |node: &mut Node<u32>| node.children_mut());
+        let mut visited = vec![];
+        while let Some(node) = cursor.next() {
+            visited.push(node.data);
+        }
+        assert_eq!(visited, [100, 0, 4, 5, 6, 1, 2, 3, 7, 8, 9]);
+        assert!(cursor.next().is_none());
+    }
+
+    #[test]
+    fn modify_children_while_visiting() {
+        let mut root = Node::new(1);
+        let mut cursor = BfsCursor::new(&mut root, |node: &mut Node<u32>| node.children_mut());
+        let mut visited = vec![];
+        while let Some(node) = cursor.next() {
+            visited.push(node.data);
+            if node.data < 8 {
+                node.push_child(Node::new(node.data * 2));
+                node.push_child(Node::new(node.data * 2 + 1));
+            }
+        }
+        assert_eq!(visited, (1..16).collect::<Vec<_>>());
+    }
+}
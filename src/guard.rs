@@ -0,0 +1,922 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    ops::DerefMut,
+    ptr,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockWriteGuard, TryLockError, TryLockResult,
+    },
+};
+
+use crate::{DescendCtx, ToDepthError, TopId};
+
+/// A lock that can be held by every level of a `GuardStack`.
+///
+/// This is implemented for `Mutex` and, with exclusive (write) locks, for `RwLock`.
+/// With the `parking_lot` feature, it is also implemented for `parking_lot::Mutex` and `parking_lot::RwLock`.
+/// Lock types that cannot be poisoned should never return `TryLockError::Poisoned`.
+pub trait Lockable {
+    /// The type of the nodes behind the lock.
+    type Target: ?Sized;
+    /// The guard of a held lock.
+    type Guard<'a>: DerefMut<Target = Self::Target>
+    where
+        Self: 'a;
+
+    /// Try to lock exclusively, without blocking.
+    fn try_lock(&self) -> TryLockResult<Self::Guard<'_>>;
+
+    /// Clear the poison of the lock, for `PoisonPolicy::ClearAndContinue`.
+    /// Lock types that cannot be poisoned can keep the default, which does nothing.
+    fn clear_poison(&self) {}
+}
+
+impl<T: ?Sized> Lockable for Mutex<T> {
+    type Target = T;
+    type Guard<'a>
+        = MutexGuard<'a, T>
+    where
+        T: 'a;
+
+    fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        Mutex::try_lock(self)
+    }
+
+    fn clear_poison(&self) {
+        Mutex::clear_poison(self)
+    }
+}
+
+impl<T: ?Sized> Lockable for RwLock<T> {
+    type Target = T;
+    type Guard<'a>
+        = RwLockWriteGuard<'a, T>
+    where
+        T: 'a;
+
+    fn try_lock(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        RwLock::try_write(self)
+    }
+
+    fn clear_poison(&self) {
+        RwLock::clear_poison(self)
+    }
+}
+
+#[cfg(feature = "parking_lot")]
+impl<T: ?Sized> Lockable for parking_lot::Mutex<T> {
+    type Target = T;
+    type Guard<'a>
+        = parking_lot::MutexGuard<'a, T>
+    where
+        T: 'a;
+
+    fn try_lock(&self) -> TryLockResult<parking_lot::MutexGuard<'_, T>> {
+        parking_lot::Mutex::try_lock(self).ok_or(TryLockError::WouldBlock)
+    }
+}
+
+#[cfg(feature = "parking_lot")]
+impl<T: ?Sized> Lockable for parking_lot::RwLock<T> {
+    type Target = T;
+    type Guard<'a>
+        = parking_lot::RwLockWriteGuard<'a, T>
+    where
+        T: 'a;
+
+    fn try_lock(&self) -> TryLockResult<parking_lot::RwLockWriteGuard<'_, T>> {
+        parking_lot::RwLock::try_write(self).ok_or(TryLockError::WouldBlock)
+    }
+}
+
+/// How a `GuardStack` handles locking a poisoned lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoisonPolicy {
+    /// Fail to lock.
+    #[default]
+    Fail,
+    /// Lock anyway, leaving the lock poisoned.
+    IgnoreAndContinue,
+    /// Lock anyway, and clear the poison with `Lockable::clear_poison` (e.g. `Mutex::clear_poison`).
+    ClearAndContinue,
+}
+
+impl PoisonPolicy {
+    /// The policy equivalent to the deprecated `ignore_poison` arguments.
+    pub(crate) fn from_ignore_poison(ignore_poison: bool) -> Self {
+        if ignore_poison {
+            Self::IgnoreAndContinue
+        } else {
+            Self::Fail
+        }
+    }
+}
+
+/// A cursor over a recursive data structure of `RwLock`s, holding the write lock of every node from the root to the top.
+pub type RwLockWriteGuardStack<'root, T> = GuardStack<'root, RwLock<T>>;
+
+/// A cursor over a recursive data structure of `parking_lot::Mutex`es, holding the lock of every node from the root to the top.
+#[cfg(feature = "parking_lot")]
+pub type ParkingLotMutexGuardStack<'root, T> = GuardStack<'root, parking_lot::Mutex<T>>;
+
+/// A cursor over a recursive data structure of any `Lockable` type, holding the lock of every node from the root to the top.
+///
+/// `MutexGuardStack` is the `GuardStack` of `Mutex`es, which additionally has blocking and async descends,
+/// closure-driven moves, and more.
+///
+/// Like the guards, this is `Send` when the guards are `Send` (and the locks can be shared between threads),
+/// and `Sync` when the guards are `Sync`.
+pub struct GuardStack<'root, L: Lockable + ?Sized + 'root> {
+    /// Ensures this stack does not exceed the lifetime of its root.
+    lifetime: PhantomData<&'root mut L::Target>,
+    /// The stack of guards. Each one borrows from the one prior, except the first which is the `root` and may never be popped.
+    /// Note: the `'root` lifetime is a "lie", only used because there's no raw pointer counterpart for the guards.
+    /// The guards are not publicly accessible so this is fine.
+    pub(crate) data: Vec<Level<'root, L>>,
+    /// Whether any guard on this stack was recovered from a poisoned lock.
+    pub(crate) poisoned: bool,
+    /// Whether the most recent attempt to lock a new top would have blocked.
+    pub(crate) blocked: bool,
+    /// The default poison policy of this stack.
+    pub(crate) policy: PoisonPolicy,
+}
+
+// SAFETY: Moving the stack moves its guards, and the `Arc`s of owned levels.
+// The raw lock pointers are then only used on the new thread through `&mut self`, to lock and clear poison through `&L`.
+unsafe impl<'root, L: Lockable + ?Sized + Send + Sync + 'root> Send for GuardStack<'root, L> where
+    L::Guard<'root>: Send
+{
+}
+// SAFETY: A shared `&GuardStack` only gives out `&L::Target` (and addresses), exactly like a shared guard.
+// The raw lock pointers are only used through `&mut self`.
+unsafe impl<'root, L: Lockable + ?Sized + 'root> Sync for GuardStack<'root, L> where
+    L::Guard<'root>: Sync
+{
+}
+
+/// One level of a `GuardStack`.
+pub(crate) struct Level<'root, L: Lockable + ?Sized + 'root> {
+    pub(crate) guard: L::Guard<'root>,
+    /// The lock the guard was locked from, if known.
+    /// This is unknown for a root adopted with `GuardStack::from_guard`.
+    pub(crate) lock: Option<*const L>,
+    /// Whether the guard was recovered from a poisoned lock.
+    pub(crate) poisoned: bool,
+    /// For a level injected with `MutexGuardStack::inject_owned`, the `Arc` that keeps the lock alive.
+    /// Fields are dropped in order, so this is dropped after the guard.
+    pub(crate) owned: Option<Arc<L>>,
+}
+
+impl<'root, L: Lockable + ?Sized + 'root> Level<'root, L> {
+    pub(crate) fn new(guard: L::Guard<'root>, lock: Option<*const L>, poisoned: bool) -> Self {
+        if let Some(lock) = lock {
+            held::insert(lock.cast());
+        }
+        Self {
+            guard,
+            lock,
+            poisoned,
+            owned: None,
+        }
+    }
+
+    /// Take the guard out of this level, which is then no longer tracked as held by this thread.
+    /// The guard may borrow from the level's `Arc`, if any, so that is leaked.
+    pub(crate) fn into_guard(self) -> L::Guard<'root> {
+        let (guard, owned) = self.into_parts();
+        mem::forget(owned);
+        guard
+    }
+
+    /// Take the guard and the `Arc`, if any, out of this level, which is then no longer tracked as held by this thread.
+    /// The guard may borrow from the `Arc`, so it must be dropped first.
+    pub(crate) fn into_parts(self) -> (L::Guard<'root>, Option<Arc<L>>) {
+        let this = ManuallyDrop::new(self);
+        if let Some(lock) = this.lock {
+            held::remove(lock.cast());
+        }
+        // SAFETY: `this` is never used or dropped again.
+        unsafe { (ptr::read(&this.guard), ptr::read(&this.owned)) }
+    }
+}
+
+impl<'root, L: Lockable + ?Sized + 'root> Drop for Level<'root, L> {
+    fn drop(&mut self) {
+        if let Some(lock) = self.lock {
+            held::remove(lock.cast());
+        }
+    }
+}
+
+/// The addresses of the locks held by any `GuardStack` on the current thread,
+/// used by the `deadlock-detection` feature. Without the feature, nothing is tracked.
+pub(crate) mod held {
+    #[cfg(feature = "deadlock-detection")]
+    thread_local! {
+        static HELD: std::cell::RefCell<std::collections::HashSet<usize>> = Default::default();
+    }
+
+    pub(crate) fn insert(lock: *const ()) {
+        #[cfg(feature = "deadlock-detection")]
+        HELD.with(|held| held.borrow_mut().insert(lock as usize));
+        let _ = lock;
+    }
+
+    pub(crate) fn remove(lock: *const ()) {
+        #[cfg(feature = "deadlock-detection")]
+        HELD.with(|held| held.borrow_mut().remove(&(lock as usize)));
+        let _ = lock;
+    }
+
+    /// Panic if the `Mutex` is already locked by a stack on the current thread, since locking it would deadlock.
+    pub(crate) fn check(mutex: *const ()) {
+        #[cfg(feature = "deadlock-detection")]
+        if HELD.with(|held| held.borrow().contains(&(mutex as usize))) {
+            panic!(
+                "deadlock: the Mutex at {mutex:p} is already locked by a MutexGuardStack on this thread"
+            );
+        }
+        let _ = mutex;
+    }
+}
+
+/// The locks held by a `GuardStack`, returned by `GuardStack::guards_held`.
+/// Its `Display` implementation prints one line per level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuardReport {
+    /// The levels of the stack, from the root to the top.
+    pub levels: Vec<GuardInfo>,
+}
+
+/// One level of a `GuardReport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuardInfo {
+    /// How many levels above the root this level is.
+    pub depth: usize,
+    /// The address of the held lock (a `Mutex`, for a `MutexGuardStack`).
+    /// This is None for a root adopted with `GuardStack::from_guard`.
+    pub mutex: Option<*const ()>,
+    /// Whether the guard was recovered from a poisoned lock.
+    pub poisoned: bool,
+}
+
+// SAFETY: A `GuardInfo` only holds an address, it is never dereferenced.
+unsafe impl Send for GuardInfo {}
+// SAFETY: A `GuardInfo` only holds an address, it is never dereferenced.
+unsafe impl Sync for GuardInfo {}
+
+impl fmt::Display for GuardReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for level in &self.levels {
+            write!(f, "depth {}: ", level.depth)?;
+            match level.mutex {
+                Some(mutex) => write!(f, "lock at {mutex:p}")?,
+                None => f.write_str("lock at unknown address")?,
+            }
+            if level.poisoned {
+                f.write_str(" (poisoned)")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'root, L: Lockable + ?Sized + 'root> GuardStack<'root, L> {
+    /// Create a new GuardStack from the root lock of a recursive data structure.
+    /// If the root is poisoned, the stack is still created, and returned in a `PoisonError`.
+    pub fn new(root: &'root L) -> TryLockResult<Self> {
+        let root: *const L = root;
+        let guard = unsafe { (*root).try_lock() };
+        match guard {
+            Ok(guard) => Ok(Self::from_root_guard(guard, Some(root), false)),
+            Err(TryLockError::Poisoned(guard)) => Err(TryLockError::Poisoned(PoisonError::new(
+                Self::from_root_guard(guard.into_inner(), Some(root), true),
+            ))),
+            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Create a new GuardStack from the root lock of a recursive data structure,
+    /// with a default poison policy, which is also used to lock the root.
+    /// If the root is poisoned and `policy` is `PoisonPolicy::Fail`, the stack is returned in a `PoisonError`, like `new`.
+    pub fn with_policy(root: &'root L, policy: PoisonPolicy) -> TryLockResult<Self> {
+        let mut stack = match root.try_lock() {
+            Ok(guard) => Self::from_root_guard(guard, Some(root), false),
+            Err(TryLockError::Poisoned(guard)) => {
+                let stack = Self::from_root_guard(guard.into_inner(), Some(root), true);
+                match policy {
+                    PoisonPolicy::Fail => {
+                        return Err(TryLockError::Poisoned(PoisonError::new(stack)))
+                    }
+                    PoisonPolicy::IgnoreAndContinue => {}
+                    PoisonPolicy::ClearAndContinue => root.clear_poison(),
+                }
+                stack
+            }
+            Err(TryLockError::WouldBlock) => return Err(TryLockError::WouldBlock),
+        };
+        stack.policy = policy;
+        Ok(stack)
+    }
+
+    /// The default poison policy of this stack, set by `with_policy` (`PoisonPolicy::Fail` otherwise).
+    pub fn poison_policy(&self) -> PoisonPolicy {
+        self.policy
+    }
+
+    /// Change the default poison policy of this stack.
+    pub fn set_poison_policy(&mut self, policy: PoisonPolicy) {
+        self.policy = policy;
+    }
+
+    /// Create a new GuardStack from an already-held guard of the root of a recursive data structure.
+    /// The guard becomes the root of the stack, and is never popped.
+    pub fn from_guard(guard: L::Guard<'root>) -> Self {
+        Self::from_root_guard(guard, None, false)
+    }
+
+    /// Helper function to create a stack with only the given root guard.
+    pub(crate) fn from_root_guard(
+        guard: L::Guard<'root>,
+        lock: Option<*const L>,
+        poisoned: bool,
+    ) -> Self {
+        Self {
+            lifetime: PhantomData,
+            data: vec![Level::new(guard, lock, poisoned)],
+            poisoned,
+            blocked: false,
+            policy: PoisonPolicy::Fail,
+        }
+    }
+
+    /// Was any guard on this stack recovered from a poisoned lock?
+    /// This stays `true` even after ascending past the poisoned level.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Was the guard at the top of the stack recovered from a poisoned lock?
+    pub fn top_was_poisoned(&self) -> bool {
+        self.data.last().unwrap().poisoned
+    }
+
+    /// Was any guard currently on the stack recovered from a poisoned lock?
+    /// Unlike `is_poisoned`, this forgets about levels that have been ascended past.
+    pub fn any_poisoned(&self) -> bool {
+        self.data.iter().any(|level| level.poisoned)
+    }
+
+    /// Iterate over the depths of the levels whose guards were recovered from a poisoned lock, from the root to the top.
+    pub fn poisoned_depths(&self) -> impl Iterator<Item = usize> + use<'_, 'root, L> {
+        self.data
+            .iter()
+            .enumerate()
+            .filter(|(_, level)| level.poisoned)
+            .map(|(depth, _)| depth)
+    }
+
+    /// Clear the poison of the lock of the top of the stack, with `Lockable::clear_poison`.
+    /// This does not change the result of `top_was_poisoned`.
+    /// Returns false if the lock is unknown, because the root was adopted with `from_guard`.
+    pub fn clear_poison_top(&mut self) -> bool {
+        match self.data.last().unwrap().lock {
+            Some(lock) => {
+                unsafe { (*lock).clear_poison() };
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn raw_top_mut(&mut self) -> *mut L::Target {
+        let guard: *mut L::Guard<'root> = &mut self.data.last_mut().unwrap().guard;
+        unsafe { &mut **guard }
+    }
+
+    /// Obtain a raw pointer to the top of the stack, e.g. to compare node identities.
+    pub fn raw_top(&self) -> *const L::Target {
+        self.top()
+    }
+
+    /// Obtain a shared reference to the top of the stack.
+    pub fn top(&self) -> &L::Target {
+        &self.data.last().unwrap().guard
+    }
+
+    /// Obtain a mutable reference to the top of the stack.
+    pub fn top_mut(&mut self) -> &mut L::Target {
+        &mut self.data.last_mut().unwrap().guard
+    }
+
+    /// Is this GuardStack currently at its root?
+    pub fn is_at_root(&self) -> bool {
+        self.data.len() == 1
+    }
+
+    /// How many guards the stack can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+
+    /// Shrink the stack's storage to fit its current contents.
+    /// The stack always keeps at least the root.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+    }
+
+    /// How many levels above the root the top of the stack is.
+    pub fn depth(&self) -> usize {
+        self.data.len() - 1
+    }
+
+    /// Obtain an identity token for the top of the stack, from the address of its lock.
+    /// This is None if the top is a root adopted with `from_guard`, whose lock is unknown.
+    /// The token is only meaningful while that lock is alive.
+    pub fn top_id(&self) -> Option<TopId<L>> {
+        self.data.last().unwrap().lock.map(TopId::new)
+    }
+
+    /// Is the top of the stack the node whose lock is identified by `id`?
+    pub fn is_at(&self, id: TopId<L>) -> bool {
+        self.top_id() == Some(id)
+    }
+
+    /// Iterate over the identity tokens of the locks of every node on the stack, from the root to the top.
+    /// As with `top_id`, the token of a root adopted with `from_guard` is None.
+    /// The tokens are only meaningful while their locks are alive.
+    pub fn path_ids(&self) -> impl Iterator<Item = Option<TopId<L>>> + use<'_, 'root, L> {
+        self.data.iter().map(|level| level.lock.map(TopId::new))
+    }
+
+    /// Information about the current position, for the `*_with_ctx` methods.
+    pub(crate) fn ctx(&self) -> DescendCtx {
+        DescendCtx {
+            depth: self.depth(),
+            is_at_root: self.is_at_root(),
+            previous_step_blocked: self.blocked,
+        }
+    }
+
+    /// Report which locks are currently held by this stack, from the root to the top.
+    /// This does not lock anything, so it is safe to call when diagnosing a deadlock.
+    pub fn guards_held(&self) -> GuardReport {
+        let levels = self
+            .data
+            .iter()
+            .enumerate()
+            .map(|(depth, level)| GuardInfo {
+                depth,
+                mutex: level.lock.map(|lock| lock.cast::<()>()),
+                poisoned: level.poisoned,
+            })
+            .collect();
+        GuardReport { levels }
+    }
+
+    pub(crate) fn push_level(&mut self, guard: L::Guard<'root>, lock: *const L, poisoned: bool) {
+        self.poisoned |= poisoned;
+        self.data.push(Level::new(guard, Some(lock), poisoned));
+    }
+
+    /// Try to lock `lock` and push it onto the stack. A poisoned lock is handled according to `policy`.
+    /// On failure, the error does not borrow the stack, so the caller can still use it.
+    /// The guard of a poisoned lock that was not pushed is returned in the error.
+    pub(crate) fn try_push_lock(
+        &mut self,
+        lock: *const L,
+        policy: PoisonPolicy,
+    ) -> Result<(), TryLockError<L::Guard<'root>>> {
+        let guard = unsafe { (*lock).try_lock() };
+        self.blocked = matches!(guard, Err(TryLockError::WouldBlock));
+        match (guard, policy) {
+            (Ok(guard), _) => {
+                self.push_level(guard, lock, false);
+                Ok(())
+            }
+            (Err(TryLockError::Poisoned(guard)), PoisonPolicy::Fail) => {
+                Err(TryLockError::Poisoned(guard))
+            }
+            (Err(TryLockError::Poisoned(guard)), _) => {
+                if policy == PoisonPolicy::ClearAndContinue {
+                    unsafe { (*lock).clear_poison() };
+                }
+                self.push_level(guard.into_inner(), lock, true);
+                Ok(())
+            }
+            (Err(TryLockError::WouldBlock), _) => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Helper function to lock `lock`, push it, and return the new top.
+    pub(crate) fn handle_trylock_result(
+        &mut self,
+        lock: *const L,
+        policy: PoisonPolicy,
+    ) -> Result<&mut L::Target, TryLockError<()>> {
+        match self.try_push_lock(lock, policy) {
+            Ok(()) => Ok(self.top_mut()),
+            Err(TryLockError::Poisoned(_guard)) => {
+                Err(TryLockError::Poisoned(PoisonError::new(())))
+            }
+            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Inject a new reference to the top of the stack. The reference still must live
+    /// as long as the root of the stack.
+    /// A poisoned lock is handled according to `policy`.
+    pub fn inject_top_policy(
+        &mut self,
+        new_top: &'root L,
+        policy: PoisonPolicy,
+    ) -> Result<&mut L::Target, TryLockError<()>> {
+        self.handle_trylock_result(new_top, policy)
+    }
+
+    /// Descend into the recursive data structure, returning a mutable reference to the new top element.
+    /// Rust's borrow checker enforces that the closure cannot inject any lifetime (other than `'static`),
+    /// because the closure must work for any lifetime `'node`.
+    /// A poisoned lock is handled according to `policy`.
+    pub fn descend_with_policy(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut L::Target) -> Option<&'node L>,
+        policy: PoisonPolicy,
+    ) -> Option<Result<&mut L::Target, TryLockError<()>>> {
+        let old_top: *mut L::Target = self.raw_top_mut();
+        let new_top: &L = unsafe { f(&mut *old_top)? };
+        let new_top: *const L = new_top;
+        Some(self.handle_trylock_result(new_top, policy))
+    }
+
+    /// Ascend back up from the recursive data structure, returning a mutable reference to the new top element, if it changed.
+    /// If we are not currently at the root, ascend and return a reference to the new top.
+    /// If we are already at the root, returns None (the top is the root and does not change).
+    pub fn ascend(&mut self) -> Option<&mut L::Target> {
+        match self.data.len() {
+            0 => unreachable!("root pointer must always exist"),
+            1 => None,
+            _ => {
+                self.data.pop();
+                Some(self.top_mut())
+            }
+        }
+    }
+
+    /// Ascend back up from the recursive data structure while the given closure returns `true`, returning a mutable reference to the new top element.
+    /// If we are not currently at the root, and the predicate returns `true`, ascend and continue.
+    /// If we are already at the root, or if the predicate returned false, returns a reference to the top element.
+    ///
+    /// If the predicate panics, the stack is left at the level the predicate panicked on,
+    /// and is still usable if the panic is caught; the levels already ascended stay ascended (and their guards released).
+    pub fn ascend_while<P>(&mut self, mut predicate: P) -> &mut L::Target
+    where
+        P: FnMut(&mut L::Target) -> bool,
+    {
+        while !self.is_at_root() && predicate(self.top_mut()) {
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        self.top_mut()
+    }
+
+    /// Ascend like `ascend_while`, but the predicate can fail.
+    /// If it returns an error, ascending stops and the stack is left at the level the predicate failed on.
+    pub fn ascend_while_result<E>(
+        &mut self,
+        mut predicate: impl FnMut(&mut L::Target) -> Result<bool, E>,
+    ) -> Result<&mut L::Target, E> {
+        while !self.is_at_root() && predicate(self.top_mut())? {
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        Ok(self.top_mut())
+    }
+
+    /// Run the closure on the current top, then ascend, returning the closure's result.
+    /// If we are already at the root, returns None without calling the closure.
+    pub fn ascend_map<R>(&mut self, f: impl FnOnce(&mut L::Target) -> R) -> Option<R> {
+        if self.is_at_root() {
+            return None;
+        }
+        let result = f(self.top_mut());
+        let Some(_) = self.ascend() else {
+            unreachable!();
+        };
+        Some(result)
+    }
+
+    /// Ascend while the closure returns `Some`, collecting its results in pop order, i.e. starting with the current top.
+    /// The closure is called on the current top before each ascend, and never on the root.
+    pub fn ascend_while_map<R>(
+        &mut self,
+        mut f: impl FnMut(&mut L::Target) -> Option<R>,
+    ) -> Vec<R> {
+        let mut results = Vec::new();
+        while !self.is_at_root() {
+            let Some(result) = f(self.top_mut()) else {
+                break;
+            };
+            results.push(result);
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        results
+    }
+
+    /// Ascend like `ascend_while`, additionally passing the predicate the depth of the node it is called with.
+    /// For example, `ascend_while_indexed(|_, depth| depth > 2)` ascends until the top is at depth 2.
+    pub fn ascend_while_indexed<P>(&mut self, mut predicate: P) -> &mut L::Target
+    where
+        P: FnMut(&mut L::Target, usize) -> bool,
+    {
+        let mut depth = self.depth();
+        self.ascend_while(|top| {
+            let ascend = predicate(top, depth);
+            depth -= 1;
+            ascend
+        })
+    }
+
+    /// Ascend `n` levels, returning a mutable reference to the new top.
+    /// The guards are dropped one at a time, from the top down.
+    /// If the top is fewer than `n` levels above the root, returns None and does not ascend at all.
+    pub fn ascend_n(&mut self, n: usize) -> Option<&mut L::Target> {
+        let target = self.depth().checked_sub(n)?;
+        self.to_depth(target).ok()
+    }
+
+    /// Ascend until the top is `target` levels above the root, returning a mutable reference to the new top.
+    /// The guards are dropped one at a time, from the top down.
+    /// Fails without ascending if the top is already less than `target` levels above the root.
+    pub fn to_depth(&mut self, target: usize) -> Result<&mut L::Target, ToDepthError> {
+        let depth = self.depth();
+        if target > depth {
+            return Err(ToDepthError { target, depth });
+        }
+        for _ in target..depth {
+            // Pop one at a time, so that child guards are dropped before their parents.
+            self.data.pop();
+        }
+        Ok(self.top_mut())
+    }
+
+    /// Pop all guards off the stack, and return the guard of the root.
+    /// Unlike `MutexGuardStack::into_top_leaking`, this does not leak anything.
+    pub fn into_root(mut self) -> L::Guard<'root> {
+        self.to_root();
+        self.data.pop().unwrap().into_guard()
+    }
+
+    /// Pop all guards off the stack and go back to the root, returning how many were popped
+    /// (the depth of the stack beforehand).
+    pub fn ascend_to_root(&mut self) -> usize {
+        let depth = self.depth();
+        self.to_root();
+        depth
+    }
+
+    /// Pop all guards off the stack and go back to the root.
+    pub fn to_root(&mut self) -> &mut L::Target {
+        for _ in 1..self.data.len() {
+            // We need to drop the guards in the reverse order.
+            // Vec::truncate does not specify drop order, but it's probably wrong anyway.
+            self.data.pop();
+        }
+        self.top_mut()
+    }
+}
+
+#[cfg(feature = "parking_lot")]
+impl<'root, T: ?Sized> ParkingLotMutexGuardStack<'root, T> {
+    /// Descend into the recursive data structure, waiting up to `timeout` for the selected `Mutex`
+    /// with `parking_lot::Mutex::try_lock_for`, returning a mutable reference to the new top element.
+    /// Fails with `WouldBlock` if the `Mutex` is still locked once `timeout` has passed.
+    /// Returns None if the closure did not select a `Mutex`.
+    pub fn descend_with_timeout(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node parking_lot::Mutex<T>>,
+        timeout: std::time::Duration,
+    ) -> Option<Result<&mut T, crate::mutex::WouldBlock>> {
+        let old_top: *mut T = self.raw_top_mut();
+        let new_top: &parking_lot::Mutex<T> = unsafe { f(&mut *old_top)? };
+        let new_top: *const parking_lot::Mutex<T> = new_top;
+        let guard = unsafe { (*new_top).try_lock_for(timeout) };
+        self.blocked = guard.is_none();
+        let Some(guard) = guard else {
+            return Some(Err(crate::mutex::WouldBlock));
+        };
+        self.push_level(guard, new_top, false);
+        Some(Ok(self.top_mut()))
+    }
+}
+
+impl<'root, L: Lockable + ?Sized + 'root> fmt::Debug for GuardStack<'root, L> {
+    /// Prints the depth and the lock of each level, without locking anything or printing the nodes.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GuardStack")
+            .field("depth", &self.depth())
+            .field("levels", &self.guards_held().levels)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'root, L: Lockable + ?Sized + 'root> Drop for GuardStack<'root, L> {
+    fn drop(&mut self) {
+        for _ in 0..self.data.len() {
+            // We need to drop the guards in the reverse order.
+            self.data.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell, UnsafeCell},
+        ops::Deref,
+        rc::Rc,
+    };
+
+    use super::*;
+
+    /// A lock that records its id in a shared log whenever its guard is dropped.
+    struct LoggedLock {
+        id: usize,
+        locked: Cell<bool>,
+        log: Rc<RefCell<Vec<usize>>>,
+        node: UnsafeCell<Node>,
+    }
+
+    struct Node {
+        id: usize,
+        child: Option<Box<LoggedLock>>,
+    }
+
+    struct LoggedGuard<'a> {
+        lock: &'a LoggedLock,
+    }
+
+    impl Lockable for LoggedLock {
+        type Target = Node;
+        type Guard<'a> = LoggedGuard<'a>;
+
+        fn try_lock(&self) -> TryLockResult<LoggedGuard<'_>> {
+            if self.locked.replace(true) {
+                return Err(TryLockError::WouldBlock);
+            }
+            Ok(LoggedGuard { lock: self })
+        }
+    }
+
+    impl Deref for LoggedGuard<'_> {
+        type Target = Node;
+
+        fn deref(&self) -> &Node {
+            unsafe { &*self.lock.node.get() }
+        }
+    }
+
+    impl DerefMut for LoggedGuard<'_> {
+        fn deref_mut(&mut self) -> &mut Node {
+            unsafe { &mut *self.lock.node.get() }
+        }
+    }
+
+    impl Drop for LoggedGuard<'_> {
+        fn drop(&mut self) {
+            self.lock.locked.set(false);
+            self.lock.log.borrow_mut().push(self.lock.id);
+        }
+    }
+
+    /// A chain of `len` locks with the ids `0..len`.
+    fn chain(len: usize, log: &Rc<RefCell<Vec<usize>>>) -> LoggedLock {
+        (0..len)
+            .rev()
+            .fold(None, |child, id| {
+                Some(LoggedLock {
+                    id,
+                    locked: Cell::new(false),
+                    log: log.clone(),
+                    node: UnsafeCell::new(Node {
+                        id,
+                        child: child.map(Box::new),
+                    }),
+                })
+            })
+            .unwrap()
+    }
+
+    fn descend_all(stack: &mut GuardStack<'_, LoggedLock>) {
+        while let Some(top) =
+            stack.descend_with_policy(|node| node.child.as_deref(), PoisonPolicy::Fail)
+        {
+            top.unwrap();
+        }
+    }
+
+    #[test]
+    fn into_root_unlocks_from_the_top_down() {
+        let log = Rc::default();
+        let root = chain(4, &log);
+        let mut stack = GuardStack::new(&root).unwrap();
+        descend_all(&mut stack);
+        assert_eq!(stack.depth(), 3);
+        let guard = stack.into_root();
+        assert_eq!(guard.id, 0);
+        assert_eq!(*log.borrow(), [3, 2, 1]);
+        let child = guard.child.as_deref().unwrap();
+        drop(child.try_lock().unwrap());
+        assert!(root.try_lock().is_err());
+        drop(guard);
+        assert_eq!(*log.borrow(), [3, 2, 1, 1, 0]);
+    }
+
+    #[cfg(feature = "parking_lot")]
+    #[test]
+    fn descend_with_timeout() {
+        use std::{sync::Barrier, time::Duration};
+
+        struct Node {
+            next: Option<Arc<parking_lot::Mutex<Node>>>,
+        }
+        let child = Arc::new(parking_lot::Mutex::new(Node { next: None }));
+        let root = parking_lot::Mutex::new(Node {
+            next: Some(Arc::clone(&child)),
+        });
+        let (locked, unlock) = (Barrier::new(2), Barrier::new(2));
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _guard = child.lock();
+                locked.wait();
+                unlock.wait();
+            });
+            locked.wait();
+            let mut stack = ParkingLotMutexGuardStack::new(&root).unwrap();
+            let top =
+                stack.descend_with_timeout(|node| node.next.as_deref(), Duration::from_millis(10));
+            assert!(matches!(top, Some(Err(crate::mutex::WouldBlock))));
+            assert!(stack.is_at_root());
+            assert!(stack.ctx().previous_step_blocked);
+            unlock.wait();
+            let top =
+                stack.descend_with_timeout(|node| node.next.as_deref(), Duration::from_secs(10));
+            assert!(top.unwrap().unwrap().next.is_none());
+            assert_eq!(stack.depth(), 1);
+        });
+    }
+
+    #[test]
+    fn ascend_while_result_stops_at_the_error() {
+        let log = Rc::default();
+        let root = chain(5, &log);
+        let mut stack = GuardStack::new(&root).unwrap();
+        descend_all(&mut stack);
+        let result = stack.ascend_while_result(|node| match node.id {
+            2 => Err(node.id),
+            _ => Ok(true),
+        });
+        assert_eq!(result.map(|top| top.id), Err(2));
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(*log.borrow(), [4, 3]);
+    }
+
+    #[test]
+    fn ascend_while_result_reaches_the_root() {
+        let log = Rc::default();
+        let root = chain(5, &log);
+        let mut stack = GuardStack::new(&root).unwrap();
+        descend_all(&mut stack);
+        let top = stack.ascend_while_result(|_| Ok::<_, ()>(true));
+        assert_eq!(top.unwrap().id, 0);
+        assert!(stack.is_at_root());
+        assert_eq!(*log.borrow(), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn ascend_while_result_panics() {
+        let log = Rc::default();
+        let root = chain(5, &log);
+        let mut stack = GuardStack::new(&root).unwrap();
+        descend_all(&mut stack);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = stack.ascend_while_result(|node| match node.id {
+                2 => panic!("predicate panicked"),
+                _ => Ok::<_, ()>(true),
+            });
+        }));
+        assert!(result.is_err());
+        // The stack is left where the predicate panicked, still holding the locks from the root to there.
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(stack.top().id, 2);
+        assert_eq!(*log.borrow(), [4, 3]);
+        stack.to_root();
+        assert_eq!(*log.borrow(), [4, 3, 2, 1]);
+    }
+}
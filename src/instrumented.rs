@@ -0,0 +1,1042 @@
+use std::{
+    cell::{BorrowMutError, RefCell, RefMut},
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::{Mutex, MutexGuard, TryLockError},
+};
+
+use crate::{
+    guard::{GuardStack, Lockable, PoisonPolicy},
+    mutex, refcell, simple, with_data, DescendResult, ToDepthError,
+};
+
+/// The outcome of a lock-based cursor trying to acquire a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockStatus {
+    /// The node was acquired.
+    Acquired,
+    /// The node's lock was poisoned. Whether the node was acquired anyway depends on the `PoisonPolicy`.
+    Poisoned,
+    /// The node was already locked or borrowed.
+    WouldBlock,
+}
+
+/// Callbacks invoked by `Instrumented` around the operations of the wrapped cursor.
+/// The callbacks never get access to any node, only to the depth of the cursor.
+pub trait Observer {
+    /// Called after the cursor descends or injects, with the new depth.
+    fn on_descend(&mut self, _depth: usize) {}
+    /// Called after the cursor ascends, with the new depth.
+    fn on_ascend(&mut self, _depth: usize) {}
+    /// Called when `move_with` (or `move_with_async`) fails.
+    fn on_move_error(&mut self, _err: &dyn Error) {}
+    /// Called by the lock-based cursors after trying to acquire a node that would be at `depth`.
+    fn on_lock(&mut self, _depth: usize, _status: LockStatus) {}
+}
+
+/// A wrapper around any of the cursor types that invokes an `Observer` around every navigation step.
+///
+/// This forwards the accessors and the navigation methods of the wrapped cursor that the observer can report on:
+/// descending and injecting, ascending (one level, several levels, or while a predicate holds), `move_with` and `drive`.
+/// The more specialised methods (e.g. the `*_ctx` and `*_blocking` variants)
+/// are deliberately not forwarded, and can still be reached through `inner_mut`, unreported.
+/// A `GuardStack` over any `Lockable` can be instrumented; the methods specific to `Mutex` are only forwarded for `MutexGuardStack`.
+pub struct Instrumented<C, O> {
+    cursor: C,
+    observer: O,
+}
+
+impl<C, O> Instrumented<C, O> {
+    /// Wrap a cursor, reporting its navigation steps to `observer`.
+    pub fn new(cursor: C, observer: O) -> Self {
+        Self { cursor, observer }
+    }
+
+    /// Obtain a shared reference to the wrapped cursor.
+    pub fn inner(&self) -> &C {
+        &self.cursor
+    }
+
+    /// Obtain a mutable reference to the wrapped cursor.
+    /// Navigation done through this reference is not reported to the observer.
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.cursor
+    }
+
+    /// Obtain a shared reference to the observer.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Obtain a mutable reference to the observer.
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Unwrap this into the cursor and the observer.
+    pub fn into_inner(self) -> (C, O) {
+        (self.cursor, self.observer)
+    }
+}
+
+impl<C, O> Instrumented<C, O> {
+    /// Report the change in depth caused by a step that started at `old_depth`.
+    fn report_step(&mut self, old_depth: usize, new_depth: usize)
+    where
+        O: Observer,
+    {
+        if new_depth > old_depth {
+            self.observer.on_descend(new_depth);
+        } else if new_depth < old_depth {
+            self.observer.on_ascend(new_depth);
+        }
+    }
+}
+
+impl<'root, T: ?Sized, O: Observer> Instrumented<simple::MutRefStack<'root, T>, O> {
+    /// See `MutRefStack::top`.
+    pub fn top(&self) -> &T {
+        self.cursor.top()
+    }
+
+    /// See `MutRefStack::top_mut`.
+    pub fn top_mut(&mut self) -> &mut T {
+        self.cursor.top_mut()
+    }
+
+    /// See `MutRefStack::is_at_root`.
+    pub fn is_at_root(&self) -> bool {
+        self.cursor.is_at_root()
+    }
+
+    /// See `MutRefStack::depth`.
+    pub fn depth(&self) -> usize {
+        self.cursor.depth()
+    }
+
+    /// See `MutRefStack::inject_top`.
+    pub fn inject_top(&mut self, new_top: &'root mut T) -> &mut T {
+        self.cursor.inject_top(new_top);
+        self.observer.on_descend(self.cursor.depth());
+        self.cursor.top_mut()
+    }
+
+    /// See `MutRefStack::inject_with`.
+    pub fn inject_with(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&'root mut T>,
+    ) -> Option<&mut T> {
+        self.cursor.inject_with(f)?;
+        self.observer.on_descend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStack::descend_with`.
+    pub fn descend_with(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node mut T>,
+    ) -> Option<&mut T> {
+        self.cursor.descend_with(f)?;
+        self.observer.on_descend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStack::descend_with_cycle_check`.
+    pub fn descend_with_cycle_check(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node mut T>,
+        policy: simple::CyclePolicy,
+    ) -> Result<Option<&mut T>, simple::CycleDetected> {
+        if self.cursor.descend_with_cycle_check(f, policy)?.is_none() {
+            return Ok(None);
+        }
+        self.observer.on_descend(self.cursor.depth());
+        Ok(Some(self.cursor.top_mut()))
+    }
+
+    /// See `MutRefStack::ascend`.
+    pub fn ascend(&mut self) -> Option<&mut T> {
+        self.cursor.ascend()?;
+        self.observer.on_ascend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStack::ascend_while`.
+    pub fn ascend_while<P>(&mut self, mut predicate: P) -> &mut T
+    where
+        P: FnMut(&mut T) -> bool,
+    {
+        let observer = &mut self.observer;
+        self.cursor.ascend_while_indexed(|top, depth| {
+            let ascend = predicate(top);
+            if ascend {
+                observer.on_ascend(depth - 1);
+            }
+            ascend
+        })
+    }
+
+    /// See `MutRefStack::move_with`.
+    pub fn move_with<F>(&mut self, f: F) -> Result<&mut T, simple::MoveError>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> simple::MoveDecision<'root, 'a, T>,
+    {
+        let old_depth = self.cursor.depth();
+        if let Err(err) = self.cursor.move_with(f) {
+            self.observer.on_move_error(&err);
+            return Err(err);
+        }
+        self.report_step(old_depth, self.cursor.depth());
+        Ok(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStack::try_move_with`.
+    /// Only failed moves are reported to `Observer::on_move_error`, not errors of the closure.
+    pub fn try_move_with<E, F>(&mut self, f: F) -> Result<&mut T, simple::TryMoveError<E>>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> Result<simple::MoveDecision<'root, 'a, T>, E>,
+    {
+        let old_depth = self.cursor.depth();
+        match self.cursor.try_move_with(f) {
+            Ok(_) => {}
+            Err(simple::TryMoveError::Move(err)) => {
+                self.observer.on_move_error(&err);
+                return Err(simple::TryMoveError::Move(err));
+            }
+            Err(err) => return Err(err),
+        }
+        self.report_step(old_depth, self.cursor.depth());
+        Ok(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStack::move_with_async`.
+    pub async fn move_with_async<F>(&mut self, f: F) -> Result<&mut T, simple::MoveError>
+    where
+        F: for<'a> FnOnce(
+            &'a mut T,
+        )
+            -> Pin<Box<dyn Future<Output = simple::MoveDecision<'root, 'a, T>> + 'a>>,
+    {
+        let old_depth = self.cursor.depth();
+        if let Err(err) = self.cursor.move_with_async(f).await {
+            self.observer.on_move_error(&err);
+            return Err(err);
+        }
+        self.report_step(old_depth, self.cursor.depth());
+        Ok(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStack::to_root`.
+    pub fn to_root(&mut self) -> &mut T {
+        let old_depth = self.cursor.depth();
+        self.cursor.to_root();
+        self.report_step(old_depth, 0);
+        self.cursor.top_mut()
+    }
+
+    /// See `MutRefStack::into_top`.
+    pub fn into_top(self) -> &'root mut T {
+        self.cursor.into_top()
+    }
+}
+
+impl<'root, T: ?Sized, U, O: Observer>
+    Instrumented<with_data::MutRefStackWithData<'root, T, U>, O>
+{
+    /// See `MutRefStackWithData::top`.
+    pub fn top(&self) -> (&T, &U) {
+        self.cursor.top()
+    }
+
+    /// See `MutRefStackWithData::top_mut`.
+    pub fn top_mut(&mut self) -> (&mut T, &mut U) {
+        self.cursor.top_mut()
+    }
+
+    /// See `MutRefStackWithData::is_at_root`.
+    pub fn is_at_root(&self) -> bool {
+        self.cursor.is_at_root()
+    }
+
+    /// See `MutRefStackWithData::depth`.
+    pub fn depth(&self) -> usize {
+        self.cursor.depth()
+    }
+
+    /// See `MutRefStackWithData::descend_with`.
+    pub fn descend_with(
+        &mut self,
+        f: impl for<'node, 'addl> FnOnce(&'node mut T, &'addl mut U) -> Option<(&'node mut T, U)>,
+    ) -> Option<(&mut T, &mut U)> {
+        self.cursor.descend_with(f)?;
+        self.observer.on_descend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStackWithData::inject_with`.
+    pub fn inject_with(
+        &mut self,
+        f: impl for<'node, 'addl> FnOnce(&'node mut T, &'addl mut U) -> Option<(&'root mut T, U)>,
+    ) -> Option<(&mut T, &mut U)> {
+        self.cursor.inject_with(f)?;
+        self.observer.on_descend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStackWithData::ascend`.
+    pub fn ascend(&mut self) -> Option<((&mut T, &mut U), U)> {
+        let (_top, addl) = self.cursor.ascend()?;
+        self.observer.on_ascend(self.cursor.depth());
+        Some((self.cursor.top_mut(), addl))
+    }
+
+    /// See `MutRefStackWithData::ascend_while`.
+    pub fn ascend_while<P>(
+        &mut self,
+        mut predicate: P,
+    ) -> ((&mut T, &mut U), with_data::AscendedData<U>)
+    where
+        P: FnMut(&mut T, &mut U) -> bool,
+    {
+        let observer = &mut self.observer;
+        self.cursor.ascend_while_indexed(|top, addl, depth| {
+            let ascend = predicate(top, addl);
+            if ascend {
+                observer.on_ascend(depth - 1);
+            }
+            ascend
+        })
+    }
+
+    /// See `MutRefStackWithData::ascend_fold`.
+    pub fn ascend_fold(&mut self, f: impl FnOnce(U, &mut U)) -> Option<(&mut T, &mut U)> {
+        self.cursor.ascend_fold(f)?;
+        self.observer.on_ascend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `MutRefStackWithData::ascend_while_fold`.
+    pub fn ascend_while_fold<P>(
+        &mut self,
+        mut predicate: P,
+        combine: impl FnMut(U, &mut U),
+    ) -> (&mut T, &mut U)
+    where
+        P: FnMut(&mut T, &mut U) -> bool,
+    {
+        let mut depth = self.cursor.depth();
+        let observer = &mut self.observer;
+        self.cursor.ascend_while_fold(
+            |top, addl| {
+                let ascend = predicate(top, addl);
+                if ascend {
+                    depth -= 1;
+                    observer.on_ascend(depth);
+                }
+                ascend
+            },
+            combine,
+        )
+    }
+
+    /// See `MutRefStackWithData::move_with`.
+    pub fn move_with(
+        &mut self,
+        f: impl for<'node, 'addl> FnOnce(
+            &'node mut T,
+            &'addl mut U,
+        ) -> with_data::MoveDecision<'root, 'node, T, U>,
+    ) -> Result<((&mut T, &mut U), Option<U>), with_data::MoveError> {
+        let old_depth = self.cursor.depth();
+        let old_addl = match self.cursor.move_with(f) {
+            Ok((_top, old_addl)) => old_addl,
+            Err(err) => {
+                self.observer.on_move_error(&err);
+                return Err(err);
+            }
+        };
+        self.report_step(old_depth, self.cursor.depth());
+        Ok((self.cursor.top_mut(), old_addl))
+    }
+
+    /// See `MutRefStackWithData::into_top`.
+    pub fn into_top(self) -> &'root mut T {
+        self.cursor.into_top()
+    }
+}
+
+impl<'root, T: ?Sized, O: Observer> Instrumented<refcell::RefCellRefMutStack<'root, T>, O> {
+    /// Report the outcome of trying to borrow a node, and reborrow the new top.
+    fn finish_borrow<E>(&mut self, result: Result<(), E>) -> Result<&mut T, E> {
+        let depth = self.cursor.depth();
+        match result {
+            Ok(()) => {
+                self.observer.on_lock(depth, LockStatus::Acquired);
+                self.observer.on_descend(depth);
+                Ok(self.cursor.top_mut())
+            }
+            Err(err) => {
+                self.observer.on_lock(depth + 1, LockStatus::WouldBlock);
+                Err(err)
+            }
+        }
+    }
+
+    /// Report the outcome of a `move_with` step that started at `old_depth`, and reborrow the new top.
+    fn finish_move(
+        &mut self,
+        old_depth: usize,
+        result: Result<(), refcell::MoveError>,
+    ) -> Result<&mut T, refcell::MoveError> {
+        let depth = self.cursor.depth();
+        match result {
+            Ok(()) => {
+                if depth > old_depth {
+                    self.observer.on_lock(depth, LockStatus::Acquired);
+                }
+                self.report_step(old_depth, depth);
+                Ok(self.cursor.top_mut())
+            }
+            Err(err) => {
+                if let refcell::MoveError::BorrowMutError(_) = err {
+                    self.observer.on_lock(depth + 1, LockStatus::WouldBlock);
+                }
+                self.observer.on_move_error(&err);
+                Err(err)
+            }
+        }
+    }
+
+    /// See `RefCellRefMutStack::top`.
+    pub fn top(&self) -> &T {
+        self.cursor.top()
+    }
+
+    /// See `RefCellRefMutStack::top_mut`.
+    pub fn top_mut(&mut self) -> &mut T {
+        self.cursor.top_mut()
+    }
+
+    /// See `RefCellRefMutStack::is_at_root`.
+    pub fn is_at_root(&self) -> bool {
+        self.cursor.is_at_root()
+    }
+
+    /// See `RefCellRefMutStack::depth`.
+    pub fn depth(&self) -> usize {
+        self.cursor.depth()
+    }
+
+    /// See `RefCellRefMutStack::inject_top`.
+    pub fn inject_top(&mut self, new_top: &'root RefCell<T>) -> Result<&mut T, BorrowMutError> {
+        let result = self.cursor.inject_top(new_top).map(|_| ());
+        self.finish_borrow(result)
+    }
+
+    /// See `RefCellRefMutStack::inject_with`.
+    pub fn inject_with(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&'root RefCell<T>>,
+    ) -> Option<Result<&mut T, BorrowMutError>> {
+        let result = self.cursor.inject_with(f)?.map(|_| ());
+        Some(self.finish_borrow(result))
+    }
+
+    /// See `RefCellRefMutStack::descend_with`.
+    pub fn descend_with(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node RefCell<T>>,
+    ) -> Option<Result<&mut T, refcell::DescendError>> {
+        let result = self.cursor.descend_with(f)?.map(|_| ());
+        Some(self.finish_borrow(result))
+    }
+
+    /// See `RefCellRefMutStack::try_descend`.
+    pub fn try_descend(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node RefCell<T>>,
+    ) -> DescendResult<'_, T, refcell::DescendError> {
+        DescendResult::from_option(self.descend_with(f))
+    }
+
+    /// See `RefCellRefMutStack::descend_filter_map_with`.
+    pub fn descend_filter_map_with(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&mut T>,
+    ) -> Option<&mut T> {
+        self.cursor.descend_filter_map_with(f)?;
+        self.observer.on_descend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `RefCellRefMutStack::ascend`.
+    pub fn ascend(&mut self) -> Option<&mut T> {
+        self.cursor.ascend()?;
+        self.observer.on_ascend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `RefCellRefMutStack::ascend_while`.
+    pub fn ascend_while<P>(&mut self, mut predicate: P) -> &mut T
+    where
+        P: FnMut(&mut T) -> bool,
+    {
+        let observer = &mut self.observer;
+        self.cursor.ascend_while_indexed(|top, depth| {
+            let ascend = predicate(top);
+            if ascend {
+                observer.on_ascend(depth - 1);
+            }
+            ascend
+        })
+    }
+
+    /// See `RefCellRefMutStack::move_with`.
+    pub fn move_with<F>(&mut self, f: F) -> Result<&mut T, refcell::MoveError>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> refcell::MoveDecision<'root, 'a, T>,
+    {
+        let old_depth = self.cursor.depth();
+        let result = self.cursor.move_with(f).map(|_| ());
+        self.finish_move(old_depth, result)
+    }
+
+    /// See `RefCellRefMutStack::drive`.
+    /// Every step is reported like a separate `move_with`, except for the final `Finish`.
+    pub fn drive<R, F>(&mut self, mut f: F) -> Result<Option<R>, refcell::MoveError>
+    where
+        F: for<'a> FnMut(&'a mut T) -> refcell::MoveDecision<'root, 'a, T, R>,
+    {
+        loop {
+            let old_depth = self.cursor.depth();
+            let mut finished = None;
+            let result = self
+                .cursor
+                .move_with(|top| match f(top) {
+                    refcell::MoveDecision::Finish(result) => {
+                        finished = Some(result);
+                        refcell::MoveDecision::Finish(())
+                    }
+                    refcell::MoveDecision::Ascend => refcell::MoveDecision::Ascend,
+                    refcell::MoveDecision::Stay => refcell::MoveDecision::Stay,
+                    refcell::MoveDecision::Descend(new_top) => {
+                        refcell::MoveDecision::Descend(new_top)
+                    }
+                    refcell::MoveDecision::Inject(new_top) => {
+                        refcell::MoveDecision::Inject(new_top)
+                    }
+                })
+                .map(|_| ());
+            if let Some(result) = finished {
+                return Ok(Some(result));
+            }
+            if let Err(refcell::MoveError::AscendAtRoot) = result {
+                return Ok(None);
+            }
+            self.finish_move(old_depth, result)?;
+        }
+    }
+
+    /// See `RefCellRefMutStack::move_with_async`.
+    pub async fn move_with_async<F>(&mut self, f: F) -> Result<&mut T, refcell::MoveError>
+    where
+        F: for<'a> FnOnce(
+            &'a mut T,
+        ) -> Pin<
+            Box<dyn Future<Output = refcell::MoveDecision<'root, 'a, T>> + 'a>,
+        >,
+    {
+        let old_depth = self.cursor.depth();
+        let result = self.cursor.move_with_async(f).await.map(|_| ());
+        self.finish_move(old_depth, result)
+    }
+
+    /// See `RefCellRefMutStack::ascend_n`.
+    pub fn ascend_n(&mut self, n: usize) -> Option<&mut T> {
+        let old_depth = self.cursor.depth();
+        self.cursor.ascend_n(n)?;
+        self.report_step(old_depth, self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `RefCellRefMutStack::truncate_to_depth`.
+    pub fn truncate_to_depth(&mut self, target: usize) -> Result<&mut T, ToDepthError> {
+        let old_depth = self.cursor.depth();
+        self.cursor.truncate_to_depth(target)?;
+        self.report_step(old_depth, target);
+        Ok(self.cursor.top_mut())
+    }
+
+    /// See `RefCellRefMutStack::to_root`.
+    pub fn to_root(&mut self) -> &mut T {
+        let old_depth = self.cursor.depth();
+        self.cursor.to_root();
+        self.report_step(old_depth, 0);
+        self.cursor.top_mut()
+    }
+
+    /// See `RefCellRefMutStack::into_top_leaking`.
+    pub fn into_top_leaking(self) -> RefMut<'root, T> {
+        self.cursor.into_top_leaking()
+    }
+}
+
+impl<'root, L: Lockable + ?Sized + 'root, O: Observer> Instrumented<GuardStack<'root, L>, O> {
+    /// The lock status of the top of the stack, which was just locked.
+    fn top_status(&self) -> LockStatus {
+        if self.cursor.top_was_poisoned() {
+            LockStatus::Poisoned
+        } else {
+            LockStatus::Acquired
+        }
+    }
+
+    /// Report the outcome of trying to lock a node, and reborrow the new top.
+    fn finish_lock(
+        &mut self,
+        result: Result<(), TryLockError<()>>,
+    ) -> Result<&mut L::Target, TryLockError<()>> {
+        let depth = self.cursor.depth();
+        match result {
+            Ok(()) => {
+                let status = self.top_status();
+                self.observer.on_lock(depth, status);
+                self.observer.on_descend(depth);
+                Ok(self.cursor.top_mut())
+            }
+            Err(err) => {
+                let status = match err {
+                    TryLockError::Poisoned(_) => LockStatus::Poisoned,
+                    TryLockError::WouldBlock => LockStatus::WouldBlock,
+                };
+                self.observer.on_lock(depth + 1, status);
+                Err(err)
+            }
+        }
+    }
+
+    /// See `GuardStack::top`.
+    pub fn top(&self) -> &L::Target {
+        self.cursor.top()
+    }
+
+    /// See `GuardStack::top_mut`.
+    pub fn top_mut(&mut self) -> &mut L::Target {
+        self.cursor.top_mut()
+    }
+
+    /// See `GuardStack::is_at_root`.
+    pub fn is_at_root(&self) -> bool {
+        self.cursor.is_at_root()
+    }
+
+    /// See `GuardStack::depth`.
+    pub fn depth(&self) -> usize {
+        self.cursor.depth()
+    }
+
+    /// See `GuardStack::inject_top_policy`.
+    pub fn inject_top_policy(
+        &mut self,
+        new_top: &'root L,
+        policy: PoisonPolicy,
+    ) -> Result<&mut L::Target, TryLockError<()>> {
+        let result = self.cursor.inject_top_policy(new_top, policy).map(|_| ());
+        self.finish_lock(result)
+    }
+
+    /// See `GuardStack::descend_with_policy`.
+    pub fn descend_with_policy(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut L::Target) -> Option<&'node L>,
+        policy: PoisonPolicy,
+    ) -> Option<Result<&mut L::Target, TryLockError<()>>> {
+        let result = self.cursor.descend_with_policy(f, policy)?.map(|_| ());
+        Some(self.finish_lock(result))
+    }
+
+    /// See `GuardStack::ascend`.
+    pub fn ascend(&mut self) -> Option<&mut L::Target> {
+        self.cursor.ascend()?;
+        self.observer.on_ascend(self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `GuardStack::ascend_while`.
+    pub fn ascend_while<P>(&mut self, mut predicate: P) -> &mut L::Target
+    where
+        P: FnMut(&mut L::Target) -> bool,
+    {
+        let observer = &mut self.observer;
+        self.cursor.ascend_while_indexed(|top, depth| {
+            let ascend = predicate(top);
+            if ascend {
+                observer.on_ascend(depth - 1);
+            }
+            ascend
+        })
+    }
+
+    /// See `GuardStack::ascend_n`.
+    pub fn ascend_n(&mut self, n: usize) -> Option<&mut L::Target> {
+        let old_depth = self.cursor.depth();
+        self.cursor.ascend_n(n)?;
+        self.report_step(old_depth, self.cursor.depth());
+        Some(self.cursor.top_mut())
+    }
+
+    /// See `GuardStack::to_depth`.
+    pub fn to_depth(&mut self, target: usize) -> Result<&mut L::Target, ToDepthError> {
+        let old_depth = self.cursor.depth();
+        self.cursor.to_depth(target)?;
+        self.report_step(old_depth, target);
+        Ok(self.cursor.top_mut())
+    }
+
+    /// See `GuardStack::to_root`.
+    pub fn to_root(&mut self) -> &mut L::Target {
+        let old_depth = self.cursor.depth();
+        self.cursor.to_root();
+        self.report_step(old_depth, 0);
+        self.cursor.top_mut()
+    }
+
+    /// See `GuardStack::into_root`.
+    pub fn into_root(self) -> L::Guard<'root> {
+        self.cursor.into_root()
+    }
+}
+
+impl<'root, T: ?Sized, O: Observer> Instrumented<mutex::MutexGuardStack<'root, T>, O> {
+    /// Report a failed `move_with` step, and tie the error to the stack.
+    fn fail_move(&mut self, err: mutex::RawMoveError<'root, T>) -> mutex::MoveError<'_, 'root, T> {
+        let depth = self.cursor.depth();
+        match err {
+            mutex::RawMoveError::AscendAtRoot => {}
+            mutex::RawMoveError::Poisoned(..) => {
+                self.observer.on_lock(depth + 1, LockStatus::Poisoned)
+            }
+            mutex::RawMoveError::WouldBlock => {
+                self.observer.on_lock(depth + 1, LockStatus::WouldBlock)
+            }
+        }
+        let err = err.attach(&mut self.cursor);
+        self.observer.on_move_error(&err);
+        err
+    }
+
+    /// Report the successful `move_with` step that started at `old_depth`.
+    fn finish_move(&mut self, old_depth: usize) {
+        let depth = self.cursor.depth();
+        if depth > old_depth {
+            let status = self.top_status();
+            self.observer.on_lock(depth, status);
+        }
+        self.report_step(old_depth, depth);
+    }
+
+    /// See `MutexGuardStack::inject_with_policy`.
+    pub fn inject_with_policy(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&'root Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> Option<Result<&mut T, TryLockError<()>>> {
+        let result = self.cursor.inject_with_policy(f, policy)?.map(|_| ());
+        Some(self.finish_lock(result))
+    }
+
+    /// See `MutexGuardStack::descend_with`.
+    #[deprecated(note = "use `descend_with_policy` instead")]
+    pub fn descend_with(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node Mutex<T>>,
+        ignore_poison: bool,
+    ) -> Option<Result<&mut T, TryLockError<()>>> {
+        self.descend_with_policy(f, PoisonPolicy::from_ignore_poison(ignore_poison))
+    }
+
+    /// See `MutexGuardStack::try_descend`.
+    pub fn try_descend(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> DescendResult<'_, T, TryLockError<()>> {
+        DescendResult::from_option(self.descend_with_policy(f, policy))
+    }
+
+    /// See `MutexGuardStack::move_with_policy`.
+    pub fn move_with_policy<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, mutex::MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> mutex::MoveDecision<'root, 'a, T>,
+    {
+        let old_depth = self.cursor.depth();
+        if let Err(err) = self.cursor.move_with_raw(f, policy) {
+            return Err(self.fail_move(err));
+        }
+        self.finish_move(old_depth);
+        Ok(self.cursor.top_mut())
+    }
+
+    /// See `MutexGuardStack::drive`.
+    /// Every step is reported like a separate `move_with_policy`, except for the final `Finish`.
+    pub fn drive<R, F>(
+        &mut self,
+        mut f: F,
+        policy: PoisonPolicy,
+    ) -> Result<Option<R>, mutex::MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnMut(&'a mut T) -> mutex::MoveDecision<'root, 'a, T, R>,
+    {
+        loop {
+            let old_depth = self.cursor.depth();
+            let mut finished = None;
+            let result = self.cursor.move_with_raw(
+                |top| match f(top) {
+                    mutex::MoveDecision::Finish(result) => {
+                        finished = Some(result);
+                        mutex::MoveDecision::Finish(())
+                    }
+                    mutex::MoveDecision::Ascend => mutex::MoveDecision::Ascend,
+                    mutex::MoveDecision::Stay => mutex::MoveDecision::Stay,
+                    mutex::MoveDecision::Descend(new_top) => mutex::MoveDecision::Descend(new_top),
+                    mutex::MoveDecision::Inject(new_top) => mutex::MoveDecision::Inject(new_top),
+                    mutex::MoveDecision::AscendWhile(predicate) => {
+                        mutex::MoveDecision::AscendWhile(predicate)
+                    }
+                },
+                policy,
+            );
+            if let Some(result) = finished {
+                return Ok(Some(result));
+            }
+            match result {
+                Ok(()) => self.finish_move(old_depth),
+                Err(mutex::RawMoveError::AscendAtRoot) => return Ok(None),
+                Err(err) => return Err(self.fail_move(err)),
+            }
+        }
+    }
+
+    /// See `MutexGuardStack::move_with_async_policy`.
+    pub async fn move_with_async_policy<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, mutex::MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnOnce(
+            &'a mut T,
+        )
+            -> Pin<Box<dyn Future<Output = mutex::MoveDecision<'root, 'a, T>> + 'a>>,
+    {
+        self.move_with_async_unboxed(f, policy).await
+    }
+
+    /// See `MutexGuardStack::move_with_async_unboxed`.
+    pub async fn move_with_async_unboxed<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, mutex::MoveError<'_, 'root, T>>
+    where
+        F: for<'a> mutex::AsyncMover<'a, 'root, T>,
+    {
+        let old_depth = self.cursor.depth();
+        if let Err(err) = self.cursor.move_with_async_raw(f, policy).await {
+            return Err(self.fail_move(err));
+        }
+        self.finish_move(old_depth);
+        Ok(self.cursor.top_mut())
+    }
+
+    /// See `MutexGuardStack::into_top_leaking`.
+    pub fn into_top_leaking(self) -> MutexGuard<'root, T> {
+        self.cursor.into_top_leaking()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        Descend(usize),
+        Ascend(usize),
+        MoveError,
+        Lock(usize, LockStatus),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl Observer for Recorder {
+        fn on_descend(&mut self, depth: usize) {
+            self.0.push(Event::Descend(depth));
+        }
+
+        fn on_ascend(&mut self, depth: usize) {
+            self.0.push(Event::Ascend(depth));
+        }
+
+        fn on_move_error(&mut self, _err: &dyn Error) {
+            self.0.push(Event::MoveError);
+        }
+
+        fn on_lock(&mut self, depth: usize, status: LockStatus) {
+            self.0.push(Event::Lock(depth, status));
+        }
+    }
+
+    impl Recorder {
+        /// Take the events recorded since the last call.
+        fn take(&mut self) -> Vec<Event> {
+            std::mem::take(&mut self.0)
+        }
+    }
+
+    struct Node {
+        next: Option<Arc<Mutex<Node>>>,
+    }
+
+    /// A list of `len` nodes, returning every node from the head.
+    fn list(len: usize) -> Vec<Arc<Mutex<Node>>> {
+        let mut nodes: Vec<Arc<Mutex<Node>>> = vec![];
+        for _ in 0..len {
+            let next = nodes.last().cloned();
+            nodes.push(Arc::new(Mutex::new(Node { next })));
+        }
+        nodes.reverse();
+        nodes
+    }
+
+    fn poison(node: &Mutex<Node>) {
+        let result = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _guard = node.lock();
+                    panic!("poison the Mutex");
+                })
+                .join()
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn mutex_events() {
+        use mutex::MoveDecision;
+        use Event::*;
+        use LockStatus::*;
+
+        let nodes = list(4);
+        poison(&nodes[2]);
+        let stack = mutex::MutexGuardStack::new(&nodes[0]).unwrap();
+        let mut stack = Instrumented::new(stack, Recorder::default());
+        fn next(node: &mut Node) -> Option<&Mutex<Node>> {
+            node.next.as_deref()
+        }
+
+        stack
+            .descend_with_policy(next, PoisonPolicy::Fail)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stack.observer_mut().take(), [Lock(1, Acquired), Descend(1)]);
+
+        // A poisoned step, first refused and then accepted.
+        let result = stack.descend_with_policy(next, PoisonPolicy::Fail).unwrap();
+        assert!(matches!(result, Err(TryLockError::Poisoned(_))));
+        assert_eq!(stack.observer_mut().take(), [Lock(2, Poisoned)]);
+        let result = stack.move_with_policy(
+            |node| MoveDecision::Descend(node.next.as_deref().unwrap()),
+            PoisonPolicy::Fail,
+        );
+        assert!(matches!(result, Err(mutex::MoveError::Poisoned(_))));
+        // Release the poisoned guard held by the error.
+        drop(result);
+        assert_eq!(stack.observer_mut().take(), [Lock(2, Poisoned), MoveError]);
+        stack
+            .descend_with_policy(next, PoisonPolicy::IgnoreAndContinue)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stack.observer_mut().take(), [Lock(2, Poisoned), Descend(2)]);
+
+        // A step that would block, while the node is locked elsewhere.
+        let held = nodes[3].lock().unwrap();
+        let result = stack.try_descend(next, PoisonPolicy::Fail);
+        assert!(matches!(
+            result,
+            DescendResult::LockFailed(TryLockError::WouldBlock)
+        ));
+        assert_eq!(stack.observer_mut().take(), [Lock(3, WouldBlock)]);
+        let result = stack.move_with_policy(
+            |node| MoveDecision::Descend(node.next.as_deref().unwrap()),
+            PoisonPolicy::Fail,
+        );
+        assert!(matches!(result, Err(mutex::MoveError::WouldBlock)));
+        assert_eq!(
+            stack.observer_mut().take(),
+            [Lock(3, WouldBlock), MoveError]
+        );
+        drop(held);
+
+        let result = stack.drive(
+            |node| match node.next.as_deref() {
+                Some(next) => MoveDecision::Descend(next),
+                None => MoveDecision::Finish("leaf"),
+            },
+            PoisonPolicy::Fail,
+        );
+        assert_eq!(result.unwrap(), Some("leaf"));
+        assert_eq!(stack.observer_mut().take(), [Lock(3, Acquired), Descend(3)]);
+
+        stack.ascend_while(|_| true);
+        assert_eq!(
+            stack.observer_mut().take(),
+            [Ascend(2), Ascend(1), Ascend(0)]
+        );
+        let result = stack.move_with_policy(|_| MoveDecision::Ascend, PoisonPolicy::Fail);
+        assert!(matches!(result, Err(mutex::MoveError::AscendAtRoot)));
+        assert_eq!(stack.observer_mut().take(), [MoveError]);
+    }
+
+    #[test]
+    fn rwlock_events() {
+        use Event::*;
+
+        struct Node {
+            next: Option<Box<RwLock<Node>>>,
+        }
+
+        let leaf = RwLock::new(Node { next: None });
+        let root = RwLock::new(Node {
+            next: Some(Box::new(RwLock::new(Node { next: None }))),
+        });
+        let stack = GuardStack::new(&root).unwrap();
+        let mut stack = Instrumented::new(stack, Recorder::default());
+        stack
+            .descend_with_policy(|node| node.next.as_deref(), PoisonPolicy::Fail)
+            .unwrap()
+            .unwrap();
+        stack.inject_top_policy(&leaf, PoisonPolicy::Fail).unwrap();
+        assert_eq!(stack.depth(), 2);
+        assert!(stack.to_depth(3).is_err());
+        stack.ascend_n(2).unwrap();
+        assert_eq!(
+            stack.observer_mut().take(),
+            [
+                Lock(1, LockStatus::Acquired),
+                Descend(1),
+                Lock(2, LockStatus::Acquired),
+                Descend(2),
+                Ascend(0),
+            ]
+        );
+    }
+}
@@ -1,4 +1,346 @@
+use std::{
+    cell::{BorrowError, BorrowMutError},
+    error::Error as StdError,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{PoisonError, TryLockError},
+};
+
+pub mod bfs;
+pub mod guard;
+pub mod instrumented;
 pub mod mutex;
 pub mod refcell;
 pub mod simple;
+pub mod slice;
+pub mod tree;
+pub mod walk;
 pub mod with_data;
+
+/// Information about a cursor's position, passed to the `*_with_ctx` closures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescendCtx {
+    /// How many levels above the root the top of the stack is.
+    pub depth: usize,
+    /// Whether the top of the stack is the root.
+    pub is_at_root: bool,
+    /// Whether the previous attempt to lock or borrow a new top would have blocked.
+    /// Always `false` for cursors that do not lock or borrow.
+    pub previous_step_blocked: bool,
+}
+
+/// Which nodes the `descend_with_cycle_check` methods consider to be a repeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CyclePolicy {
+    /// Only nodes currently on the stack.
+    OnPath,
+    /// Nodes currently on the stack, and any node previously descended into with `descend_with_cycle_check`.
+    Anywhere,
+}
+
+/// Returned by `MutexGuardStack::to_depth` and `RefCellRefMutStack::truncate_to_depth` when the target is deeper than the top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToDepthError {
+    /// The requested depth.
+    pub target: usize,
+    /// The depth of the top of the stack.
+    pub depth: usize,
+}
+
+impl fmt::Display for ToDepthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot ascend to depth {} from depth {}",
+            self.target, self.depth
+        )
+    }
+}
+
+impl StdError for ToDepthError {}
+
+/// The result of the `probe_with` methods, which check whether a descend would succeed without descending.
+///
+/// The answer is only advisory: another borrow or lock may be taken (or released) between the probe and the descend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeResult {
+    /// The selected node could be borrowed or locked.
+    Free,
+    /// The selected node is already borrowed or locked, so descending into it would fail (or block).
+    WouldConflict,
+    /// The closure did not select a node.
+    NoChild,
+}
+
+/// The result of the `try_descend` methods, with one variant per outcome.
+#[derive(Debug)]
+pub enum DescendResult<'a, T: ?Sized, E> {
+    /// The closure did not select a node, so the stack did not move.
+    NoChild,
+    /// The stack descended into the selected node, which is the new top.
+    Descended(&'a mut T),
+    /// The selected node could not be borrowed or locked, so the stack did not move.
+    LockFailed(E),
+}
+
+impl<'a, T: ?Sized, E> DescendResult<'a, T, E> {
+    /// Helper function to convert the return value of the `descend_with` methods.
+    pub(crate) fn from_option(result: Option<Result<&'a mut T, E>>) -> Self {
+        match result {
+            None => DescendResult::NoChild,
+            Some(Ok(top)) => DescendResult::Descended(top),
+            Some(Err(err)) => DescendResult::LockFailed(err),
+        }
+    }
+}
+
+/// An identity token for a node on a cursor's stack.
+///
+/// This wraps the address (and, for `?Sized` types, the pointer metadata) of the node,
+/// or, for a `GuardStack`, of the node's lock.
+/// It is never dereferenced, so it is only meaningful while the underlying node is alive:
+/// once a node is dropped, its address may be reused by an unrelated node.
+pub struct TopId<T: ?Sized> {
+    ptr: *const T,
+}
+
+// SAFETY: A `TopId` is only an address, it is never dereferenced.
+unsafe impl<T: ?Sized> Send for TopId<T> {}
+// SAFETY: A `TopId` is only an address, it is never dereferenced.
+unsafe impl<T: ?Sized> Sync for TopId<T> {}
+
+impl<T: ?Sized> TopId<T> {
+    pub(crate) fn new(ptr: *const T) -> Self {
+        Self { ptr }
+    }
+
+    /// The address this id was created from.
+    pub fn as_ptr(self) -> *const T {
+        self.ptr
+    }
+}
+
+impl<T: ?Sized> Clone for TopId<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for TopId<T> {}
+
+impl<T: ?Sized> PartialEq for TopId<T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.ptr, other.ptr)
+    }
+}
+
+impl<T: ?Sized> Eq for TopId<T> {}
+
+impl<T: ?Sized> Hash for TopId<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ptr.hash(state);
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for TopId<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TopId")
+            .field(&self.ptr.cast::<()>())
+            .finish()
+    }
+}
+
+mod sealed {
+    pub trait Decision {}
+    pub trait AsyncMove<T: ?Sized, D: ?Sized> {}
+}
+
+/// The `MoveDecision` of a cursor module, for a top borrowed for `'a`.
+///
+/// This is sealed, and implemented by `simple::MoveDecision<'root, 'root, T>`, `refcell::MoveDecision<'root, 'root, T>`
+/// and `mutex::MoveDecision<'root, 'root, T>`, each of which stands for its own `MoveDecision<'root, 'a, T>` for every `'a`.
+/// The `_Bound` parameter should be left as its default. It only exists so that `for<'a> MoveDecisionFor<'a, T>`
+/// implies `T: 'a`.
+pub trait MoveDecisionFor<'a, T: ?Sized + 'a, _Bound = &'a T>: sealed::Decision {
+    /// The decision, which may borrow from the top for `'a`.
+    type Decision;
+}
+
+/// An async closure for the `move_with_async_fn` methods, e.g. `async |top| { ... }`,
+/// turning the current top into a `MoveDecision`.
+///
+/// This is sealed, and implemented for every `for<'a> AsyncFnOnce(&'a mut T) -> MoveDecision<'root, 'a, T>`,
+/// where `D` is the cursor module's `MoveDecision<'root, 'root, T>`.
+/// Unlike with `move_with_async`, the future does not need to be boxed, and the closure does not need to name any lifetime.
+pub trait AsyncMove<T: ?Sized, D: for<'a> MoveDecisionFor<'a, T>>:
+    for<'a> AsyncFnOnce(&'a mut T) -> <D as MoveDecisionFor<'a, T>>::Decision + sealed::AsyncMove<T, D>
+{
+}
+
+impl<T: ?Sized, D: for<'a> MoveDecisionFor<'a, T>, F> sealed::AsyncMove<T, D> for F where
+    F: for<'a> AsyncFnOnce(&'a mut T) -> <D as MoveDecisionFor<'a, T>>::Decision
+{
+}
+
+impl<T: ?Sized, D: for<'a> MoveDecisionFor<'a, T>, F> AsyncMove<T, D> for F where
+    F: for<'a> AsyncFnOnce(&'a mut T) -> <D as MoveDecisionFor<'a, T>>::Decision
+{
+}
+
+/// An error from any of the cursors, so that one `?` works across modules.
+/// Every module's error types convert into this.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A move asked to ascend, but the stack was already at its root.
+    AscendAtRoot,
+    /// A `RefCell` could not be mutably borrowed.
+    BorrowMutError(BorrowMutError),
+    /// A `RefCell` could not be borrowed, because it was already mutably borrowed.
+    BorrowError(BorrowError),
+    /// A `Mutex` was poisoned.
+    Poisoned,
+    /// A `Mutex` could not be locked without blocking.
+    WouldBlock,
+    /// The top of a `refcell::AnyRefMutStack` was not of the requested type.
+    TypeMismatch(refcell::TypeMismatch),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AscendAtRoot => f.write_str("cannot ascend above the root"),
+            Error::BorrowMutError(_) => f.write_str("the new top is already borrowed"),
+            Error::BorrowError(_) => f.write_str("the new top is already mutably borrowed"),
+            Error::Poisoned => f.write_str("the new top's Mutex is poisoned"),
+            Error::WouldBlock => f.write_str("the new top's Mutex is already locked"),
+            Error::TypeMismatch(err) => err.fmt(f),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::BorrowMutError(err) => Some(err),
+            Error::BorrowError(err) => Some(err),
+            Error::TypeMismatch(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<BorrowMutError> for Error {
+    fn from(err: BorrowMutError) -> Self {
+        Error::BorrowMutError(err)
+    }
+}
+
+impl From<BorrowError> for Error {
+    fn from(err: BorrowError) -> Self {
+        Error::BorrowError(err)
+    }
+}
+
+impl From<TryLockError<()>> for Error {
+    fn from(err: TryLockError<()>) -> Self {
+        match err {
+            TryLockError::Poisoned(_) => Error::Poisoned,
+            TryLockError::WouldBlock => Error::WouldBlock,
+        }
+    }
+}
+
+impl From<PoisonError<()>> for Error {
+    fn from(_: PoisonError<()>) -> Self {
+        Error::Poisoned
+    }
+}
+
+impl From<mutex::WouldBlock> for Error {
+    fn from(_: mutex::WouldBlock) -> Self {
+        Error::WouldBlock
+    }
+}
+
+impl From<simple::MoveError> for Error {
+    fn from(err: simple::MoveError) -> Self {
+        match err {
+            simple::MoveError::AscendAtRoot => Error::AscendAtRoot,
+        }
+    }
+}
+
+impl From<with_data::MoveError> for Error {
+    fn from(err: with_data::MoveError) -> Self {
+        match err {
+            with_data::MoveError::AscendAtRoot => Error::AscendAtRoot,
+        }
+    }
+}
+
+impl From<refcell::MoveError> for Error {
+    fn from(err: refcell::MoveError) -> Self {
+        match err {
+            refcell::MoveError::AscendAtRoot => Error::AscendAtRoot,
+            refcell::MoveError::BorrowMutError(err) => Error::BorrowMutError(err),
+        }
+    }
+}
+
+impl From<refcell::RefMoveError> for Error {
+    fn from(err: refcell::RefMoveError) -> Self {
+        match err {
+            refcell::RefMoveError::AscendAtRoot => Error::AscendAtRoot,
+            refcell::RefMoveError::BorrowError(err) => Error::BorrowError(err),
+        }
+    }
+}
+
+impl From<refcell::TypeMismatch> for Error {
+    fn from(err: refcell::TypeMismatch) -> Self {
+        Error::TypeMismatch(err)
+    }
+}
+
+impl From<refcell::AnyDescendError> for Error {
+    fn from(err: refcell::AnyDescendError) -> Self {
+        match err {
+            refcell::AnyDescendError::TypeMismatch(err) => Error::TypeMismatch(err),
+            refcell::AnyDescendError::BorrowMutError(err) => Error::BorrowMutError(err),
+        }
+    }
+}
+
+impl From<refcell::DescendError> for Error {
+    fn from(err: refcell::DescendError) -> Self {
+        Error::BorrowMutError(err.source)
+    }
+}
+
+/// This drops the stack held by `mutex::NewError::Poisoned`.
+impl<T: ?Sized> From<mutex::NewError<'_, T>> for Error {
+    fn from(err: mutex::NewError<'_, T>) -> Self {
+        match err {
+            mutex::NewError::WouldBlock => Error::WouldBlock,
+            mutex::NewError::Poisoned(_) => Error::Poisoned,
+        }
+    }
+}
+
+impl From<refcell::NewError> for Error {
+    fn from(err: refcell::NewError) -> Self {
+        Error::BorrowMutError(err.source)
+    }
+}
+
+/// This drops the guard held by `mutex::MoveError::Poisoned`.
+impl<T: ?Sized> From<mutex::MoveError<'_, '_, T>> for Error {
+    fn from(err: mutex::MoveError<'_, '_, T>) -> Self {
+        match err {
+            mutex::MoveError::AscendAtRoot => Error::AscendAtRoot,
+            mutex::MoveError::Poisoned(_) => Error::Poisoned,
+            mutex::MoveError::WouldBlock => Error::WouldBlock,
+        }
+    }
+}
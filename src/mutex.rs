@@ -1,230 +1,1039 @@
 use std::{
+    error::Error,
+    fmt,
     future::Future,
-    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
     pin::Pin,
-    sync::{Mutex, MutexGuard, PoisonError, TryLockError, TryLockResult},
+    ptr,
+    sync::{Arc, Condvar, LockResult, Mutex, MutexGuard, PoisonError, TryLockError},
+    task::{Context, Poll},
+    thread,
+    time::Duration,
 };
 
-pub struct MutexGuardStack<'root, T: ?Sized> {
-    /// Ensures this mutrefstack does not exceed the lifetime of its root.
-    lifetime: PhantomData<&'root mut T>,
-    /// The stack of pointers. Each one borrows from the one prior, except the first which is the `root` and may never be popped.
-    /// Note: the `'root` lifetime is a "lie", only used because there's no raw pointer counterpart for `MutexGuard`.
-    /// The `MutexGuard`s are not publicly accessible so this is fine.
-    data: Vec<MutexGuard<'root, T>>,
-}
+use crate::{guard::held, DescendCtx};
+pub use crate::{
+    guard::{GuardInfo, GuardReport, GuardStack, PoisonPolicy},
+    AsyncMove, DescendResult, MoveDecisionFor, ProbeResult, ToDepthError,
+};
 
-pub enum MoveDecision<'root, 'this, T: ?Sized> {
+/// A cursor over a recursive data structure of `Mutex`es, holding the lock of every node from the root to the top.
+///
+/// This is a `GuardStack`, so it has all of its methods, and the `Mutex`-specific ones below,
+/// such as blocking and async descends, and closure-driven moves.
+///
+/// Like `MutexGuard`, this is not `Send`: every guard must be unlocked on the thread that locked it.
+/// It is `Sync` when `T` is `Sync`.
+///
+/// ```compile_fail
+/// # use generic_cursors::mutex::MutexGuardStack;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<MutexGuardStack<'static, u32>>();
+/// ```
+pub type MutexGuardStack<'root, T> = GuardStack<'root, Mutex<T>>;
+
+pub enum MoveDecision<'root, 'this, T: ?Sized, R = ()> {
     Ascend,
     Stay,
     Descend(&'this Mutex<T>),
     Inject(&'root Mutex<T>),
+    /// Ascend while the predicate returns `true`, like `MutexGuardStack::ascend_while`, starting with the current top.
+    /// The root is never popped.
+    /// The predicate is called on nodes after the current top has been unlocked, so it may not borrow from it
+    /// (it must outlive `'root`).
+    /// Ascending never locks anything, so the poison policy does not apply.
+    AscendWhile(Box<dyn FnMut(&mut T) -> bool + 'root>),
+    /// Stop `MutexGuardStack::drive`, returning the value. The `move_with*` methods treat this like `Stay`.
+    Finish(R),
+}
+
+impl<'root, 'this, T: ?Sized, R> MoveDecision<'root, 'this, T, R> {
+    /// Descend into `node`. Equivalent to `MoveDecision::Descend(node)`.
+    pub fn descend(node: &'this Mutex<T>) -> Self {
+        MoveDecision::Descend(node)
+    }
+
+    /// Inject `node` as the new top. Equivalent to `MoveDecision::Inject(node)`.
+    pub fn inject(node: &'root Mutex<T>) -> Self {
+        MoveDecision::Inject(node)
+    }
+}
+
+/// An asynchronous step of `MutexGuardStack::move_with_async_unboxed`,
+/// turning the current top into a future of a `MoveDecision`.
+///
+/// This is implemented for every `FnOnce(&'a mut T) -> impl Future<Output = MoveDecision<'root, 'a, T>>`,
+/// so an `async fn` taking the top can be passed without boxing its future.
+/// Closures usually cannot name the lifetime of the returned future, so for those, use an async closure
+/// with `move_with_async_fn`, implement this trait on a type of your own, or box the future and use `move_with_async_policy`.
+///
+/// The `_Bound` parameter should be left as its default. It only exists so that `for<'a> AsyncMover<'a, 'root, T>`
+/// implies `T: 'a`, instead of requiring `T: 'static`.
+///
+/// For example, a mover that adds the top to a running total:
+///
+/// ```
+/// # use std::{future::{ready, Ready}, sync::Mutex};
+/// # use generic_cursors::mutex::{AsyncMover, MoveDecision, MutexGuardStack, PoisonPolicy};
+/// struct Count<'s>(&'s mut u32);
+///
+/// impl<'a, 'root> AsyncMover<'a, 'root, u32> for Count<'_> {
+///     type Future = Ready<MoveDecision<'root, 'a, u32>>;
+///
+///     fn call(self, top: &'a mut u32) -> Self::Future {
+///         *self.0 += *top;
+///         ready(MoveDecision::Stay)
+///     }
+/// }
+///
+/// let root = Mutex::new(3u32);
+/// let mut stack = MutexGuardStack::new(&root).unwrap();
+/// let mut total = 0;
+/// drop(stack.move_with_async_unboxed(Count(&mut total), PoisonPolicy::Fail));
+/// ```
+///
+/// The mover must work for any lifetime `'a`, so it cannot smuggle the top out:
+///
+/// ```compile_fail
+/// # use std::{future::{ready, Ready}, sync::Mutex};
+/// # use generic_cursors::mutex::{AsyncMover, MoveDecision, MutexGuardStack, PoisonPolicy};
+/// struct Smuggle<'s>(&'s mut Option<&'s mut u32>);
+///
+/// impl<'s, 'root> AsyncMover<'s, 'root, u32> for Smuggle<'s> {
+///     type Future = Ready<MoveDecision<'root, 's, u32>>;
+///
+///     fn call(self, top: &'s mut u32) -> Self::Future {
+///         *self.0 = Some(top);
+///         ready(MoveDecision::Stay)
+///     }
+/// }
+///
+/// let root = Mutex::new(3u32);
+/// let mut stack = MutexGuardStack::new(&root).unwrap();
+/// let mut smuggled = None;
+/// drop(stack.move_with_async_unboxed(Smuggle(&mut smuggled), PoisonPolicy::Fail));
+/// ```
+pub trait AsyncMover<'a, 'root, T: ?Sized + 'a + 'root, _Bound = &'a T> {
+    type Future: Future<Output = MoveDecision<'root, 'a, T>>;
+
+    fn call(self, top: &'a mut T) -> Self::Future;
 }
 
-pub enum MoveError {
+impl<'a, 'root, T: ?Sized + 'root, F, Fut> AsyncMover<'a, 'root, T, &'a T> for F
+where
+    F: FnOnce(&'a mut T) -> Fut,
+    Fut: Future<Output = MoveDecision<'root, 'a, T>>,
+{
+    type Future = Fut;
+
+    fn call(self, top: &'a mut T) -> Fut {
+        self(top)
+    }
+}
+
+impl<T: ?Sized> crate::sealed::Decision for MoveDecision<'_, '_, T> {}
+
+impl<'root, 'a, T: ?Sized> MoveDecisionFor<'a, T> for MoveDecision<'root, 'root, T> {
+    type Decision = MoveDecision<'root, 'a, T>;
+}
+
+#[non_exhaustive]
+pub enum MoveError<'a, 'root, T: ?Sized> {
+    /// The closure asked to ascend, but the stack was already at its root.
     AscendAtRoot,
-    Poisoned,
+    /// The selected `Mutex` was poisoned, and the poison policy was `PoisonPolicy::Fail`.
+    /// The poisoned guard is still held, and can be pushed onto the stack after all with `RecoverTop::push`.
+    Poisoned(RecoverTop<'a, 'root, T>),
+    /// The selected `Mutex` could not be locked without blocking.
     WouldBlock,
 }
 
-impl<'root, T: ?Sized> MutexGuardStack<'root, T> {
-    /// Create a new MutRefStack from a mutable reference to the root
-    /// of a recursive data structure.
-    pub fn new(root: &'root Mutex<T>) -> TryLockResult<Self> {
-        let root: *const Mutex<T> = root;
-        let guard = unsafe { (*root).try_lock() };
-        match guard {
-            Ok(guard) => Ok(Self {
-                lifetime: PhantomData,
-                data: vec![guard],
-            }),
-            Err(TryLockError::Poisoned(guard)) => {
-                Err(TryLockError::Poisoned(PoisonError::new(Self {
-                    lifetime: PhantomData,
-                    data: vec![guard.into_inner()],
-                })))
+impl<'a, 'root, T: ?Sized> MoveError<'a, 'root, T> {
+    /// Did the closure ask to ascend at the root?
+    pub fn is_ascend_at_root(&self) -> bool {
+        matches!(self, MoveError::AscendAtRoot)
+    }
+
+    /// Was the selected `Mutex` poisoned?
+    pub fn is_poisoned(&self) -> bool {
+        matches!(self, MoveError::Poisoned(_))
+    }
+
+    /// Would locking the selected `Mutex` have blocked?
+    pub fn is_would_block(&self) -> bool {
+        matches!(self, MoveError::WouldBlock)
+    }
+
+    /// The guard of the poisoned `Mutex`, if that is why the move failed.
+    pub fn into_recover_top(self) -> Option<RecoverTop<'a, 'root, T>> {
+        match self {
+            MoveError::Poisoned(recover) => Some(recover),
+            _ => None,
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for MoveError<'_, '_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::AscendAtRoot => f.write_str("AscendAtRoot"),
+            MoveError::Poisoned(recover) => f.debug_tuple("Poisoned").field(recover).finish(),
+            MoveError::WouldBlock => f.write_str("WouldBlock"),
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Display for MoveError<'_, '_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::AscendAtRoot => f.write_str("cannot ascend above the root"),
+            MoveError::Poisoned(_) => f.write_str("the new top's Mutex is poisoned"),
+            MoveError::WouldBlock => f.write_str("the new top's Mutex is already locked"),
+        }
+    }
+}
+
+impl<T: ?Sized> Error for MoveError<'_, '_, T> {}
+
+/// Two `MoveError::Poisoned`s are equal if they hold the guard of the same `Mutex`.
+impl<T: ?Sized> PartialEq for MoveError<'_, '_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (MoveError::AscendAtRoot, MoveError::AscendAtRoot) => true,
+            (MoveError::Poisoned(this), MoveError::Poisoned(other)) => {
+                std::ptr::eq(this.mutex, other.mutex)
             }
-            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+            (MoveError::WouldBlock, MoveError::WouldBlock) => true,
+            _ => false,
         }
     }
+}
 
-    pub fn raw_top_mut(&mut self) -> *mut T {
-        let guard: *mut MutexGuard<T> = self.data.last_mut().unwrap();
-        unsafe { &mut **guard }
+/// `MoveError::WouldBlock` becomes `io::ErrorKind::WouldBlock`, the rest `io::ErrorKind::Other`.
+/// This drops the guard held by `MoveError::Poisoned`.
+#[cfg(feature = "io")]
+impl<T: ?Sized> From<MoveError<'_, '_, T>> for std::io::Error {
+    fn from(err: MoveError<'_, '_, T>) -> Self {
+        let kind = match err {
+            MoveError::WouldBlock => std::io::ErrorKind::WouldBlock,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err.to_string())
     }
+}
+
+/// The guard of a poisoned `Mutex` that a `MutexGuardStack` refused to push, returned in `MoveError::Poisoned`.
+/// It borrows the stack, so the guard can still be pushed onto it after all.
+/// Dropping this unlocks the `Mutex`.
+pub struct RecoverTop<'a, 'root, T: ?Sized> {
+    stack: &'a mut MutexGuardStack<'root, T>,
+    /// Note: the `'root` lifetime is a "lie", like the guards on the stack.
+    guard: MutexGuard<'root, T>,
+    mutex: *const Mutex<T>,
+}
 
-    /// Obtain a shared reference to the top of the stack.
-    pub fn top(&self) -> &T {
-        &*self.data.last().unwrap()
+impl<'a, 'root, T: ?Sized> RecoverTop<'a, 'root, T> {
+    /// Obtain a shared reference to the poisoned data.
+    pub fn get(&self) -> &T {
+        &self.guard
     }
 
-    /// Obtain a mutable reference to the top of the stack.
-    pub fn top_mut(&mut self) -> &mut T {
-        &mut *self.data.last_mut().unwrap()
+    /// Obtain a mutable reference to the poisoned data.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.guard
     }
 
-    /// Is this MutRefStack currently at its root?
-    pub fn is_at_root(&self) -> bool {
-        self.data.len() == 1
+    /// Push the poisoned guard onto the stack after all, leaving the `Mutex` poisoned,
+    /// and return a mutable reference to the new top.
+    pub fn push(self) -> &'a mut T {
+        let Self {
+            stack,
+            guard,
+            mutex,
+        } = self;
+        stack.push_level(guard, mutex, true);
+        stack.top_mut()
     }
 
-    fn handle_trylock_result(
-        &mut self,
-        guard: TryLockResult<MutexGuard<'root, T>>,
-        ignore_poison: bool,
-    ) -> Result<&mut T, TryLockError<()>> {
-        match (guard, ignore_poison) {
-            (Ok(guard), _) => {
-                self.data.push(guard);
-                Ok(self.top_mut())
-            }
-            (Err(TryLockError::Poisoned(guard)), true) => {
-                self.data.push(guard.into_inner());
-                Ok(self.top_mut())
-            }
-            (Err(TryLockError::Poisoned(_guard)), false) => {
-                Err(TryLockError::Poisoned(PoisonError::new(())))
-            }
-            (Err(TryLockError::WouldBlock), _) => Err(TryLockError::WouldBlock),
+    /// Clear the poison of the `Mutex` with `Mutex::clear_poison`, and push the guard onto the stack after all.
+    pub fn clear_and_push(self) -> &'a mut T {
+        unsafe { (*self.mutex).clear_poison() };
+        self.push()
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for RecoverTop<'_, '_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecoverTop").finish_non_exhaustive()
+    }
+}
+
+/// A failure to lock a new top, before it is tied to a borrow of the stack (see `MoveError`).
+pub(crate) enum RawMoveError<'root, T: ?Sized> {
+    AscendAtRoot,
+    Poisoned(MutexGuard<'root, T>, *const Mutex<T>),
+    WouldBlock,
+}
+
+impl<'root, T: ?Sized> RawMoveError<'root, T> {
+    /// Tie this to the stack whose move failed.
+    pub(crate) fn attach<'a>(
+        self,
+        stack: &'a mut MutexGuardStack<'root, T>,
+    ) -> MoveError<'a, 'root, T> {
+        match self {
+            RawMoveError::AscendAtRoot => MoveError::AscendAtRoot,
+            RawMoveError::Poisoned(guard, mutex) => MoveError::Poisoned(RecoverTop {
+                stack,
+                guard,
+                mutex,
+            }),
+            RawMoveError::WouldBlock => MoveError::WouldBlock,
         }
     }
+}
 
-    fn handle_move_trylock_result(
-        &mut self,
-        guard: TryLockResult<MutexGuard<'root, T>>,
-        ignore_poison: bool,
-    ) -> Result<&mut T, MoveError> {
-        match (guard, ignore_poison) {
-            (Ok(guard), _) => {
-                self.data.push(guard);
-                Ok(self.top_mut())
-            }
-            (Err(TryLockError::Poisoned(guard)), true) => {
-                self.data.push(guard.into_inner());
-                Ok(self.top_mut())
+/// Returned by `MutexGuardStack::descend_chain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainOutcome {
+    /// How many levels were descended.
+    pub descended: usize,
+    /// Why the chain stopped.
+    pub stop: ChainStop,
+}
+
+/// Why `MutexGuardStack::descend_chain` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainStop {
+    /// The closure did not select a `Mutex`.
+    NoChild,
+    /// The selected `Mutex` could not be locked without blocking.
+    WouldBlock,
+    /// The selected `Mutex` was poisoned, and the poison policy was `PoisonPolicy::Fail`.
+    Poisoned,
+    /// The maximum number of levels was descended.
+    DepthLimit,
+}
+
+/// Returned by `MutexGuardStack::try_descend_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescendError<E> {
+    /// The closure failed.
+    User(E),
+    /// The selected `Mutex` was poisoned, and the poison policy was `PoisonPolicy::Fail`.
+    Poisoned,
+    /// The selected `Mutex` could not be locked without blocking.
+    WouldBlock,
+}
+
+impl<E: fmt::Display> fmt::Display for DescendError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DescendError::User(err) => err.fmt(f),
+            DescendError::Poisoned => f.write_str("the new top's Mutex is poisoned"),
+            DescendError::WouldBlock => f.write_str("the new top's Mutex is already locked"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for DescendError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DescendError::User(err) => Some(err),
+            DescendError::Poisoned | DescendError::WouldBlock => None,
+        }
+    }
+}
+
+/// Returned when the root `Mutex` could not be locked without blocking,
+/// and by `ParkingLotMutexGuardStack::descend_with_timeout` when the new top stayed locked until the timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock;
+
+/// Returned by `MutexGuardStack::try_new`.
+pub enum NewError<'root, T: ?Sized> {
+    /// The root `Mutex` could not be locked without blocking.
+    WouldBlock,
+    /// The root `Mutex` was poisoned. The stack was created anyway, and can be used to recover.
+    Poisoned(MutexGuardStack<'root, T>),
+}
+
+impl<'root, T: ?Sized> NewError<'root, T> {
+    /// The stack, if the root was locked despite being poisoned.
+    pub fn into_stack(self) -> Option<MutexGuardStack<'root, T>> {
+        match self {
+            NewError::WouldBlock => None,
+            NewError::Poisoned(stack) => Some(stack),
+        }
+    }
+}
+
+impl<T: ?Sized> From<WouldBlock> for NewError<'_, T> {
+    fn from(_: WouldBlock) -> Self {
+        NewError::WouldBlock
+    }
+}
+
+impl<'root, T: ?Sized> From<TryLockError<MutexGuardStack<'root, T>>> for NewError<'root, T> {
+    fn from(err: TryLockError<MutexGuardStack<'root, T>>) -> Self {
+        match err {
+            TryLockError::Poisoned(poison) => NewError::Poisoned(poison.into_inner()),
+            TryLockError::WouldBlock => NewError::WouldBlock,
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for NewError<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewError::WouldBlock => f.write_str("WouldBlock"),
+            NewError::Poisoned(stack) => f.debug_tuple("Poisoned").field(stack).finish(),
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Display for NewError<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewError::WouldBlock => f.write_str("the root Mutex is already locked"),
+            NewError::Poisoned(_) => f.write_str("the root Mutex is poisoned"),
+        }
+    }
+}
+
+impl<T: ?Sized> Error for NewError<'_, T> {}
+
+/// How `MutexGuardStack::descend_with_retry` waits between attempts to lock a contended `Mutex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: usize,
+    /// How many failed attempts are followed by a `std::hint::spin_loop` before backing off further.
+    pub spins_before_yield: usize,
+    /// If set, sleep between the later attempts instead of calling `std::thread::yield_now`.
+    /// The sleep starts at this duration and doubles after every attempt.
+    pub sleep_backoff: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 100,
+            spins_before_yield: 10,
+            sleep_backoff: None,
+        }
+    }
+}
+
+/// Returned by `MutexGuardStack::descend_with_retry` when no attempt locked the new top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryExhausted {
+    /// How many attempts were made.
+    pub attempts: usize,
+    /// Whether the last attempt failed because the `Mutex` was poisoned (rather than contended).
+    /// Poison does not go away by waiting, so this also ends the retries early.
+    pub poisoned: bool,
+}
+
+impl fmt::Display for RetryExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = if self.poisoned {
+            "is poisoned"
+        } else {
+            "stayed locked"
+        };
+        write!(
+            f,
+            "the new top's Mutex {reason} after {} attempt(s)",
+            self.attempts
+        )
+    }
+}
+
+impl Error for RetryExhausted {}
+
+impl<'root, T: ?Sized> MutexGuardStack<'root, T> {
+    /// Create a new MutexGuardStack from the root `Mutex` of a recursive data structure, like `new`,
+    /// but without wrapping a poisoned stack in a `PoisonError`.
+    pub fn try_new(root: &'root Mutex<T>) -> Result<Self, NewError<'root, T>> {
+        Ok(Self::new(root)?)
+    }
+
+    /// Create a new MutexGuardStack from the root `Mutex` of a recursive data structure,
+    /// blocking until the root can be locked.
+    ///
+    /// With the `deadlock-detection` feature, this panics instead of deadlocking if the root is already
+    /// locked by any `MutexGuardStack` on the current thread.
+    pub fn new_blocking(root: &'root Mutex<T>) -> LockResult<Self> {
+        held::check((root as *const Mutex<T>).cast());
+        match root.lock() {
+            Ok(guard) => Ok(Self::from_root_guard(guard, Some(root), false)),
+            Err(poison) => Err(PoisonError::new(Self::from_root_guard(
+                poison.into_inner(),
+                Some(root),
+                true,
+            ))),
+        }
+    }
+
+    /// Create a new MutexGuardStack from the root `Mutex` of a recursive data structure,
+    /// ignoring poison.
+    pub fn new_ignore_poison(root: &'root Mutex<T>) -> Result<Self, WouldBlock> {
+        match root.try_lock() {
+            Ok(guard) => Ok(Self::from_root_guard(guard, Some(root), false)),
+            Err(TryLockError::Poisoned(poison)) => {
+                Ok(Self::from_root_guard(poison.into_inner(), Some(root), true))
             }
-            (Err(TryLockError::Poisoned(_guard)), false) => Err(MoveError::Poisoned),
-            (Err(TryLockError::WouldBlock), _) => Err(MoveError::WouldBlock),
+            Err(TryLockError::WouldBlock) => Err(WouldBlock),
         }
     }
 
+    /// Create a new MutexGuardStack from the root `Mutex` of a recursive data structure,
+    /// blocking until the root can be locked and ignoring poison.
+    ///
+    /// With the `deadlock-detection` feature, this panics like `new_blocking`.
+    pub fn new_blocking_ignore_poison(root: &'root Mutex<T>) -> Self {
+        Self::new_recover(root).0
+    }
+
+    /// Create a new MutexGuardStack from the root `Mutex` of a recursive data structure,
+    /// blocking until the root can be locked and ignoring poison.
+    /// Also returns whether the root was poisoned.
+    ///
+    /// With the `deadlock-detection` feature, this panics like `new_blocking`.
+    pub fn new_recover(root: &'root Mutex<T>) -> (Self, bool) {
+        held::check((root as *const Mutex<T>).cast());
+        let (guard, poisoned) = match root.lock() {
+            Ok(guard) => (guard, false),
+            Err(poison) => (poison.into_inner(), true),
+        };
+        (Self::from_root_guard(guard, Some(root), poisoned), poisoned)
+    }
+
+    /// Try to lock `mutex` and push it onto the stack.
+    /// On failure, the error does not borrow the stack, so the caller can still use it.
+    fn try_push(
+        &mut self,
+        mutex: *const Mutex<T>,
+        policy: PoisonPolicy,
+    ) -> Result<(), RawMoveError<'root, T>> {
+        self.try_push_lock(mutex, policy).map_err(|err| match err {
+            TryLockError::Poisoned(poison) => RawMoveError::Poisoned(poison.into_inner(), mutex),
+            TryLockError::WouldBlock => RawMoveError::WouldBlock,
+        })
+    }
+
     /// Inject a new reference to the top of the stack. The reference still must live
     /// as long as the root of the stack.
+    #[deprecated(note = "use `inject_top_policy` instead")]
     pub fn inject_top(
         &mut self,
         new_top: &'root Mutex<T>,
         ignore_poison: bool,
     ) -> Result<&mut T, TryLockError<()>> {
-        let new_top: *const Mutex<T> = new_top;
-        let guard = unsafe { (*new_top).try_lock() };
-        self.handle_trylock_result(guard, ignore_poison)
+        self.inject_top_policy(new_top, PoisonPolicy::from_ignore_poison(ignore_poison))
+    }
+
+    /// Inject a new top that is owned by the stack, e.g. a node just allocated by the caller,
+    /// returning a mutable reference to the new top element.
+    /// The `Arc` is kept by the new level, and when the level is ascended, it is dropped after the level's guard.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    pub fn inject_owned(
+        &mut self,
+        mutex: Arc<Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, TryLockError<()>> {
+        let len = self.data.len();
+        match self.try_push(Arc::as_ptr(&mutex), policy) {
+            Ok(()) => {}
+            Err(RawMoveError::Poisoned(_guard, _)) => {
+                return Err(TryLockError::Poisoned(PoisonError::new(())))
+            }
+            Err(RawMoveError::WouldBlock) => return Err(TryLockError::WouldBlock),
+            Err(RawMoveError::AscendAtRoot) => unreachable!("locking never ascends"),
+        }
+        debug_assert_eq!(self.data.len(), len + 1);
+        self.data.last_mut().unwrap().owned = Some(mutex);
+        Ok(self.top_mut())
+    }
+
+    /// Inject a new top that is owned by the stack like `inject_owned`, selected by the closure.
+    /// Returns None if the closure did not select a `Mutex`.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    pub fn inject_owned_with(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<Arc<Mutex<T>>>,
+        policy: PoisonPolicy,
+    ) -> Option<Result<&mut T, TryLockError<()>>> {
+        let mutex = f(self.top_mut())?;
+        Some(self.inject_owned(mutex, policy))
     }
 
     /// Inject a new reference to the top of the stack. The reference still must live
     /// as long as the root of the stack.
+    #[deprecated(note = "use `inject_with_policy` instead")]
     pub fn inject_with(
         &mut self,
         f: impl FnOnce(&mut T) -> Option<&'root Mutex<T>>,
         ignore_poison: bool,
+    ) -> Option<Result<&mut T, TryLockError<()>>> {
+        self.inject_with_policy(f, PoisonPolicy::from_ignore_poison(ignore_poison))
+    }
+
+    /// Inject a new reference to the top of the stack. The reference still must live
+    /// as long as the root of the stack.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    pub fn inject_with_policy(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&'root Mutex<T>>,
+        policy: PoisonPolicy,
     ) -> Option<Result<&mut T, TryLockError<()>>> {
         let old_top: *mut T = self.raw_top_mut();
         let new_top: &Mutex<T> = unsafe { f(&mut *old_top)? };
-        let new_top: *const Mutex<T> = new_top;
-        let guard = unsafe { (*new_top).try_lock() };
-        Some(self.handle_trylock_result(guard, ignore_poison))
+        Some(self.handle_trylock_result(new_top, policy))
+    }
+
+    /// Inject a new reference to the top of the stack like `inject_with_policy`, but block until the
+    /// injected `Mutex` can be locked.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    ///
+    /// With the `deadlock-detection` feature, this panics instead of deadlocking if the `Mutex` is already
+    /// locked by any `MutexGuardStack` on the current thread.
+    pub fn inject_with_blocking(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&'root Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> Option<Result<&mut T, PoisonError<()>>> {
+        let old_top: *mut T = self.raw_top_mut();
+        let new_top: &Mutex<T> = unsafe { f(&mut *old_top)? };
+        Some(self.lock_and_push(new_top, policy))
     }
 
     /// Descend into the recursive data structure, returning a mutable reference to the new top element.
     /// Rust's borrow checker enforces that the closure cannot inject any lifetime (other than `'static`),
     /// because the closure must work for any lifetime `'node`.
+    #[deprecated(note = "use `descend_with_policy` instead")]
     pub fn descend_with(
         &mut self,
         f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node Mutex<T>>,
         ignore_poison: bool,
     ) -> Option<Result<&mut T, TryLockError<()>>> {
+        self.descend_with_policy(f, PoisonPolicy::from_ignore_poison(ignore_poison))
+    }
+
+    /// Descend into the recursive data structure like `descend_with_policy`, but return a `DescendResult`,
+    /// so that not selecting a child and failing to lock it can be matched at the same level.
+    pub fn try_descend(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> DescendResult<'_, T, TryLockError<()>> {
+        DescendResult::from_option(self.descend_with_policy(f, policy))
+    }
+
+    /// Descend into the recursive data structure like `descend_with_policy`, but the closure may fail with its own error.
+    /// Returns `Ok(None)` if the closure did not select a `Mutex`.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    pub fn try_descend_with<E>(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Result<Option<&'node Mutex<T>>, E>,
+        policy: PoisonPolicy,
+    ) -> Result<Option<&mut T>, DescendError<E>> {
+        let old_top: *mut T = self.raw_top_mut();
+        let Some(new_top) = unsafe { f(&mut *old_top) }.map_err(DescendError::User)? else {
+            return Ok(None);
+        };
+        match self.try_push(new_top, policy) {
+            Ok(()) => Ok(Some(self.top_mut())),
+            Err(RawMoveError::Poisoned(_guard, _)) => Err(DescendError::Poisoned),
+            Err(RawMoveError::WouldBlock) => Err(DescendError::WouldBlock),
+            Err(RawMoveError::AscendAtRoot) => unreachable!("locking never ascends"),
+        }
+    }
+
+    /// Descend into the recursive data structure like `descend_with_policy`, but block until the selected `Mutex` can be locked.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    /// Returns None if the closure did not select a `Mutex`.
+    ///
+    /// With the `deadlock-detection` feature, this panics instead of deadlocking if the `Mutex` is already
+    /// locked by any `MutexGuardStack` on the current thread.
+    pub fn descend_with_blocking(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> Option<Result<&mut T, PoisonError<()>>> {
         let old_top: *mut T = self.raw_top_mut();
         let new_top: &Mutex<T> = unsafe { f(&mut *old_top)? };
-        let new_top: *const Mutex<T> = new_top;
-        let guard = unsafe { (*new_top).try_lock() };
-        Some(self.handle_trylock_result(guard, ignore_poison))
+        Some(self.lock_and_push(new_top, policy))
     }
 
-    /// Ascend back up from the recursive data structure, returning a mutable reference to the new top element, if it changed.
-    /// If we are not currently at the root, ascend and return a reference to the new top.
-    /// If we are already at the root, returns None (the top is the root and does not change).
-    pub fn ascend(&mut self) -> Option<&mut T> {
-        match self.data.len() {
-            0 => unreachable!("root pointer must always exist"),
-            1 => None,
-            _ => {
-                self.data.pop();
-                Some(self.top_mut())
+    /// Lock `mutex`, blocking if necessary, and push it onto the stack.
+    fn lock_and_push(
+        &mut self,
+        mutex: *const Mutex<T>,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, PoisonError<()>> {
+        held::check(mutex.cast());
+        self.blocked = false;
+        let guard = unsafe { (*mutex).lock() };
+        match (guard, policy) {
+            (Ok(guard), _) => self.push_level(guard, mutex, false),
+            (Err(_poison), PoisonPolicy::Fail) => return Err(PoisonError::new(())),
+            (Err(poison), _) => {
+                if policy == PoisonPolicy::ClearAndContinue {
+                    unsafe { (*mutex).clear_poison() };
+                }
+                self.push_level(poison.into_inner(), mutex, true);
             }
         }
+        Ok(self.top_mut())
     }
 
-    /// Ascend back up from the recursive data structure while the given closure returns `true`, returning a mutable reference to the new top element.
-    /// If we are not currently at the root, and the predicate returns `true`, ascend and continue.
-    /// If we are already at the root, or if the predicate returned false, returns a reference to the top element.
-    pub fn ascend_while<P>(&mut self, mut predicate: P) -> &mut T
-    where
-        P: FnMut(&mut T) -> bool,
-    {
-        while !self.is_at_root() && predicate(self.top_mut()) {
-            let Some(_) = self.ascend() else {
-                unreachable!();
+    /// Descend into the recursive data structure like `descend_with_policy`, but return a future that
+    /// keeps trying to lock the selected `Mutex` whenever it is polled, yielding to the executor while it is locked elsewhere.
+    /// The closure is called immediately, not when the future is first polled.
+    /// Nothing is pushed until the `Mutex` is locked, so dropping the future leaves the stack where it was.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    ///
+    /// A `Mutex` cannot wake a task when it is unlocked, so while it is locked elsewhere the future wakes itself
+    /// every time it returns `Poll::Pending`. That keeps its task busy polling, at the cost of CPU time.
+    /// To give up after a number of attempts instead, resolving to `TryLockError::WouldBlock`, use `DescendFuture::max_attempts`.
+    pub fn descend_with_async(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> DescendFuture<'_, 'root, T> {
+        let old_top: *mut T = self.raw_top_mut();
+        let new_top: Option<&Mutex<T>> = unsafe { f(&mut *old_top) };
+        DescendFuture {
+            stack: Some(self),
+            new_top: new_top.map(|new_top| new_top as *const Mutex<T>),
+            policy,
+            attempts: 0,
+            max_attempts: None,
+        }
+    }
+
+    /// Descend into the recursive data structure like `descend_with_policy`, retrying according to `retry`
+    /// while the selected `Mutex` is locked elsewhere.
+    /// The closure is called again before every attempt, so it may select a different `Mutex` each time.
+    /// A poisoned `Mutex` is handled according to the stack's default poison policy, and is not retried.
+    /// Returns None if the closure did not select a `Mutex`.
+    pub fn descend_with_retry(
+        &mut self,
+        mut f: impl for<'node> FnMut(&'node mut T) -> Option<&'node Mutex<T>>,
+        retry: RetryPolicy,
+    ) -> Option<Result<&mut T, RetryExhausted>> {
+        let mut sleep = retry.sleep_backoff;
+        let mut attempts = 0;
+        loop {
+            let old_top: *mut T = self.raw_top_mut();
+            let new_top: &Mutex<T> = unsafe { f(&mut *old_top)? };
+            attempts += 1;
+            let poisoned = match self.try_push(new_top, self.policy) {
+                Ok(()) => return Some(Ok(self.top_mut())),
+                Err(RawMoveError::Poisoned(_guard, _)) => true,
+                Err(RawMoveError::WouldBlock) => false,
+                Err(RawMoveError::AscendAtRoot) => unreachable!("locking never ascends"),
+            };
+            if poisoned || attempts >= retry.max_attempts {
+                return Some(Err(RetryExhausted { attempts, poisoned }));
+            }
+            if attempts <= retry.spins_before_yield {
+                std::hint::spin_loop();
+            } else if let Some(duration) = sleep {
+                thread::sleep(duration);
+                sleep = Some(duration.saturating_mul(2));
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+
+    /// Descend into the recursive data structure like `descend_with_policy`, additionally passing the closure
+    /// information about the current position.
+    pub fn descend_with_ctx(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T, DescendCtx) -> Option<&'node Mutex<T>>,
+        policy: PoisonPolicy,
+    ) -> Option<Result<&mut T, TryLockError<()>>> {
+        let ctx = self.ctx();
+        self.descend_with_policy(|node| f(node, ctx), policy)
+    }
+
+    /// Descend repeatedly like `descend_with_policy`, until the closure selects no `Mutex`, the selected `Mutex`
+    /// cannot be locked, or `max_levels` levels have been descended.
+    /// Levels that were successfully descended stay on the stack; nothing is pushed for the step that stopped the chain.
+    pub fn descend_chain(
+        &mut self,
+        mut f: impl for<'node> FnMut(&'node mut T) -> Option<&'node Mutex<T>>,
+        policy: PoisonPolicy,
+        max_levels: usize,
+    ) -> ChainOutcome {
+        let mut descended = 0;
+        let stop = loop {
+            if descended == max_levels {
+                break ChainStop::DepthLimit;
+            }
+            let old_top: *mut T = self.raw_top_mut();
+            let Some(new_top) = (unsafe { f(&mut *old_top) }) else {
+                break ChainStop::NoChild;
             };
+            match self.try_push(new_top, policy) {
+                Ok(()) => descended += 1,
+                Err(RawMoveError::Poisoned(_guard, _)) => break ChainStop::Poisoned,
+                Err(RawMoveError::WouldBlock) => break ChainStop::WouldBlock,
+                Err(RawMoveError::AscendAtRoot) => unreachable!("locking never ascends"),
+            }
+        };
+        ChainOutcome { descended, stop }
+    }
+
+    /// Check whether the `Mutex` selected by the closure could currently be locked, without descending into it.
+    /// The lock is attempted and immediately released, so the answer may be stale by the time it is acted on.
+    /// A poisoned `Mutex` is still considered lockable.
+    /// Returns None if the closure did not select a `Mutex`.
+    pub fn peek_lockable(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node Mutex<T>>,
+    ) -> Option<bool> {
+        let next = f(self.top_mut())?;
+        match next.try_lock() {
+            Ok(_guard) => Some(true),
+            Err(TryLockError::Poisoned(_guard)) => Some(true),
+            Err(TryLockError::WouldBlock) => Some(false),
+        }
+    }
+
+    /// Check whether the `Mutex` selected by the closure could currently be locked, like `peek_lockable`,
+    /// but only needing a shared borrow of the stack.
+    /// The lock is attempted and immediately released. This is advisory only: another thread may lock or unlock
+    /// the `Mutex` between this check and a descend into it. A poisoned `Mutex` is still considered free.
+    pub fn probe_with(
+        &self,
+        f: impl for<'node> FnOnce(&'node T) -> Option<&'node Mutex<T>>,
+    ) -> ProbeResult {
+        match f(self.top()) {
+            None => ProbeResult::NoChild,
+            Some(next) => match next.try_lock() {
+                Ok(_guard) => ProbeResult::Free,
+                Err(TryLockError::Poisoned(_guard)) => ProbeResult::Free,
+                Err(TryLockError::WouldBlock) => ProbeResult::WouldConflict,
+            },
         }
-        self.top_mut()
     }
 
     /// Ascend from, descend from, inject a new stack top, or stay at the current node,
     /// based on the return value of the closure.
-    pub fn move_with<F>(&mut self, f: F, ignore_poison: bool) -> Result<&mut T, MoveError>
+    #[deprecated(note = "use `move_with_policy` instead")]
+    pub fn move_with<F>(
+        &mut self,
+        f: F,
+        ignore_poison: bool,
+    ) -> Result<&mut T, MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> MoveDecision<'root, 'a, T>,
+    {
+        self.move_with_policy(f, PoisonPolicy::from_ignore_poison(ignore_poison))
+    }
+
+    /// Ascend from, descend from, inject a new stack top, or stay at the current node,
+    /// based on the return value of the closure.
+    /// A poisoned `Mutex` is handled according to `policy`.
+    pub fn move_with_policy<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> MoveDecision<'root, 'a, T>,
+    {
+        match self.move_with_raw(f, policy) {
+            Ok(()) => Ok(self.top_mut()),
+            Err(err) => Err(err.attach(self)),
+        }
+    }
+
+    /// Repeatedly move based on the return value of the closure, like `move_with_policy`, until it returns `MoveDecision::Finish`.
+    /// Returns the value it finished with, or None if it asked to ascend at the root.
+    /// If a selected `Mutex` cannot be locked, stops with the error, and the stack stays where it was
+    /// (except that a poisoned `Mutex` is handled according to `policy`).
+    pub fn drive<R, F>(
+        &mut self,
+        mut f: F,
+        policy: PoisonPolicy,
+    ) -> Result<Option<R>, MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnMut(&'a mut T) -> MoveDecision<'root, 'a, T, R>,
+    {
+        loop {
+            let old_top: *mut T = self.raw_top_mut();
+            let decision = match unsafe { f(&mut *old_top) } {
+                MoveDecision::Finish(result) => return Ok(Some(result)),
+                MoveDecision::Ascend => {
+                    if self.ascend().is_none() {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                MoveDecision::Stay => MoveDecision::Stay,
+                MoveDecision::Descend(new_top) => MoveDecision::Descend(new_top),
+                MoveDecision::Inject(new_top) => MoveDecision::Inject(new_top),
+                MoveDecision::AscendWhile(predicate) => MoveDecision::AscendWhile(predicate),
+            };
+            if let Err(err) = self.apply_decision(decision, policy) {
+                return Err(err.attach(self));
+            }
+        }
+    }
+
+    /// Helper function for `move_with_policy`, whose error does not borrow the stack.
+    pub(crate) fn move_with_raw<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<(), RawMoveError<'root, T>>
     where
         F: for<'a> FnOnce(&'a mut T) -> MoveDecision<'root, 'a, T>,
     {
         let old_top: *mut T = self.raw_top_mut();
         let result = unsafe { f(&mut *old_top) };
-        match result {
-            MoveDecision::Ascend => self.ascend().ok_or(MoveError::AscendAtRoot),
-            MoveDecision::Stay => Ok(self.top_mut()),
+        self.apply_decision(result, policy)
+    }
+
+    /// Carry out the decision of a `move_with*` closure.
+    fn apply_decision(
+        &mut self,
+        decision: MoveDecision<'root, '_, T>,
+        policy: PoisonPolicy,
+    ) -> Result<(), RawMoveError<'root, T>> {
+        match decision {
+            MoveDecision::Ascend => match self.ascend() {
+                Some(_) => Ok(()),
+                None => Err(RawMoveError::AscendAtRoot),
+            },
+            MoveDecision::Stay | MoveDecision::Finish(()) => Ok(()),
+            MoveDecision::AscendWhile(predicate) => {
+                self.ascend_while(predicate);
+                Ok(())
+            }
             MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
-                let new_top: *const Mutex<T> = new_top;
-                let guard = unsafe { (*new_top).try_lock() };
-                self.handle_move_trylock_result(guard, ignore_poison)
+                self.try_push(new_top, policy)
             }
         }
     }
 
+    /// Like `move_with_policy`, additionally passing the closure information about the current position.
+    pub fn move_with_ctx<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnOnce(&'a mut T, DescendCtx) -> MoveDecision<'root, 'a, T>,
+    {
+        let ctx = self.ctx();
+        self.move_with_policy(|node| f(node, ctx), policy)
+    }
+
+    #[deprecated(note = "use `move_with_async_policy` instead")]
     pub async fn move_with_async<F>(
         &mut self,
         f: F,
         ignore_poison: bool,
-    ) -> Result<&mut T, MoveError>
+    ) -> Result<&mut T, MoveError<'_, 'root, T>>
     where
         F: for<'a> FnOnce(
             &'a mut T,
         )
             -> Pin<Box<dyn Future<Output = MoveDecision<'root, 'a, T>> + 'a>>,
+    {
+        self.move_with_async_policy(f, PoisonPolicy::from_ignore_poison(ignore_poison))
+            .await
+    }
+
+    /// Like `move_with_policy`, but the closure returns a boxed future.
+    /// To pass an async closure instead, without boxing its future, use `move_with_async_fn`.
+    pub async fn move_with_async_policy<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, MoveError<'_, 'root, T>>
+    where
+        F: for<'a> FnOnce(
+            &'a mut T,
+        )
+            -> Pin<Box<dyn Future<Output = MoveDecision<'root, 'a, T>> + 'a>>,
+    {
+        self.move_with_async_unboxed(f, policy).await
+    }
+
+    /// Like `move_with_async_policy`, but the future returned by `f` does not need to be boxed.
+    /// See `AsyncMover`.
+    pub async fn move_with_async_unboxed<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, MoveError<'_, 'root, T>>
+    where
+        F: for<'a> AsyncMover<'a, 'root, T>,
+    {
+        match self.move_with_async_raw(f, policy).await {
+            Ok(()) => Ok(self.top_mut()),
+            Err(err) => Err(err.attach(self)),
+        }
+    }
+
+    /// Like `move_with_async_policy`, but takes an async closure, whose future does not need to be boxed.
+    /// See `AsyncMove`.
+    pub async fn move_with_async_fn<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<&mut T, MoveError<'_, 'root, T>>
+    where
+        F: AsyncMove<T, MoveDecision<'root, 'root, T>>,
     {
         let old_top: *mut T = self.raw_top_mut();
         let result = unsafe { f(&mut *old_top) }.await;
-        match result {
-            MoveDecision::Ascend => self.ascend().ok_or(MoveError::AscendAtRoot),
-            MoveDecision::Stay => Ok(self.top_mut()),
-            MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
-                let new_top: *const Mutex<T> = new_top;
-                let guard = unsafe { (*new_top).try_lock() };
-                self.handle_move_trylock_result(guard, ignore_poison)
-            }
+        match self.apply_decision(result, policy) {
+            Ok(()) => Ok(self.top_mut()),
+            Err(err) => Err(err.attach(self)),
         }
     }
 
+    /// Helper function for `move_with_async_unboxed`, whose error does not borrow the stack.
+    pub(crate) async fn move_with_async_raw<F>(
+        &mut self,
+        f: F,
+        policy: PoisonPolicy,
+    ) -> Result<(), RawMoveError<'root, T>>
+    where
+        F: for<'a> AsyncMover<'a, 'root, T>,
+    {
+        let old_top: *mut T = self.raw_top_mut();
+        let result = unsafe { f.call(&mut *old_top) }.await;
+        self.apply_decision(result, policy)
+    }
+
     /// Return reference to the top element of this stack, forgetting about the stack entirely.
     /// Note that this leaks all `MutexGuard`s above the top.
-    pub fn into_top(mut self) -> MutexGuard<'root, T> {
-        let ret = self.data.pop().unwrap();
+    #[deprecated(note = "use `scoped_top`, `into_root`, or `into_top_leaking` instead")]
+    pub fn into_top(self) -> MutexGuard<'root, T> {
+        self.into_top_leaking()
+    }
+
+    /// Obtain a guard that derefs to the top of the stack.
+    /// Unlike `into_top_leaking`, this only borrows the stack, so no parent `MutexGuard` can be leaked.
+    pub fn scoped_top(&mut self) -> TopGuard<'_, 'root, T> {
+        TopGuard { stack: self }
+    }
+
+    /// Return reference to the top element of this stack, forgetting about the stack entirely.
+    /// Note that this leaks all `MutexGuard`s above the top.
+    pub fn into_top_leaking(mut self) -> MutexGuard<'root, T> {
+        let ret = self.data.pop().unwrap().into_guard();
         unsafe {
             // We need to not drop the parent MutexGuards, if any
             self.data.set_len(0);
@@ -232,23 +1041,848 @@ impl<'root, T: ?Sized> MutexGuardStack<'root, T> {
         ret
     }
 
-    /// Pop all `MutexGuard`s off the stack and go back to the root.
-    pub fn to_root(&mut self) -> &mut T {
-        for _ in 1..self.data.len() {
-            // We need to drop the MutexGuard's in the reverse order.
-            // Vec::truncate does not specify drop order, but it's probably wrong anyway.
-            self.data.pop();
+    /// Pop every `MutexGuard` but the root's off the stack, passing each one to the closure in pop order
+    /// (starting with the current top), and return how many were popped.
+    /// The closure must work for any lifetime `'a`, so it cannot keep the guards: the stack still holds the root,
+    /// through which the `Mutex` of a popped guard could be dropped.
+    pub fn drain_to_root_with(&mut self, mut f: impl for<'a> FnMut(MutexGuard<'a, T>)) -> usize {
+        let depth = self.depth();
+        while self.data.len() > 1 {
+            let (guard, owned) = self.data.pop().unwrap().into_parts();
+            f(guard);
+            drop(owned);
+        }
+        depth
+    }
+}
+
+impl<'root, T> MutexGuardStack<'root, T> {
+    /// Wait on `condvar` with the guard of the top of the stack, like `Condvar::wait`,
+    /// and put the guard back at the top once woken. The guards of the ancestors stay locked throughout.
+    /// If the `Mutex` was poisoned while waiting, the guard is still put back, and `PoisonError` is returned.
+    ///
+    /// The top stays on the stack even if `Condvar::wait` panics (e.g. because `condvar` is also used with another `Mutex`):
+    /// its `Mutex` is locked again before the panic continues, and the level is marked as poisoned.
+    /// If the top's `Mutex` is unknown, because the root was adopted with `from_guard`, the process is aborted instead.
+    pub fn wait_top(&mut self, condvar: &Condvar) -> Result<&mut T, PoisonError<()>> {
+        /// Restores the top level if `Condvar::wait` panics, after the guard moved out of the level was dropped.
+        struct Relock<'root, T> {
+            stack: *mut MutexGuardStack<'root, T>,
+        }
+        impl<T> Drop for Relock<'_, T> {
+            fn drop(&mut self) {
+                // SAFETY: The stack is not otherwise used until `Condvar::wait` returns.
+                let stack = unsafe { &mut *self.stack };
+                let level = stack.data.last_mut().unwrap();
+                let Some(mutex) = level.lock else {
+                    // The level's guard was dropped, and there is no way to replace it.
+                    std::process::abort();
+                };
+                let guard = unsafe { (*mutex).lock() }.unwrap_or_else(PoisonError::into_inner);
+                // SAFETY: The bitwise copy in the level was moved out and dropped, so it must not be dropped again.
+                unsafe { ptr::write(&mut level.guard, guard) };
+                level.poisoned = true;
+                stack.poisoned = true;
+            }
+        }
+
+        // SAFETY: The level keeps a bitwise copy of the guard, which is overwritten before the level is used again,
+        // either below or by `Relock` if `Condvar::wait` panics.
+        let guard = unsafe { ptr::read(&self.data.last().unwrap().guard) };
+        let relock = Relock { stack: self };
+        let result = condvar.wait(guard);
+        mem::forget(relock);
+        let (guard, poisoned) = match result {
+            Ok(guard) => (guard, false),
+            Err(poison) => (poison.into_inner(), true),
+        };
+        let level = self.data.last_mut().unwrap();
+        // SAFETY: The bitwise copy in the level was moved into `Condvar::wait`, which gave it back here.
+        unsafe { ptr::write(&mut level.guard, guard) };
+        level.poisoned |= poisoned;
+        self.poisoned |= poisoned;
+        if poisoned {
+            Err(PoisonError::new(()))
+        } else {
+            Ok(self.top_mut())
+        }
+    }
+
+    /// Like `wait_top`, but keep waiting while `condition` returns `true`, like `Condvar::wait_while`.
+    /// The condition is called while the guard is on the stack, so if it panics, the stack is unchanged.
+    pub fn wait_top_while(
+        &mut self,
+        condvar: &Condvar,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> Result<&mut T, PoisonError<()>> {
+        while condition(self.top_mut()) {
+            self.wait_top(condvar)?;
+        }
+        Ok(self.top_mut())
+    }
+}
+
+/// The future returned by `MutexGuardStack::descend_with_async`.
+/// It resolves to None if the closure did not select a `Mutex`.
+pub struct DescendFuture<'a, 'root, T: ?Sized> {
+    /// The stack to push onto, until the future completes.
+    stack: Option<&'a mut MutexGuardStack<'root, T>>,
+    /// The `Mutex` selected by the closure, which is borrowed from the top of the stack.
+    new_top: Option<*const Mutex<T>>,
+    policy: PoisonPolicy,
+    /// How many times the future has tried to lock the `Mutex`.
+    attempts: usize,
+    max_attempts: Option<usize>,
+}
+
+impl<'a, 'root, T: ?Sized> DescendFuture<'a, 'root, T> {
+    /// Give up after `max_attempts` attempts to lock the selected `Mutex`, each made by one poll,
+    /// and resolve to `TryLockError::WouldBlock` instead of polling until it is unlocked.
+    /// A `max_attempts` of 0 is treated as 1.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+}
+
+impl<'a, 'root, T: ?Sized> Future for DescendFuture<'a, 'root, T> {
+    type Output = Option<Result<&'a mut T, TryLockError<()>>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let stack = this
+            .stack
+            .take()
+            .expect("DescendFuture polled after completion");
+        let Some(new_top) = this.new_top else {
+            return Poll::Ready(None);
+        };
+        this.attempts += 1;
+        match stack.try_push(new_top, this.policy) {
+            Ok(()) => Poll::Ready(Some(Ok(stack.top_mut()))),
+            Err(RawMoveError::Poisoned(_guard, _)) => {
+                Poll::Ready(Some(Err(TryLockError::Poisoned(PoisonError::new(())))))
+            }
+            Err(RawMoveError::WouldBlock) => {
+                if this.max_attempts.is_some_and(|max| this.attempts >= max) {
+                    return Poll::Ready(Some(Err(TryLockError::WouldBlock)));
+                }
+                this.stack = Some(stack);
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(RawMoveError::AscendAtRoot) => unreachable!("locking never ascends"),
         }
-        self.top_mut()
     }
 }
 
-impl<'root, T: ?Sized> Drop for MutexGuardStack<'root, T> {
-    fn drop(&mut self) {
-        for _ in 0..self.data.len() {
-            // We need to drop the MutexGuard's in the reverse order.
-            // Vec::truncate does not specify drop order, but it's probably wrong anyway.
-            self.data.pop();
+/// A borrow of the top of a `MutexGuardStack`, returned by `MutexGuardStack::scoped_top`.
+/// Dropping it returns control to the stack, with every level still held.
+pub struct TopGuard<'a, 'root, T: ?Sized> {
+    stack: &'a mut MutexGuardStack<'root, T>,
+}
+
+impl<T: ?Sized> Deref for TopGuard<'_, '_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.stack.top()
+    }
+}
+
+impl<T: ?Sized> DerefMut for TopGuard<'_, '_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.stack.top_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Node {
+        value: u32,
+        next: Option<Arc<Mutex<Node>>>,
+    }
+
+    /// A list of `len` nodes with values `0..len`, returning every node from the head.
+    fn list(len: u32) -> Vec<Arc<Mutex<Node>>> {
+        let mut nodes: Vec<Arc<Mutex<Node>>> = vec![];
+        for value in (0..len).rev() {
+            let next = nodes.last().cloned();
+            nodes.push(Arc::new(Mutex::new(Node { value, next })));
+        }
+        nodes.reverse();
+        nodes
+    }
+
+    fn descend(stack: &mut MutexGuardStack<'_, Node>) -> Option<u32> {
+        let top = stack.descend_with_policy(|node| node.next.as_deref(), PoisonPolicy::Fail)?;
+        Some(top.unwrap().value)
+    }
+
+    #[test]
+    #[cfg(feature = "deadlock-detection")]
+    #[should_panic(expected = "deadlock")]
+    fn new_blocking_detects_deadlock() {
+        let nodes = list(1);
+        let _held = MutexGuardStack::new(&nodes[0]).unwrap();
+        let _ = MutexGuardStack::new_blocking(&nodes[0]);
+    }
+
+    #[test]
+    #[cfg(feature = "deadlock-detection")]
+    #[should_panic(expected = "deadlock")]
+    fn new_recover_detects_deadlock() {
+        let nodes = list(1);
+        let _held = MutexGuardStack::new(&nodes[0]).unwrap();
+        let _ = MutexGuardStack::new_recover(&nodes[0]);
+    }
+
+    #[test]
+    #[cfg(feature = "deadlock-detection")]
+    #[should_panic(expected = "deadlock")]
+    fn new_blocking_ignore_poison_detects_deadlock() {
+        let nodes = list(2);
+        let mut held = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend(&mut held).unwrap();
+        let _ = MutexGuardStack::new_blocking_ignore_poison(&nodes[1]);
+    }
+
+    #[test]
+    #[cfg(feature = "deadlock-detection")]
+    #[should_panic(expected = "deadlock")]
+    fn descend_with_blocking_detects_deadlock() {
+        let nodes = list(2);
+        let _held = MutexGuardStack::new(&nodes[1]).unwrap();
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let _ = stack.descend_with_blocking(|node| node.next.as_deref(), PoisonPolicy::Fail);
+    }
+
+    #[test]
+    fn blocking_after_release() {
+        let nodes = list(2);
+        let mut held = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend(&mut held).unwrap();
+        held.ascend().unwrap();
+        let other = MutexGuardStack::new_blocking(&nodes[1]).unwrap();
+        assert_eq!(other.top().value, 1);
+        drop(other);
+        drop(held);
+        let mut stack = MutexGuardStack::new_blocking(&nodes[0]).unwrap();
+        let top = stack.descend_with_blocking(|node| node.next.as_deref(), PoisonPolicy::Fail);
+        assert_eq!(top.unwrap().unwrap().value, 1);
+    }
+
+    fn descend_all(stack: &mut MutexGuardStack<'_, Node>) {
+        while descend(stack).is_some() {}
+    }
+
+    #[test]
+    fn into_root_unlocks_every_child() {
+        let nodes = list(4);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        assert_eq!(stack.depth(), 3);
+        assert!(nodes.iter().all(|node| node.try_lock().is_err()));
+        let guard = stack.into_root();
+        assert_eq!(guard.value, 0);
+        assert!(nodes[0].try_lock().is_err());
+        for node in &nodes[1..] {
+            drop(node.try_lock().unwrap());
         }
+        drop(guard);
+        drop(nodes[0].try_lock().unwrap());
+    }
+
+    /// A node that records its id in a shared log when it is dropped.
+    #[derive(Debug)]
+    struct Logged {
+        id: usize,
+        log: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Drop for Logged {
+        fn drop(&mut self) {
+            self.log.lock().unwrap().push(self.id);
+        }
+    }
+
+    fn logged_root() -> (Mutex<Logged>, Arc<Mutex<Vec<usize>>>) {
+        let log = Arc::default();
+        let root = Mutex::new(Logged {
+            id: 0,
+            log: Arc::clone(&log),
+        });
+        (root, log)
+    }
+
+    /// Inject the levels `1..=depth` as owned nodes, so each node is dropped as soon as its level is popped.
+    fn inject_logged(stack: &mut MutexGuardStack<'_, Logged>, depth: usize) {
+        for id in 1..=depth {
+            let log = Arc::clone(&stack.top().log);
+            let node = Arc::new(Mutex::new(Logged { id, log }));
+            stack.inject_owned(node, PoisonPolicy::Fail).unwrap();
+        }
+    }
+
+    #[test]
+    fn to_depth_unlocks_from_the_top_down() {
+        let (root, log) = logged_root();
+        let mut stack = MutexGuardStack::new(&root).unwrap();
+        inject_logged(&mut stack, 5);
+        assert_eq!(stack.depth(), 5);
+        assert_eq!(
+            stack.to_depth(6).unwrap_err(),
+            ToDepthError {
+                target: 6,
+                depth: 5
+            }
+        );
+        assert_eq!(stack.to_depth(2).unwrap().id, 2);
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(*log.lock().unwrap(), [5, 4, 3]);
+        assert_eq!(stack.to_depth(2).unwrap().id, 2);
+        assert_eq!(*log.lock().unwrap(), [5, 4, 3]);
+        stack.to_depth(0).unwrap();
+        assert_eq!(*log.lock().unwrap(), [5, 4, 3, 2, 1]);
+        assert!(stack.is_at_root());
+    }
+
+    #[test]
+    fn ascend_n_unlocks_from_the_top_down() {
+        let (root, log) = logged_root();
+        let mut stack = MutexGuardStack::new(&root).unwrap();
+        inject_logged(&mut stack, 4);
+        assert!(stack.ascend_n(5).is_none());
+        assert_eq!(stack.depth(), 4);
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(stack.ascend_n(0).unwrap().id, 4);
+        assert_eq!(stack.ascend_n(3).unwrap().id, 1);
+        assert_eq!(*log.lock().unwrap(), [4, 3, 2]);
+        assert_eq!(stack.ascend_n(1).unwrap().id, 0);
+        assert_eq!(*log.lock().unwrap(), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn to_depth_unlocks_children() {
+        let nodes = list(4);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        assert_eq!(stack.to_depth(1).unwrap().value, 1);
+        assert!(nodes[..2].iter().all(|node| node.try_lock().is_err()));
+        for node in &nodes[2..] {
+            drop(node.try_lock().unwrap());
+        }
+    }
+
+    #[test]
+    fn ids_are_mutex_addresses() {
+        let nodes = list(3);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        let addresses: Vec<*const Mutex<Node>> =
+            nodes.iter().map(|node| &**node as *const _).collect();
+        assert_eq!(stack.top_id().unwrap().as_ptr(), addresses[2]);
+        let path: Vec<_> = stack.path_ids().map(|id| id.unwrap().as_ptr()).collect();
+        assert_eq!(path, addresses);
+        let middle = stack.path_ids().nth(1).unwrap().unwrap();
+        assert!(!stack.is_at(middle));
+        stack.ascend().unwrap();
+        assert!(stack.is_at(middle));
+        drop(stack);
+
+        let stack = MutexGuardStack::from_guard(nodes[0].lock().unwrap());
+        assert_eq!(stack.top_id(), None);
+        assert_eq!(stack.path_ids().collect::<Vec<_>>(), [None]);
+    }
+
+    fn assert_sync<T: Sync>() {}
+
+    #[test]
+    fn auto_traits() {
+        assert_sync::<MutexGuardStack<'_, Node>>();
+        assert_sync::<MutexGuardStack<'_, [u8]>>();
+    }
+
+    #[test]
+    fn share_with_another_thread() {
+        let nodes = list(3);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend(&mut stack).unwrap();
+        let stack = &stack;
+        let value = std::thread::scope(|scope| scope.spawn(|| stack.top().value).join().unwrap());
+        assert_eq!(value, 1);
+    }
+
+    /// A waker that counts how many times it was woken.
+    #[derive(Default)]
+    struct CountWakes(std::sync::atomic::AtomicUsize);
+
+    impl std::task::Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn descend_with_async_gives_up_after_max_attempts() {
+        let nodes = list(2);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let held = nodes[1].lock().unwrap();
+        let wakes = Arc::new(CountWakes::default());
+        let waker = Arc::clone(&wakes).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(stack
+            .descend_with_async(|node| node.next.as_deref(), PoisonPolicy::Fail)
+            .max_attempts(3));
+        let mut polls = 0;
+        let result = loop {
+            polls += 1;
+            if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+                break result;
+            }
+        };
+        assert!(matches!(result, Some(Err(TryLockError::WouldBlock))));
+        assert_eq!(polls, 3);
+        assert_eq!(wakes.0.load(std::sync::atomic::Ordering::Relaxed), 2);
+        drop(held);
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn descend_with_async_locks_once_unlocked() {
+        let nodes = list(2);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let held = nodes[1].lock().unwrap();
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let mut future = std::pin::pin!(
+            stack.descend_with_async(|node| node.next.as_deref(), PoisonPolicy::Fail)
+        );
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        drop(held);
+        let Poll::Ready(Some(Ok(top))) = future.as_mut().poll(&mut cx) else {
+            panic!("the Mutex is unlocked");
+        };
+        assert_eq!(top.value, 1);
+    }
+
+    #[test]
+    fn wait_top_at_the_root() {
+        let nodes = list(1);
+        let condvar = Condvar::new();
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                // This can only lock the root while the stack is waiting.
+                nodes[0].lock().unwrap().value = 10;
+                condvar.notify_all();
+            });
+            // Wait again after a spurious wakeup.
+            while stack.wait_top(&condvar).unwrap().value != 10 {}
+        });
+        assert!(stack.is_at_root());
+        assert!(!stack.top_was_poisoned());
+        assert!(nodes[0].try_lock().is_err());
+    }
+
+    #[test]
+    fn wait_top_while_is_woken_by_another_thread() {
+        let nodes = list(2);
+        let condvar = Condvar::new();
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend(&mut stack).unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                nodes[1].lock().unwrap().value = 10;
+                condvar.notify_all();
+            });
+            let top = stack.wait_top_while(&condvar, |node| node.value == 1);
+            assert_eq!(top.unwrap().value, 10);
+        });
+        assert_eq!(stack.depth(), 1);
+        assert!(nodes.iter().all(|node| node.try_lock().is_err()));
+    }
+
+    #[test]
+    fn wait_top_while_poisoned_while_waiting() {
+        let nodes = list(2);
+        let condvar = Condvar::new();
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend(&mut stack).unwrap();
+        std::thread::scope(|scope| {
+            let notifier = scope.spawn(|| {
+                let mut guard = nodes[1].lock().unwrap();
+                guard.value = 10;
+                condvar.notify_all();
+                panic!("poison the Mutex");
+            });
+            let top = stack.wait_top_while(&condvar, |node| node.value == 1);
+            assert!(top.is_err());
+            assert!(notifier.join().is_err());
+        });
+        assert_eq!(stack.depth(), 1);
+        assert!(stack.top_was_poisoned());
+        assert!(stack.is_poisoned());
+        assert_eq!(stack.top().value, 10);
+        assert!(nodes[1].try_lock().is_err());
+    }
+
+    #[test]
+    fn wait_top_while_condition_panics() {
+        let nodes = list(2);
+        let condvar = Condvar::new();
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend(&mut stack).unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = stack.wait_top_while(&condvar, |_| panic!("condition panicked"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(stack.depth(), 1);
+        assert!(!stack.top_was_poisoned());
+        assert!(nodes[1].try_lock().is_err());
+        assert_eq!(stack.ascend().unwrap().value, 0);
+    }
+
+    /// Poison `node` by panicking in another thread while its `Mutex` is locked.
+    fn poison(node: &Mutex<Node>) {
+        let result = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _guard = node.lock();
+                    panic!("poison the Mutex");
+                })
+                .join()
+        });
+        assert!(result.is_err());
+        assert!(node.is_poisoned());
+    }
+
+    #[test]
+    fn descend_poisoned_with_fail() {
+        let nodes = list(2);
+        poison(&nodes[1]);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let top = stack.descend_with_policy(|node| node.next.as_deref(), PoisonPolicy::Fail);
+        assert!(matches!(top, Some(Err(TryLockError::Poisoned(_)))));
+        assert!(stack.is_at_root());
+        assert!(!stack.is_poisoned());
+        // The guard was released, and the poison is left alone.
+        assert!(matches!(
+            nodes[1].try_lock(),
+            Err(TryLockError::Poisoned(_))
+        ));
+    }
+
+    #[test]
+    fn descend_poisoned_with_ignore_and_continue() {
+        let nodes = list(2);
+        poison(&nodes[1]);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let top =
+            stack.descend_with_policy(|node| node.next.as_deref(), PoisonPolicy::IgnoreAndContinue);
+        assert_eq!(top.unwrap().unwrap().value, 1);
+        assert!(stack.is_poisoned());
+        assert!(nodes[1].is_poisoned());
+        stack.ascend().unwrap();
+        assert!(nodes[1].is_poisoned());
+    }
+
+    #[test]
+    fn descend_poisoned_with_clear_and_continue() {
+        let nodes = list(2);
+        poison(&nodes[1]);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let top =
+            stack.descend_with_policy(|node| node.next.as_deref(), PoisonPolicy::ClearAndContinue);
+        assert_eq!(top.unwrap().unwrap().value, 1);
+        // The stack still remembers that the guard was recovered from poison.
+        assert!(stack.is_poisoned());
+        assert!(!nodes[1].is_poisoned());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_ignore_poison() {
+        let nodes = list(2);
+        poison(&nodes[1]);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let top = stack.descend_with(|node| node.next.as_deref(), false);
+        assert!(matches!(top, Some(Err(TryLockError::Poisoned(_)))));
+        let top = stack.descend_with(|node| node.next.as_deref(), true);
+        assert_eq!(top.unwrap().unwrap().value, 1);
+        assert!(nodes[1].is_poisoned());
+    }
+
+    #[test]
+    fn with_policy_poisoned_root() {
+        let nodes = list(1);
+        poison(&nodes[0]);
+        let Err(TryLockError::Poisoned(poison)) =
+            MutexGuardStack::with_policy(&nodes[0], PoisonPolicy::Fail)
+        else {
+            panic!("the root is poisoned");
+        };
+        let stack = poison.into_inner();
+        assert!(stack.is_poisoned());
+        drop(stack);
+
+        let stack =
+            MutexGuardStack::with_policy(&nodes[0], PoisonPolicy::IgnoreAndContinue).unwrap();
+        assert_eq!(stack.poison_policy(), PoisonPolicy::IgnoreAndContinue);
+        assert!(stack.is_poisoned());
+        drop(stack);
+        assert!(nodes[0].is_poisoned());
+
+        let stack =
+            MutexGuardStack::with_policy(&nodes[0], PoisonPolicy::ClearAndContinue).unwrap();
+        assert_eq!(stack.poison_policy(), PoisonPolicy::ClearAndContinue);
+        assert!(stack.is_poisoned());
+        assert!(!nodes[0].is_poisoned());
+    }
+
+    #[test]
+    fn descend_with_retry_uses_the_default_policy() {
+        let nodes = list(2);
+        poison(&nodes[1]);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let top = stack.descend_with_retry(|node| node.next.as_deref(), RetryPolicy::default());
+        assert_eq!(
+            top.unwrap().unwrap_err(),
+            RetryExhausted {
+                attempts: 1,
+                poisoned: true,
+            }
+        );
+        stack.set_poison_policy(PoisonPolicy::IgnoreAndContinue);
+        let top = stack.descend_with_retry(|node| node.next.as_deref(), RetryPolicy::default());
+        assert_eq!(top.unwrap().unwrap().value, 1);
+    }
+
+    #[test]
+    fn move_with_policy_poisoned() {
+        let nodes = list(2);
+        poison(&nodes[1]);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let err = stack
+            .move_with_policy(
+                |node| MoveDecision::Descend(node.next.as_deref().unwrap()),
+                PoisonPolicy::Fail,
+            )
+            .unwrap_err();
+        assert!(err.is_poisoned());
+        let top = err.into_recover_top().unwrap().clear_and_push();
+        assert_eq!(top.value, 1);
+        assert!(stack.top_was_poisoned());
+        assert!(!nodes[1].is_poisoned());
+        stack.ascend().unwrap();
+
+        poison(&nodes[1]);
+        let top = stack.move_with_policy(
+            |node| MoveDecision::Descend(node.next.as_deref().unwrap()),
+            PoisonPolicy::IgnoreAndContinue,
+        );
+        assert_eq!(top.unwrap().value, 1);
+        assert!(nodes[1].is_poisoned());
+    }
+
+    #[test]
+    fn constructors_with_a_poisoned_root() {
+        let nodes = list(2);
+        poison(&nodes[0]);
+
+        let Err(TryLockError::Poisoned(poison)) = MutexGuardStack::new(&nodes[0]) else {
+            panic!("the root is poisoned");
+        };
+        assert!(poison.into_inner().top_was_poisoned());
+
+        let poison = MutexGuardStack::new_blocking(&nodes[0]).unwrap_err();
+        let mut stack = poison.into_inner();
+        assert!(stack.top_was_poisoned());
+        assert_eq!(descend(&mut stack), Some(1));
+        drop(stack);
+
+        let stack = MutexGuardStack::new_ignore_poison(&nodes[0]).unwrap();
+        assert!(stack.top_was_poisoned());
+        drop(stack);
+
+        let mut stack = MutexGuardStack::new_blocking_ignore_poison(&nodes[0]);
+        assert!(stack.top_was_poisoned());
+        assert_eq!(descend(&mut stack), Some(1));
+        assert!(!stack.top_was_poisoned());
+        drop(stack);
+
+        // None of them clear the poison.
+        assert!(nodes[0].is_poisoned());
+    }
+
+    #[test]
+    fn constructors_with_a_locked_root() {
+        let nodes = list(1);
+        let held = nodes[0].lock().unwrap();
+        assert!(matches!(
+            MutexGuardStack::new(&nodes[0]),
+            Err(TryLockError::WouldBlock)
+        ));
+        assert_eq!(
+            MutexGuardStack::new_ignore_poison(&nodes[0]).unwrap_err(),
+            WouldBlock
+        );
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| {
+                MutexGuardStack::new_blocking(&nodes[0])
+                    .unwrap()
+                    .top()
+                    .value
+            });
+            drop(held);
+            assert_eq!(waiter.join().unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn poison_per_level() {
+        let nodes = list(4);
+        poison(&nodes[1]);
+        poison(&nodes[3]);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        while let Some(top) =
+            stack.descend_with_policy(|node| node.next.as_deref(), PoisonPolicy::IgnoreAndContinue)
+        {
+            top.unwrap();
+        }
+        assert_eq!(stack.depth(), 3);
+        assert!(stack.top_was_poisoned());
+        assert!(stack.any_poisoned());
+        assert_eq!(stack.poisoned_depths().collect::<Vec<_>>(), [1, 3]);
+
+        assert!(stack.clear_poison_top());
+        assert!(!nodes[3].is_poisoned());
+        assert!(nodes[1].is_poisoned());
+        // The level still remembers that its guard was recovered from poison.
+        assert!(stack.top_was_poisoned());
+
+        stack.ascend().unwrap();
+        assert!(!stack.top_was_poisoned());
+        assert_eq!(stack.poisoned_depths().collect::<Vec<_>>(), [1]);
+        stack.ascend().unwrap();
+        assert!(stack.clear_poison_top());
+        assert!(!nodes[1].is_poisoned());
+        stack.ascend().unwrap();
+        assert!(!stack.any_poisoned());
+        assert_eq!(stack.poisoned_depths().count(), 0);
+        assert!(stack.is_poisoned());
+    }
+
+    #[test]
+    fn clear_poison_top_of_an_adopted_root() {
+        let nodes = list(1);
+        poison(&nodes[0]);
+        let guard = nodes[0].lock().unwrap_err().into_inner();
+        let mut stack = MutexGuardStack::from_guard(guard);
+        assert!(!stack.top_was_poisoned());
+        assert!(!stack.clear_poison_top());
+        drop(stack);
+        assert!(nodes[0].is_poisoned());
+    }
+
+    #[test]
+    fn ascend_while_result_stops_at_the_error() {
+        let nodes = list(5);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        let result = stack.ascend_while_result(|node| match node.value {
+            2 => Err(node.value),
+            _ => Ok(true),
+        });
+        assert_eq!(result.map(|top| top.value), Err(2));
+        assert_eq!(stack.depth(), 2);
+        assert!(nodes[2].try_lock().is_err());
+        drop(nodes[3].try_lock().unwrap());
+    }
+
+    #[test]
+    fn ascend_while_result_reaches_the_root() {
+        let nodes = list(5);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        let mut visited = vec![];
+        let top = stack.ascend_while_result(|node| {
+            visited.push(node.value);
+            Ok::<_, ()>(true)
+        });
+        assert_eq!(top.unwrap().value, 0);
+        assert!(stack.is_at_root());
+        assert_eq!(visited, [4, 3, 2, 1]);
+        drop(nodes[1].try_lock().unwrap());
+    }
+
+    #[test]
+    fn ascend_while_result_panics() {
+        let nodes = list(5);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = stack.ascend_while_result(|node| match node.value {
+                2 => panic!("predicate panicked"),
+                _ => Ok::<_, ()>(true),
+            });
+        }));
+        assert!(result.is_err());
+        // The stack is left where the predicate panicked, and the guards it still holds are not poisoned.
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(stack.top().value, 2);
+        assert!(!stack.any_poisoned());
+        drop(nodes[3].try_lock().unwrap());
+        drop(stack);
+        assert!(nodes.iter().all(|node| !node.is_poisoned()));
+    }
+
+    #[test]
+    fn drain_to_root_with_unlocks_each_guard_on_drop() {
+        let nodes = list(4);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        let mut drained = vec![];
+        let popped = stack.drain_to_root_with(|guard| {
+            let value = guard.value as usize;
+            // The guard passed in is still locked, but the one passed in before it was dropped.
+            assert!(nodes[value].try_lock().is_err());
+            if let Some(previous) = nodes.get(value + 1) {
+                drop(previous.try_lock().unwrap());
+            }
+            drained.push(value);
+        });
+        assert_eq!(popped, 3);
+        assert_eq!(drained, [3, 2, 1]);
+        assert!(stack.is_at_root());
+        drop(nodes[1].try_lock().unwrap());
+        assert!(nodes[0].try_lock().is_err());
+    }
+
+    #[test]
+    fn try_descend_outcomes() {
+        let nodes = list(3);
+        let mut stack = MutexGuardStack::new(&nodes[0]).unwrap();
+        let result = stack.try_descend(|node| node.next.as_deref(), PoisonPolicy::Fail);
+        assert!(matches!(result, DescendResult::Descended(top) if top.value == 1));
+        let held = nodes[2].lock().unwrap();
+        let result = stack.try_descend(|node| node.next.as_deref(), PoisonPolicy::Fail);
+        assert!(matches!(
+            result,
+            DescendResult::LockFailed(TryLockError::WouldBlock)
+        ));
+        assert_eq!(stack.depth(), 1);
+        drop(held);
+        let result = stack.try_descend(|node| node.next.as_deref(), PoisonPolicy::Fail);
+        assert!(matches!(result, DescendResult::Descended(top) if top.value == 2));
+        let result = stack.try_descend(|node| node.next.as_deref(), PoisonPolicy::Fail);
+        assert!(matches!(result, DescendResult::NoChild));
+        assert_eq!(stack.depth(), 2);
     }
 }
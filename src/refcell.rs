@@ -1,10 +1,36 @@
 use std::{
-    cell::{BorrowMutError, RefCell, RefMut},
+    any::{type_name, TypeId},
+    cell::{BorrowError, BorrowMutError, Ref, RefCell, RefMut},
+    collections::HashMap,
+    error::Error,
+    fmt,
     future::Future,
     marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
+    rc::Rc,
 };
 
+pub use crate::{AsyncMove, DescendResult, MoveDecisionFor, ProbeResult, ToDepthError};
+use crate::{CyclePolicy, DescendCtx, TopId};
+
+/// A cursor over a recursive data structure of `RefCell`s, holding a mutable borrow of every node from the root to the top.
+///
+/// Like `RefMut`, this is neither `Send` nor `Sync`: the `RefCell` borrow flags are not thread-safe.
+///
+/// ```compile_fail
+/// # use generic_cursors::refcell::RefCellRefMutStack;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<RefCellRefMutStack<'static, u32>>();
+/// ```
+///
+/// ```compile_fail
+/// # use generic_cursors::refcell::RefCellRefMutStack;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<RefCellRefMutStack<'static, u32>>();
+/// ```
 pub struct RefCellRefMutStack<'root, T: ?Sized> {
     /// Ensures this mutrefstack does not exceed the lifetime of its root.
     lifetime: PhantomData<&'root mut T>,
@@ -12,20 +38,347 @@ pub struct RefCellRefMutStack<'root, T: ?Sized> {
     /// Note: the `'root` lifetime is a "lie", only used because there's no raw pointer counterpart for `RefMut`.
     /// The `RefMut`s are not publicly accessible so this is fine.
     data: Vec<RefMut<'root, T>>,
+    /// The levels reached with `descend_map_with`, which share the `RefMut` of their parent.
+    /// For each one, the index in `data` of the `RefMut` that was mapped to it, and the node it was mapped from.
+    field_hops: Vec<(usize, *mut T)>,
+    /// The `RefCell` of every `RefMut`, so that `with_top_shared` can borrow the top again.
+    /// `cells[i]` is the `RefCell` of `data[i]`, which is only unknown for a root adopted with `from_refmut`.
+    cells: Vec<Option<*const RefCell<T>>>,
+    /// The `Rc`s of levels injected with `inject_owned`, with the index in `data` of their `RefMut`,
+    /// which keep those levels alive. Each is dropped after its `RefMut`.
+    owned: Vec<(usize, Rc<RefCell<T>>)>,
+    /// The addresses of `RefCell`s descended into by `descend_with_cycle_check`, with the depth they were pushed at.
+    /// `HashMap::new` does not allocate, so this costs nothing until it is first used.
+    visited: HashMap<usize, usize>,
+    /// Whether the most recent attempt to borrow a new top failed.
+    blocked: bool,
+    /// Bumped whenever every level above the root is popped at once, which invalidates all `Checkpoint`s.
+    generation: u64,
+}
+
+/// Returned by `RefCellRefMutStack::descend_with` when the selected `RefCell` is already borrowed.
+#[derive(Debug)]
+pub struct DescendError {
+    /// The depth the `RefCell` would have been pushed at (the root is at depth 0).
+    pub at_depth: usize,
+    /// The error from borrowing the `RefCell`.
+    pub source: BorrowMutError,
+}
+
+impl fmt::Display for DescendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the RefCell at depth {} is already borrowed",
+            self.at_depth
+        )
+    }
+}
+
+impl Error for DescendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Returned by `RefCellRefMutStack::try_new` when the root `RefCell` is already borrowed.
+#[derive(Debug)]
+pub struct NewError {
+    /// The error from borrowing the root.
+    pub source: BorrowMutError,
+}
+
+impl From<BorrowMutError> for NewError {
+    fn from(source: BorrowMutError) -> Self {
+        NewError { source }
+    }
+}
+
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the root RefCell is already borrowed")
+    }
+}
+
+impl Error for NewError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// A position of a `RefCellRefMutStack`, returned by `RefCellRefMutStack::checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    depth: usize,
+    generation: u64,
+    /// The address of the top when the checkpoint was made.
+    node: usize,
+}
+
+impl Checkpoint {
+    /// How many levels above the root the top of the stack was.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Returned by `RefCellRefMutStack::rollback_to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CheckpointError {
+    /// The stack went back to its root with `to_root` since the checkpoint was made.
+    Stale,
+    /// The checkpoint is deeper than the top of the stack.
+    Deeper {
+        checkpoint_depth: usize,
+        depth: usize,
+    },
+    /// The stack ascended past the checkpoint and descended into a different node since it was made.
+    Diverged,
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Stale => f.write_str("the checkpoint was invalidated by to_root"),
+            CheckpointError::Deeper {
+                checkpoint_depth,
+                depth,
+            } => write!(
+                f,
+                "cannot roll back to depth {checkpoint_depth} from depth {depth}"
+            ),
+            CheckpointError::Diverged => {
+                f.write_str("the stack left the checkpoint's position since it was made")
+            }
+        }
+    }
+}
+
+impl Error for CheckpointError {}
+
+/// Returned by `RefCellRefMutStack::try_descend_with`.
+#[derive(Debug)]
+pub enum TryDescendError<E> {
+    /// The closure failed, so the cursor did not move.
+    User(E),
+    /// The selected `RefCell` was already borrowed.
+    Borrow(DescendError),
+}
+
+impl<E> From<DescendError> for TryDescendError<E> {
+    fn from(err: DescendError) -> Self {
+        TryDescendError::Borrow(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for TryDescendError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryDescendError::User(err) => err.fmt(f),
+            TryDescendError::Borrow(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for TryDescendError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TryDescendError::User(err) => Some(err),
+            TryDescendError::Borrow(err) => Some(err),
+        }
+    }
+}
+
+/// Returned by `RefCellRefMutStack::descend_with_cycle_check` when the selected `RefCell` was already seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CycleDetected {
+    /// The depth the repeated `RefCell` would have been pushed at (the root is at depth 0).
+    pub at_depth: usize,
+    /// The depth the repeated `RefCell` was previously pushed at, where the cycle closes.
+    pub previous_depth: usize,
+}
+
+impl fmt::Display for CycleDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the RefCell at depth {} was already visited at depth {}",
+            self.at_depth, self.previous_depth
+        )
+    }
+}
+
+impl Error for CycleDetected {}
+
+/// Returned by `RefCellRefMutStack::descend_with_cycle_check`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CycleCheckError {
+    /// The selected `RefCell` was already seen.
+    Cycle(CycleDetected),
+    /// The selected `RefCell` was not seen before, but is already borrowed elsewhere.
+    Borrow(DescendError),
+}
+
+impl fmt::Display for CycleCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CycleCheckError::Cycle(err) => err.fmt(f),
+            CycleCheckError::Borrow(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for CycleCheckError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CycleCheckError::Cycle(err) => Some(err),
+            CycleCheckError::Borrow(err) => Some(err),
+        }
+    }
 }
 
-pub enum MoveDecision<'root, 'this, T: ?Sized> {
+/// Returned by `RefCellRefMutStack::with_top_shared` and `RefCellRefMutStack::with_top_owned`.
+#[derive(Debug)]
+pub enum WithTopSharedError<R> {
+    /// The top has no `RefCell` of its own to borrow again, so the closure was not called.
+    NoCell,
+    /// The closure ran, but the top could not be mutably borrowed again afterwards, so the stack ascended past it.
+    Reborrow {
+        /// The result of the closure.
+        result: R,
+        /// The error from borrowing the top again.
+        source: BorrowMutError,
+    },
+}
+
+impl<R> fmt::Display for WithTopSharedError<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WithTopSharedError::NoCell => f.write_str("the top has no RefCell of its own"),
+            WithTopSharedError::Reborrow { .. } => {
+                f.write_str("the top was borrowed by someone else while it was released")
+            }
+        }
+    }
+}
+
+impl<R: fmt::Debug> Error for WithTopSharedError<R> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WithTopSharedError::NoCell => None,
+            WithTopSharedError::Reborrow { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Returned by `RefCellRefMutStack::descend_chain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainOutcome {
+    /// How many levels were descended.
+    pub descended: usize,
+    /// Why the chain stopped.
+    pub stop: ChainStop,
+}
+
+/// Why `RefCellRefMutStack::descend_chain` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainStop {
+    /// The closure did not select a `RefCell`.
+    NoChild,
+    /// The selected `RefCell` was already borrowed.
+    BorrowMutError,
+    /// The maximum number of levels was descended.
+    DepthLimit,
+}
+
+pub enum MoveDecision<'root, 'this, T: ?Sized, R = ()> {
     Ascend,
     Stay,
     Descend(&'this RefCell<T>),
     Inject(&'root RefCell<T>),
+    /// Stop `RefCellRefMutStack::drive`, returning the value. The `move_with*` methods treat this like `Stay`.
+    Finish(R),
+}
+
+impl<'root, 'this, T: ?Sized, R> MoveDecision<'root, 'this, T, R> {
+    /// Descend into `node`. Equivalent to `MoveDecision::Descend(node)`.
+    pub fn descend(node: &'this RefCell<T>) -> Self {
+        MoveDecision::Descend(node)
+    }
+
+    /// Inject `node` as the new top. Equivalent to `MoveDecision::Inject(node)`.
+    pub fn inject(node: &'root RefCell<T>) -> Self {
+        MoveDecision::Inject(node)
+    }
 }
 
+impl<T: ?Sized> crate::sealed::Decision for MoveDecision<'_, '_, T> {}
+
+impl<'root, 'a, T: ?Sized> MoveDecisionFor<'a, T> for MoveDecision<'root, 'root, T> {
+    type Decision = MoveDecision<'root, 'a, T>;
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum MoveError {
+    /// The closure asked to ascend, but the stack was already at its root.
     AscendAtRoot,
+    /// The selected `RefCell` was already borrowed.
     BorrowMutError(BorrowMutError),
 }
 
+impl MoveError {
+    /// Did the closure ask to ascend at the root?
+    pub fn is_ascend_at_root(&self) -> bool {
+        matches!(self, MoveError::AscendAtRoot)
+    }
+
+    /// The error from borrowing the selected `RefCell`, if that is why the move failed.
+    pub fn borrow_error(&self) -> Option<&BorrowMutError> {
+        match self {
+            MoveError::BorrowMutError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Compares variants only: all `BorrowMutError`s are equal to each other.
+impl PartialEq for MoveError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (MoveError::AscendAtRoot, MoveError::AscendAtRoot)
+                | (MoveError::BorrowMutError(_), MoveError::BorrowMutError(_))
+        )
+    }
+}
+
+impl Eq for MoveError {}
+
+/// The result of a descend that may choose not to move, flattened so that it can be propagated with `?`.
+/// `Ok(None)` means the closure did not select a new top. The error is never `MoveError::AscendAtRoot`.
+pub type DescendOutcome<'a, T> = Result<Option<&'a mut T>, MoveError>;
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::AscendAtRoot => f.write_str("cannot ascend above the root"),
+            MoveError::BorrowMutError(_) => f.write_str("the new top is already borrowed"),
+        }
+    }
+}
+
+impl Error for MoveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MoveError::AscendAtRoot => None,
+            MoveError::BorrowMutError(err) => Some(err),
+        }
+    }
+}
+
 impl<'root, T: ?Sized> RefCellRefMutStack<'root, T> {
     /// Create a new MutRefStack from a mutable reference to the root
     /// of a recursive data structure.
@@ -35,17 +388,49 @@ impl<'root, T: ?Sized> RefCellRefMutStack<'root, T> {
         Ok(Self {
             lifetime: PhantomData,
             data: vec![borrow],
+            field_hops: Vec::new(),
+            cells: vec![Some(root)],
+            owned: Vec::new(),
+            visited: HashMap::new(),
+            blocked: false,
+            generation: 0,
         })
     }
 
+    /// Create a new RefCellRefMutStack from the root `RefCell` of a recursive data structure, like `new`,
+    /// but with an error dedicated to the root.
+    pub fn try_new(root: &'root RefCell<T>) -> Result<Self, NewError> {
+        Ok(Self::new(root)?)
+    }
+
+    /// Create a new RefCellRefMutStack from an already-held mutable borrow of the root of a recursive data structure.
+    /// The borrow becomes the root of the stack, and is never popped.
+    pub fn from_refmut(refmut: RefMut<'root, T>) -> Self {
+        Self {
+            lifetime: PhantomData,
+            data: vec![refmut],
+            field_hops: Vec::new(),
+            cells: vec![None],
+            owned: Vec::new(),
+            visited: HashMap::new(),
+            blocked: false,
+            generation: 0,
+        }
+    }
+
     pub fn raw_top_mut(&mut self) -> *mut T {
         let refmut: *mut RefMut<T> = self.data.last_mut().unwrap();
         unsafe { &mut **refmut }
     }
 
+    /// Obtain a raw pointer to the top of the stack, e.g. to compare node identities.
+    pub fn raw_top(&self) -> *const T {
+        self.top()
+    }
+
     /// Obtain a shared reference to the top of the stack.
     pub fn top(&self) -> &T {
-        &*self.data.last().unwrap()
+        self.data.last().unwrap()
     }
 
     /// Obtain a mutable reference to the top of the stack.
@@ -53,18 +438,104 @@ impl<'root, T: ?Sized> RefCellRefMutStack<'root, T> {
         &mut *self.data.last_mut().unwrap()
     }
 
+    /// Is the top of the stack exclusively borrowed?
+    /// This is always true, since the stack holds a `RefMut` of its top.
+    pub fn top_is_exclusively_borrowed(&self) -> bool {
+        true
+    }
+
     /// Is this MutRefStack currently at its root?
     pub fn is_at_root(&self) -> bool {
-        self.data.len() == 1
+        self.data.len() == 1 && self.field_hops.is_empty()
+    }
+
+    /// Is the top of the stack a level reached with `descend_map_with`?
+    fn top_is_field_hop(&self) -> bool {
+        self.field_hops
+            .last()
+            .is_some_and(|&(index, _)| index == self.data.len() - 1)
+    }
+
+    /// How many `RefMut`s the stack can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+
+    /// Reserve capacity for at least `additional` more `RefMut`s, so that descending that far does not reallocate.
+    /// Reallocating moves the `RefMut`s, but it never releases or reborrows them.
+    pub fn reserve(&mut self, additional: usize) {
+        self.data.reserve(additional);
+        self.cells.reserve(additional);
+    }
+
+    /// Shrink the stack's storage to fit its current contents.
+    /// The stack always keeps at least the root.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.cells.shrink_to_fit();
+        self.field_hops.shrink_to_fit();
+        self.owned.shrink_to_fit();
+    }
+
+    /// How many levels above the root the top of the stack is.
+    /// Levels reached with `descend_map_with` count too.
+    pub fn depth(&self) -> usize {
+        self.data.len() - 1 + self.field_hops.len()
+    }
+
+    /// Information about the current position, for the `*_with_ctx` methods.
+    fn ctx(&self) -> DescendCtx {
+        DescendCtx {
+            depth: self.depth(),
+            is_at_root: self.is_at_root(),
+            previous_step_blocked: self.blocked,
+        }
+    }
+
+    /// Obtain an identity token for the top of the stack.
+    /// The token is the address of the top node's value rather than of its `RefCell`,
+    /// since a level descended with `descend_map_with` has no `RefCell` of its own.
+    /// It is only meaningful while that node is alive.
+    pub fn top_id(&self) -> TopId<T> {
+        TopId::new(self.top())
+    }
+
+    /// Is the top of the stack the node identified by `id`?
+    pub fn is_at(&self, id: TopId<T>) -> bool {
+        self.top_id() == id
+    }
+
+    /// Iterate over the identity tokens of every node on the stack, from the root to the top.
+    /// Like `top_id`, these are value addresses, and are only meaningful while their nodes are alive.
+    pub fn path_ids(&self) -> impl Iterator<Item = TopId<T>> + '_ {
+        self.data
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, refmut)| {
+                let hops = self.field_hops.iter().filter(move |&&(i, _)| i == index);
+                hops.map(|&(_, parent)| TopId::new(parent))
+                    .chain(std::iter::once(TopId::new(&**refmut)))
+            })
+    }
+
+    fn handle_borrow_result(
+        &mut self,
+        cell: *const RefCell<T>,
+        borrow: Result<RefMut<'root, T>, BorrowMutError>,
+    ) -> Result<&mut T, BorrowMutError> {
+        self.blocked = borrow.is_err();
+        let borrow = borrow?;
+        self.data.push(borrow);
+        self.cells.push(Some(cell));
+        Ok(self.top_mut())
     }
 
     /// Inject a new reference to the top of the stack. The reference still must live
     /// as long as the root of the stack.
     pub fn inject_top(&mut self, new_top: &'root RefCell<T>) -> Result<&mut T, BorrowMutError> {
         let new_top: *const RefCell<T> = new_top;
-        let borrow = unsafe { (*new_top).try_borrow_mut()? };
-        self.data.push(borrow);
-        Ok(self.top_mut())
+        let borrow = unsafe { (*new_top).try_borrow_mut() };
+        self.handle_borrow_result(new_top, borrow)
     }
 
     /// Inject a new reference to the top of the stack. The reference still must live
@@ -77,52 +548,410 @@ impl<'root, T: ?Sized> RefCellRefMutStack<'root, T> {
         let new_top: &RefCell<T> = unsafe { f(&mut *old_top)? };
         let new_top: *const RefCell<T> = new_top;
         let borrow = unsafe { (*new_top).try_borrow_mut() };
-        match borrow {
-            Ok(borrow) => {
-                self.data.push(borrow);
-                Some(Ok(self.top_mut()))
-            }
-            Err(err) => Some(Err(err)),
+        Some(self.handle_borrow_result(new_top, borrow))
+    }
+
+    /// Inject a new top that is owned by the stack, e.g. a node just allocated by the caller,
+    /// returning a mutable reference to the new top element.
+    /// The `Rc` is kept by the new level, and when the level is ascended, it is dropped after the level's `RefMut`.
+    pub fn inject_owned(&mut self, cell: Rc<RefCell<T>>) -> Result<&mut T, BorrowMutError> {
+        let new_top: *const RefCell<T> = Rc::as_ptr(&cell);
+        // SAFETY: The `RefCell` is kept alive by `cell`, which is kept until the `RefMut` is dropped.
+        let borrow = unsafe { (*new_top).try_borrow_mut() };
+        if borrow.is_ok() {
+            self.owned.push((self.data.len(), cell));
         }
+        self.handle_borrow_result(new_top, borrow)
+    }
+
+    /// Inject a new top that is owned by the stack like `inject_owned`, selected by the closure.
+    /// Returns None if the closure did not select a `RefCell`.
+    pub fn inject_owned_with(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<Rc<RefCell<T>>>,
+    ) -> Option<Result<&mut T, BorrowMutError>> {
+        let cell = f(self.top_mut())?;
+        Some(self.inject_owned(cell))
     }
 
     /// Descend into the recursive data structure, returning a mutable reference to the new top element.
     /// Rust's borrow checker enforces that the closure cannot inject any lifetime (other than `'static`),
     /// because the closure must work for any lifetime `'node`.
+    /// If the selected `RefCell` is already borrowed, the error reports the depth it would have been pushed at.
     pub fn descend_with(
         &mut self,
         f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node RefCell<T>>,
-    ) -> Option<Result<&mut T, BorrowMutError>> {
+    ) -> Option<Result<&mut T, DescendError>> {
         let old_top: *mut T = self.raw_top_mut();
         let new_top: &RefCell<T> = unsafe { f(&mut *old_top)? };
         let new_top: *const RefCell<T> = new_top;
         let borrow = unsafe { (*new_top).try_borrow_mut() };
-        match borrow {
-            Ok(borrow) => {
-                self.data.push(borrow);
-                Some(Ok(self.top_mut()))
+        let at_depth = self.depth() + 1;
+        Some(
+            self.handle_borrow_result(new_top, borrow)
+                .map_err(|source| DescendError { at_depth, source }),
+        )
+    }
+
+    /// Descend into the recursive data structure like `descend_with`, but return a `DescendResult`,
+    /// so that not selecting a child and failing to borrow it can be matched at the same level.
+    pub fn try_descend(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node RefCell<T>>,
+    ) -> DescendResult<'_, T, DescendError> {
+        DescendResult::from_option(self.descend_with(f))
+    }
+
+    /// Descend into the recursive data structure like `descend_with`, but the closure may fail with its own error,
+    /// in which case the stack does not move.
+    /// Returns `Ok(None)` if the closure did not select a `RefCell`.
+    pub fn try_descend_with<E>(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Result<Option<&'node RefCell<T>>, E>,
+    ) -> Result<Option<&mut T>, TryDescendError<E>> {
+        let old_top: *mut T = self.raw_top_mut();
+        let Some(new_top) = unsafe { f(&mut *old_top) }.map_err(TryDescendError::User)? else {
+            return Ok(None);
+        };
+        let new_top: *const RefCell<T> = new_top;
+        let borrow = unsafe { (*new_top).try_borrow_mut() };
+        let at_depth = self.depth() + 1;
+        match self.handle_borrow_result(new_top, borrow) {
+            Ok(top) => Ok(Some(top)),
+            Err(source) => Err(TryDescendError::Borrow(DescendError { at_depth, source })),
+        }
+    }
+
+    /// Descend into the recursive data structure like `descend_with`, returning a flattened `DescendOutcome`.
+    pub fn descend_with_outcome(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node RefCell<T>>,
+    ) -> DescendOutcome<'_, T> {
+        self.descend_with(f)
+            .transpose()
+            .map_err(|err| MoveError::BorrowMutError(err.source))
+    }
+
+    /// Inject a new top like `inject_with`, returning a flattened `DescendOutcome`.
+    pub fn inject_with_outcome(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&'root RefCell<T>>,
+    ) -> DescendOutcome<'_, T> {
+        self.inject_with(f)
+            .transpose()
+            .map_err(MoveError::BorrowMutError)
+    }
+
+    /// Descend into the recursive data structure like `descend_with`, additionally passing the closure
+    /// information about the current position.
+    pub fn descend_with_ctx(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T, DescendCtx) -> Option<&'node RefCell<T>>,
+    ) -> Option<Result<&mut T, DescendError>> {
+        let ctx = self.ctx();
+        self.descend_with(|node| f(node, ctx))
+    }
+
+    /// Descend into the first of the `RefCell`s selected by the closure that is not already borrowed,
+    /// returning a mutable reference to the new top element.
+    /// Returns None if the closure selected no `RefCell`s, and the error of the last candidate if every candidate
+    /// was already borrowed, in which case the stack does not move.
+    pub fn descend_first_borrowable(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Vec<&'node RefCell<T>>,
+    ) -> Option<Result<&mut T, BorrowMutError>> {
+        let old_top: *mut T = self.raw_top_mut();
+        let candidates = unsafe { f(&mut *old_top) };
+        let mut last_error = None;
+        for new_top in candidates {
+            let new_top: *const RefCell<T> = new_top;
+            match unsafe { (*new_top).try_borrow_mut() } {
+                Ok(borrow) => return Some(self.handle_borrow_result(new_top, Ok(borrow))),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        let err = last_error?;
+        self.blocked = true;
+        Some(Err(err))
+    }
+
+    /// Descend into the recursive data structure like `descend_with`, but refuse to descend into a `RefCell` that
+    /// was already seen according to `policy`, returning `CycleCheckError::Cycle` instead and leaving the stack unchanged.
+    /// Unlike `descend_with`, this detects a cycle even if it does not pass through the current path.
+    /// Every `RefCell` descended into with this method is recorded, for use with `CyclePolicy::Anywhere`.
+    pub fn descend_with_cycle_check(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node RefCell<T>>,
+        policy: CyclePolicy,
+    ) -> Result<Option<&mut T>, CycleCheckError> {
+        let old_top: *mut T = self.raw_top_mut();
+        let Some(new_top) = (unsafe { f(&mut *old_top) }) else {
+            return Ok(None);
+        };
+        let new_top: *const RefCell<T> = new_top;
+        let addr = new_top.cast::<()>() as usize;
+        let at_depth = self.depth() + 1;
+        let on_path = self
+            .cells
+            .iter()
+            .position(|&cell| cell.is_some_and(|cell| cell.cast::<()>() as usize == addr))
+            .map(|index| self.depth_of_index(index));
+        let previous_depth = match policy {
+            CyclePolicy::OnPath => on_path,
+            CyclePolicy::Anywhere => on_path.or_else(|| self.visited.get(&addr).copied()),
+        };
+        if let Some(previous_depth) = previous_depth {
+            return Err(CycleCheckError::Cycle(CycleDetected {
+                at_depth,
+                previous_depth,
+            }));
+        }
+        let borrow = unsafe { (*new_top).try_borrow_mut() };
+        match self.handle_borrow_result(new_top, borrow) {
+            Ok(_) => {}
+            Err(source) => return Err(CycleCheckError::Borrow(DescendError { at_depth, source })),
+        }
+        self.visited.insert(addr, at_depth);
+        Ok(Some(self.top_mut()))
+    }
+
+    /// The depth of the `RefMut` at `index` in `data`, counting the field hops below it.
+    fn depth_of_index(&self, index: usize) -> usize {
+        index + self.field_hops.iter().filter(|&&(i, _)| i < index).count()
+    }
+
+    /// How many `RefCell`s have been recorded by `descend_with_cycle_check`.
+    pub fn visited_len(&self) -> usize {
+        self.visited.len()
+    }
+
+    /// Forget every `RefCell` recorded by `descend_with_cycle_check`.
+    pub fn clear_visited(&mut self) {
+        self.visited.clear();
+    }
+
+    /// Descend repeatedly like `descend_with`, until the closure selects no `RefCell`, the selected `RefCell`
+    /// is already borrowed, or `max_levels` levels have been descended.
+    /// Levels that were successfully descended stay on the stack; nothing is pushed for the step that stopped the chain.
+    pub fn descend_chain(
+        &mut self,
+        mut f: impl for<'node> FnMut(&'node mut T) -> Option<&'node RefCell<T>>,
+        max_levels: usize,
+    ) -> ChainOutcome {
+        let mut descended = 0;
+        let stop = loop {
+            if descended == max_levels {
+                break ChainStop::DepthLimit;
+            }
+            let old_top: *mut T = self.raw_top_mut();
+            let Some(new_top) = (unsafe { f(&mut *old_top) }) else {
+                break ChainStop::NoChild;
+            };
+            let new_top: *const RefCell<T> = new_top;
+            let borrow = unsafe { (*new_top).try_borrow_mut() };
+            if self.handle_borrow_result(new_top, borrow).is_err() {
+                break ChainStop::BorrowMutError;
             }
-            Err(err) => Some(Err(err)),
+            descended += 1;
+        };
+        ChainOutcome { descended, stop }
+    }
+
+    /// Check whether the `RefCell` selected by the closure could currently be mutably borrowed, without descending into it.
+    /// The borrow is attempted and immediately released, so the answer may be stale by the time it is acted on.
+    /// Returns None if the closure did not select a `RefCell`.
+    pub fn peek_borrowable(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node RefCell<T>>,
+    ) -> Option<bool> {
+        let next = f(self.top_mut())?;
+        Some(next.try_borrow_mut().is_ok())
+    }
+
+    /// Check whether the `RefCell` selected by the closure could currently be mutably borrowed, like `peek_borrowable`,
+    /// but only needing a shared borrow of the stack.
+    /// The borrow is attempted and immediately released. This is advisory only: the `RefCell` may be borrowed or
+    /// released (e.g. through a clone of its `Rc`) between this check and a descend into it.
+    pub fn probe_with(
+        &self,
+        f: impl for<'node> FnOnce(&'node T) -> Option<&'node RefCell<T>>,
+    ) -> ProbeResult {
+        match f(self.top()) {
+            None => ProbeResult::NoChild,
+            Some(next) => match next.try_borrow_mut() {
+                Ok(_borrow) => ProbeResult::Free,
+                Err(_) => ProbeResult::WouldConflict,
+            },
         }
     }
 
     /// Ascend back up from the recursive data structure, returning a mutable reference to the new top element, if it changed.
     /// If we are not currently at the root, ascend and return a reference to the new top.
     /// If we are already at the root, returns None (the top is the root and does not change).
+    /// Levels reached with `descend_map_with` are ascended the same way, by mapping the `RefMut` back to the parent.
     pub fn ascend(&mut self) -> Option<&mut T> {
+        if self.top_is_field_hop() {
+            let (_, parent) = self.field_hops.pop().unwrap();
+            let top = self.data.pop().unwrap();
+            // SAFETY: `parent` was mapped from with this `RefMut`, which still borrows it,
+            // and the old top is not used again.
+            self.data
+                .push(RefMut::map(top, |_| unsafe { &mut *parent }));
+            return Some(self.top_mut());
+        }
         match self.data.len() {
             0 => unreachable!("root pointer must always exist"),
             1 => None,
             _ => {
                 self.data.pop();
+                self.cells.pop();
+                self.release_owned();
                 Some(self.top_mut())
             }
         }
     }
 
+    /// Helper function to drop the `Rc`s of levels that have been popped, after their `RefMut`s.
+    fn release_owned(&mut self) {
+        while self
+            .owned
+            .last()
+            .is_some_and(|&(index, _)| index >= self.data.len())
+        {
+            self.owned.pop();
+        }
+    }
+
+    /// Run the closure with a shared borrow of the top instead of the held `RefMut`, so that other shared
+    /// borrows of the top (e.g. through a clone of its `Rc`) work while it runs.
+    ///
+    /// The top's `RefMut` is dropped, the top is borrowed shared for the closure, and then it is borrowed mutably
+    /// again. Between these steps, nothing else can run on this thread, but the closure itself may keep another borrow
+    /// of the top alive after it returns, e.g. a `Ref` obtained through a clone of its `Rc`. Then the top cannot be
+    /// borrowed mutably again, the stack ascends past it, and this returns `WithTopSharedError::Reborrow` with the
+    /// closure's result. Anything learned about the top before calling this may be stale afterwards.
+    ///
+    /// The root, and levels reached with `descend_map_with` (or the `RefMut` they were mapped from), cannot be
+    /// released and borrowed again, so for those this returns `WithTopSharedError::NoCell` without calling the closure.
+    pub fn with_top_shared<R>(
+        &mut self,
+        f: impl FnOnce(&T) -> R,
+    ) -> Result<R, WithTopSharedError<R>> {
+        if self.data.len() == 1 || self.top_is_field_hop() {
+            return Err(WithTopSharedError::NoCell);
+        }
+        // The level is popped completely first, so that the stack stays consistent if the closure panics.
+        self.data.pop();
+        let cell = self
+            .cells
+            .pop()
+            .unwrap()
+            .expect("only the root may have no RefCell");
+        // SAFETY: The `RefCell` is inside the new top, which is still borrowed by this stack,
+        // or it is kept alive by the `Rc` it was injected with, which is only dropped below.
+        let cell = unsafe { &*cell };
+        let result = f(&cell.borrow());
+        match cell.try_borrow_mut() {
+            Ok(borrow) => {
+                self.data.push(borrow);
+                self.cells.push(Some(cell));
+                Ok(result)
+            }
+            Err(source) => {
+                self.release_owned();
+                Err(WithTopSharedError::Reborrow { result, source })
+            }
+        }
+    }
+
+    /// Run the closure with ownership of the top's `RefMut`, e.g. to pass it to an API that takes a `RefMut`,
+    /// then borrow the top mutably again. Unlike `into_top_leaking`, nothing is leaked.
+    ///
+    /// The closure must work for any lifetime `'a`, so the `RefMut` cannot outlive it. If the closure forgets the
+    /// `RefMut`, or keeps another borrow of the top alive after it returns, the top cannot be borrowed mutably again:
+    /// the stack ascends past it, and this returns `WithTopSharedError::Reborrow` with the closure's result.
+    ///
+    /// Like `with_top_shared`, this returns `WithTopSharedError::NoCell` without calling the closure
+    /// for the root and for levels reached with `descend_map_with` (or the `RefMut` they were mapped from).
+    pub fn with_top_owned<R>(
+        &mut self,
+        f: impl for<'a> FnOnce(RefMut<'a, T>) -> R,
+    ) -> Result<R, WithTopSharedError<R>> {
+        if self.data.len() == 1 || self.top_is_field_hop() {
+            return Err(WithTopSharedError::NoCell);
+        }
+        // The level is popped completely first, so that the stack stays consistent if the closure panics.
+        let top = self.data.pop().unwrap();
+        let cell = self
+            .cells
+            .pop()
+            .unwrap()
+            .expect("only the root may have no RefCell");
+        let result = f(top);
+        // SAFETY: The `RefCell` is inside the new top, which is still borrowed by this stack,
+        // or it is kept alive by the `Rc` it was injected with, which is only dropped below.
+        let cell = unsafe { &*cell };
+        match cell.try_borrow_mut() {
+            Ok(borrow) => {
+                self.data.push(borrow);
+                self.cells.push(Some(cell));
+                Ok(result)
+            }
+            Err(source) => {
+                self.release_owned();
+                Err(WithTopSharedError::Reborrow { result, source })
+            }
+        }
+    }
+
+    /// Descend into a node that is not in its own `RefCell`, such as a plain field of the top,
+    /// returning a mutable reference to the new top element.
+    /// The new level shares the top's `RefMut`, using `RefMut::map`, and is ascended like any other level.
+    pub fn descend_map_with(&mut self, f: impl FnOnce(&mut T) -> &mut T) -> &mut T {
+        self.descend_filter_map_with(|node| Some(f(node)))
+            .expect("the closure always selects a node")
+    }
+
+    /// Like `descend_map_with`, but the closure may not select a node, in which case the stack does not move
+    /// and this returns None.
+    pub fn descend_filter_map_with(
+        &mut self,
+        f: impl FnOnce(&mut T) -> Option<&mut T>,
+    ) -> Option<&mut T> {
+        let index = self.data.len() - 1;
+        let top = self.data.pop().unwrap();
+        let mut parent = None;
+        let mut panic = None;
+        let top = RefMut::map(top, |node| {
+            let node: *mut T = node;
+            // If the closure panics, the `RefMut` must still be put back, so catch the panic and
+            // map to the old top until it is resumed below.
+            match panic::catch_unwind(AssertUnwindSafe(|| f(unsafe { &mut *node }))) {
+                Ok(Some(child)) => {
+                    parent = Some(node);
+                    child
+                }
+                Ok(None) => unsafe { &mut *node },
+                Err(payload) => {
+                    panic = Some(payload);
+                    unsafe { &mut *node }
+                }
+            }
+        });
+        self.data.push(top);
+        if let Some(payload) = panic {
+            panic::resume_unwind(payload);
+        }
+        self.field_hops.push((index, parent?));
+        Some(self.top_mut())
+    }
+
     /// Ascend back up from the recursive data structure while the given closure returns `true`, returning a mutable reference to the new top element.
     /// If we are not currently at the root, and the predicate returns `true`, ascend and continue.
     /// If we are already at the root, or if the predicate returned false, returns a reference to the top element.
+    ///
+    /// If the predicate panics, the stack is left at the level the predicate panicked on,
+    /// and is still usable if the panic is caught; the levels already ascended stay ascended (and their borrows released).
     pub fn ascend_while<P>(&mut self, mut predicate: P) -> &mut T
     where
         P: FnMut(&mut T) -> bool,
@@ -135,31 +964,129 @@ impl<'root, T: ?Sized> RefCellRefMutStack<'root, T> {
         self.top_mut()
     }
 
-    /// Ascend from, descend from, inject a new stack top, or stay at the current node,
-    /// based on the return value of the closure.
-    pub fn move_with<F>(&mut self, f: F) -> Result<&mut T, MoveError>
-    where
-        F: for<'a> FnOnce(&'a mut T) -> MoveDecision<'root, 'a, T>,
-    {
-        let old_top: *mut T = self.raw_top_mut();
-        let result = unsafe { f(&mut *old_top) };
-        match result {
-            MoveDecision::Ascend => self.ascend().ok_or(MoveError::AscendAtRoot),
-            MoveDecision::Stay => Ok(self.top_mut()),
-            MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
-                let new_top: *const RefCell<T> = new_top;
-                let borrow = unsafe { (*new_top).try_borrow_mut() };
-                match borrow {
-                    Ok(borrow) => {
-                        self.data.push(borrow);
-                        Ok(self.top_mut())
+    /// Ascend like `ascend_while`, but the predicate can fail.
+    /// If it returns an error, ascending stops and the stack is left at the level the predicate failed on.
+    pub fn ascend_while_result<E>(
+        &mut self,
+        mut predicate: impl FnMut(&mut T) -> Result<bool, E>,
+    ) -> Result<&mut T, E> {
+        while !self.is_at_root() && predicate(self.top_mut())? {
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        Ok(self.top_mut())
+    }
+
+    /// Run the closure on the current top, then ascend, returning the closure's result.
+    /// If we are already at the root, returns None without calling the closure.
+    pub fn ascend_map<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        if self.is_at_root() {
+            return None;
+        }
+        let result = f(self.top_mut());
+        let Some(_) = self.ascend() else {
+            unreachable!();
+        };
+        Some(result)
+    }
+
+    /// Ascend while the closure returns `Some`, collecting its results in pop order, i.e. starting with the current top.
+    /// The closure is called on the current top before each ascend, and never on the root.
+    pub fn ascend_while_map<R>(&mut self, mut f: impl FnMut(&mut T) -> Option<R>) -> Vec<R> {
+        let mut results = Vec::new();
+        while !self.is_at_root() {
+            let Some(result) = f(self.top_mut()) else {
+                break;
+            };
+            results.push(result);
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        results
+    }
+
+    /// Ascend like `ascend_while`, additionally passing the predicate the depth of the node it is called with.
+    /// For example, `ascend_while_indexed(|_, depth| depth > 2)` ascends until the top is at depth 2.
+    pub fn ascend_while_indexed<P>(&mut self, mut predicate: P) -> &mut T
+    where
+        P: FnMut(&mut T, usize) -> bool,
+    {
+        let mut depth = self.depth();
+        self.ascend_while(|top| {
+            let ascend = predicate(top, depth);
+            depth -= 1;
+            ascend
+        })
+    }
+
+    /// Ascend from, descend from, inject a new stack top, or stay at the current node,
+    /// based on the return value of the closure.
+    pub fn move_with<F>(&mut self, f: F) -> Result<&mut T, MoveError>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> MoveDecision<'root, 'a, T>,
+    {
+        let old_top: *mut T = self.raw_top_mut();
+        let result = unsafe { f(&mut *old_top) };
+        self.apply_decision(result)
+    }
+
+    /// Repeatedly move based on the return value of the closure, like `move_with`, until it returns `MoveDecision::Finish`.
+    /// Returns the value it finished with, or None if it asked to ascend at the root.
+    /// If a selected `RefCell` is already borrowed, stops with the error, and the stack stays where it was.
+    pub fn drive<R, F>(&mut self, mut f: F) -> Result<Option<R>, MoveError>
+    where
+        F: for<'a> FnMut(&'a mut T) -> MoveDecision<'root, 'a, T, R>,
+    {
+        loop {
+            let old_top: *mut T = self.raw_top_mut();
+            match unsafe { f(&mut *old_top) } {
+                MoveDecision::Finish(result) => return Ok(Some(result)),
+                MoveDecision::Ascend => {
+                    if self.ascend().is_none() {
+                        return Ok(None);
                     }
-                    Err(err) => Err(MoveError::BorrowMutError(err)),
                 }
+                MoveDecision::Stay => {}
+                MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
+                    let new_top: *const RefCell<T> = new_top;
+                    let borrow = unsafe { (*new_top).try_borrow_mut() };
+                    self.handle_borrow_result(new_top, borrow)
+                        .map_err(MoveError::BorrowMutError)?;
+                }
+            }
+        }
+    }
+
+    /// Carry out the decision of a `move_with*` closure.
+    fn apply_decision(
+        &mut self,
+        decision: MoveDecision<'root, '_, T>,
+    ) -> Result<&mut T, MoveError> {
+        match decision {
+            MoveDecision::Ascend => self.ascend().ok_or(MoveError::AscendAtRoot),
+            MoveDecision::Stay | MoveDecision::Finish(()) => Ok(self.top_mut()),
+            MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
+                let new_top: *const RefCell<T> = new_top;
+                let borrow = unsafe { (*new_top).try_borrow_mut() };
+                self.handle_borrow_result(new_top, borrow)
+                    .map_err(MoveError::BorrowMutError)
             }
         }
     }
 
+    /// Like `move_with`, additionally passing the closure information about the current position.
+    pub fn move_with_ctx<F>(&mut self, f: F) -> Result<&mut T, MoveError>
+    where
+        F: for<'a> FnOnce(&'a mut T, DescendCtx) -> MoveDecision<'root, 'a, T>,
+    {
+        let ctx = self.ctx();
+        self.move_with(|node| f(node, ctx))
+    }
+
+    /// Like `move_with`, but the closure returns a boxed future.
+    /// To pass an async closure instead, without boxing its future, use `move_with_async_fn`.
     pub async fn move_with_async<F>(&mut self, f: F) -> Result<&mut T, MoveError>
     where
         F: for<'a> FnOnce(
@@ -169,43 +1096,379 @@ impl<'root, T: ?Sized> RefCellRefMutStack<'root, T> {
     {
         let old_top: *mut T = self.raw_top_mut();
         let result = unsafe { f(&mut *old_top) }.await;
-        match result {
-            MoveDecision::Ascend => self.ascend().ok_or(MoveError::AscendAtRoot),
-            MoveDecision::Stay => Ok(self.top_mut()),
-            MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
-                let new_top: *const RefCell<T> = new_top;
-                let borrow = unsafe { (*new_top).try_borrow_mut() };
-                match borrow {
-                    Ok(borrow) => {
-                        self.data.push(borrow);
-                        Ok(self.top_mut())
-                    }
-                    Err(err) => Err(MoveError::BorrowMutError(err)),
-                }
-            }
-        }
+        self.apply_decision(result)
+    }
+
+    /// Like `move_with_async`, but takes an async closure, whose future does not need to be boxed.
+    /// See `AsyncMove`.
+    pub async fn move_with_async_fn<F>(&mut self, f: F) -> Result<&mut T, MoveError>
+    where
+        F: AsyncMove<T, MoveDecision<'root, 'root, T>>,
+    {
+        let old_top: *mut T = self.raw_top_mut();
+        let result = unsafe { f(&mut *old_top) }.await;
+        self.apply_decision(result)
     }
 
     /// Return reference to the top element of this stack, forgetting about the stack entirely.
     /// Note that this leaks all `RefMut`s above the top.
-    pub fn into_top(mut self) -> RefMut<'root, T> {
+    #[deprecated(note = "use `scoped_top`, `into_root`, or `into_top_leaking` instead")]
+    pub fn into_top(self) -> RefMut<'root, T> {
+        self.into_top_leaking()
+    }
+
+    /// Obtain a guard that derefs to the top of the stack.
+    /// Unlike `into_top_leaking`, this only borrows the stack, so no parent `RefMut` can be leaked.
+    pub fn scoped_top(&mut self) -> TopGuard<'_, 'root, T> {
+        TopGuard { stack: self }
+    }
+
+    /// Return reference to the top element of this stack, forgetting about the stack entirely.
+    /// Note that this leaks all `RefMut`s above the top, and the `Rc`s of every level injected with `inject_owned`.
+    pub fn into_top_leaking(mut self) -> RefMut<'root, T> {
         let ret = self.data.pop().unwrap();
         unsafe {
             // We need to not drop the parent RefMuts, if any
             self.data.set_len(0);
         }
+        // The returned `RefMut` may borrow from one of these, so they must never be dropped.
+        mem::forget(mem::take(&mut self.owned));
         ret
     }
 
+    /// Ascend `n` levels, returning a mutable reference to the new top.
+    /// The `RefMut`s are dropped one at a time, from the top down.
+    /// If the top is fewer than `n` levels above the root, returns None and does not ascend at all.
+    pub fn ascend_n(&mut self, n: usize) -> Option<&mut T> {
+        let target = self.depth().checked_sub(n)?;
+        self.truncate_to_depth(target).ok()
+    }
+
+    /// Ascend until the top is `target` levels above the root, returning a mutable reference to the new top.
+    /// The `RefMut`s are dropped one at a time, from the top down.
+    /// Fails without ascending if the top is already less than `target` levels above the root.
+    pub fn truncate_to_depth(&mut self, target: usize) -> Result<&mut T, ToDepthError> {
+        let depth = self.depth();
+        if target > depth {
+            return Err(ToDepthError { target, depth });
+        }
+        for _ in target..depth {
+            // Ascend one level at a time, so that child borrows are released before their parents.
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        Ok(self.top_mut())
+    }
+
+    /// Pop all `RefMut`s off the stack, and return the `RefMut` of the root.
+    /// Unlike `into_top_leaking`, this does not leak anything.
+    pub fn into_root(mut self) -> RefMut<'root, T> {
+        self.to_root();
+        self.data.pop().unwrap()
+    }
+
+    /// Pop all `RefMut`s off the stack and go back to the root, returning how many were popped
+    /// (the depth of the stack beforehand).
+    pub fn ascend_to_root(&mut self) -> usize {
+        let depth = self.depth();
+        self.to_root();
+        depth
+    }
+
     /// Pop all `RefMut`s off the stack and go back to the root.
+    /// This invalidates every `Checkpoint` of the stack.
     pub fn to_root(&mut self) -> &mut T {
-        for _ in 1..self.data.len() {
-            // We need to drop the RefMut's in the reverse order.
-            // Vec::truncate does not specify drop order, but it's probably wrong anyway.
-            self.data.pop();
-        }
+        // We need to drop the RefMut's in the reverse order, and map field hops back to their parents.
+        while self.ascend().is_some() {}
+        self.generation += 1;
         self.top_mut()
     }
+
+    /// Mark the current position, to return to it later with `rollback_to`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            depth: self.depth(),
+            generation: self.generation,
+            node: self.top_id().as_ptr().cast::<()>() as usize,
+        }
+    }
+
+    /// Ascend back to the position marked by `checkpoint`, returning a mutable reference to the new top element.
+    /// The `RefMut`s are dropped one at a time, from the top down.
+    /// Fails without ascending if the checkpoint was invalidated by `to_root`, if it is deeper than the top,
+    /// or if the stack has since ascended past it and descended somewhere else.
+    pub fn rollback_to(&mut self, checkpoint: Checkpoint) -> Result<&mut T, CheckpointError> {
+        if checkpoint.generation != self.generation {
+            return Err(CheckpointError::Stale);
+        }
+        let depth = self.depth();
+        if checkpoint.depth > depth {
+            return Err(CheckpointError::Deeper {
+                checkpoint_depth: checkpoint.depth,
+                depth,
+            });
+        }
+        let node = self.path_ids().nth(checkpoint.depth).unwrap();
+        if node.as_ptr().cast::<()>() as usize != checkpoint.node {
+            return Err(CheckpointError::Diverged);
+        }
+        let Ok(top) = self.truncate_to_depth(checkpoint.depth) else {
+            unreachable!("the checkpoint is not deeper than the top");
+        };
+        Ok(top)
+    }
+}
+
+/// A node whose children are indexed `RefCell`s of the same type, for `RefCellRefMutStack::descend_child`.
+///
+/// For the common case of a `Vec<Rc<RefCell<Self>>>` field, use `impl_refcell_children!`.
+pub trait RefCellChildren {
+    /// How many children this node has.
+    fn child_count(&self) -> usize;
+    /// The child at index `i`, or None if `i` is out of bounds.
+    fn child_cell(&self, i: usize) -> Option<&RefCell<Self>>;
+}
+
+/// Implement `RefCellChildren` for a type whose children are in a `Vec<Rc<RefCell<Self>>>` field
+/// (or any field with `len` and `get` that derefs to `RefCell<Self>`).
+///
+/// ```
+/// # use std::{cell::RefCell, rc::Rc};
+/// # use generic_cursors::{impl_refcell_children, refcell::RefCellRefMutStack};
+/// struct Node {
+///     value: u32,
+///     children: Vec<Rc<RefCell<Node>>>,
+/// }
+/// impl_refcell_children!(Node, children);
+///
+/// let node = |value, children| Rc::new(RefCell::new(Node { value, children }));
+/// let root = node(0, vec![node(1, vec![]), node(2, vec![node(3, vec![])])]);
+/// let mut stack = RefCellRefMutStack::new(&root).unwrap();
+/// assert_eq!(stack.descend_child_path(&[1, 0]).unwrap().value, 3);
+/// ```
+#[macro_export]
+macro_rules! impl_refcell_children {
+    ($ty:ty, $field:ident) => {
+        impl $crate::refcell::RefCellChildren for $ty {
+            fn child_count(&self) -> usize {
+                self.$field.len()
+            }
+
+            fn child_cell(&self, i: usize) -> Option<&::std::cell::RefCell<Self>> {
+                self.$field.get(i).map(|child| &**child)
+            }
+        }
+    };
+}
+
+/// Returned by `RefCellRefMutStack::descend_child` and `RefCellRefMutStack::descend_child_path`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ChildError {
+    /// The top has no child at `index`, because it only has `len` children.
+    OutOfBounds { index: usize, len: usize },
+    /// The child was already borrowed.
+    BorrowMutError(BorrowMutError),
+}
+
+impl fmt::Display for ChildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChildError::OutOfBounds { index, len } => {
+                write!(f, "child index {index} is out of bounds for {len} children")
+            }
+            ChildError::BorrowMutError(_) => f.write_str("the child is already borrowed"),
+        }
+    }
+}
+
+impl Error for ChildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChildError::OutOfBounds { .. } => None,
+            ChildError::BorrowMutError(err) => Some(err),
+        }
+    }
+}
+
+impl<'root, T: RefCellChildren> RefCellRefMutStack<'root, T> {
+    /// Descend into the child of the top at index `i`, returning a mutable reference to the new top element.
+    pub fn descend_child(&mut self, i: usize) -> Result<&mut T, ChildError> {
+        let len = self.top().child_count();
+        match self.descend_with(|node| node.child_cell(i)) {
+            Some(Ok(_)) => Ok(self.top_mut()),
+            Some(Err(err)) => Err(ChildError::BorrowMutError(err.source)),
+            None => Err(ChildError::OutOfBounds { index: i, len }),
+        }
+    }
+
+    /// Descend into the child at each index of `path` in turn, returning a mutable reference to the new top element.
+    /// If a step fails, the levels descended before it stay on the stack.
+    pub fn descend_child_path(&mut self, path: &[usize]) -> Result<&mut T, ChildError> {
+        for &i in path {
+            self.descend_child(i)?;
+        }
+        Ok(self.top_mut())
+    }
+}
+
+/// A node whose children are `RefCell`s of the same type looked up by key, e.g. in a
+/// `BTreeMap<K, Rc<RefCell<Self>>>`, for `RefCellRefMutStack::descend_key`.
+pub trait KeyedRefCellChildren<K> {
+    /// The child with the given key, if any.
+    fn child_cell(&self, key: &K) -> Option<&RefCell<Self>>;
+    /// Add `child` with the given key, for `RefCellRefMutStack::descend_key_or_insert`.
+    /// This is only called when there is no child with that key.
+    fn insert_child_cell(&mut self, key: K, child: Rc<RefCell<Self>>);
+}
+
+/// Returned by `RefCellRefMutStack::descend_key`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum KeyError {
+    /// The top has no child with the key.
+    Missing,
+    /// The child was already borrowed.
+    BorrowMutError(BorrowMutError),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Missing => f.write_str("the top has no child with the key"),
+            KeyError::BorrowMutError(_) => f.write_str("the child is already borrowed"),
+        }
+    }
+}
+
+impl Error for KeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            KeyError::Missing => None,
+            KeyError::BorrowMutError(err) => Some(err),
+        }
+    }
+}
+
+/// Returned by `RefCellRefMutStack::descend_key_path`, reporting which key could not be descended into.
+#[derive(Debug)]
+pub struct KeyPathError<K> {
+    /// The key that could not be descended into.
+    pub key: K,
+    /// The position of the key in the path.
+    pub index: usize,
+    /// Why the key could not be descended into.
+    pub error: KeyError,
+}
+
+impl<K: fmt::Debug> fmt::Display for KeyPathError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot descend into key {:?} at position {} of the path: {}",
+            self.key, self.index, self.error
+        )
+    }
+}
+
+impl<K: fmt::Debug> Error for KeyPathError<K> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<'root, T> RefCellRefMutStack<'root, T> {
+    /// Descend into the child of the top with the given key, returning a mutable reference to the new top element.
+    pub fn descend_key<K>(&mut self, key: &K) -> Result<&mut T, KeyError>
+    where
+        T: KeyedRefCellChildren<K>,
+    {
+        match self.descend_with(|node| KeyedRefCellChildren::child_cell(node, key)) {
+            Some(Ok(_)) => Ok(self.top_mut()),
+            Some(Err(err)) => Err(KeyError::BorrowMutError(err.source)),
+            None => Err(KeyError::Missing),
+        }
+    }
+
+    /// Descend into the child with each key of `keys` in turn, returning a mutable reference to the new top element.
+    /// If a step fails, the levels descended before it stay on the stack, so the top is the last node that was found.
+    pub fn descend_key_path<K>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<&mut T, KeyPathError<K>>
+    where
+        T: KeyedRefCellChildren<K>,
+    {
+        for (index, key) in keys.into_iter().enumerate() {
+            if let Err(error) = self.descend_key(&key) {
+                return Err(KeyPathError { key, index, error });
+            }
+        }
+        Ok(self.top_mut())
+    }
+
+    /// Descend into the child of the top with the given key, first inserting the child returned by `make`
+    /// if there is none, e.g. to build a trie.
+    /// The key is cloned to find the child again after inserting it.
+    pub fn descend_key_or_insert<K: Clone>(
+        &mut self,
+        key: K,
+        make: impl FnOnce() -> Rc<RefCell<T>>,
+    ) -> Result<&mut T, KeyError>
+    where
+        T: KeyedRefCellChildren<K>,
+    {
+        if KeyedRefCellChildren::child_cell(self.top(), &key).is_none() {
+            self.top_mut().insert_child_cell(key.clone(), make());
+        }
+        self.descend_key(&key)
+    }
+}
+
+impl<'root, T: ?Sized> RefCellRefMutStack<'root, T> {
+    /// Debug-format the stack like its `Debug` impl, but render the top with the closure, so `T` need not be `Debug`.
+    pub fn debug_with<'a>(&'a self, render: impl Fn(&T) -> String + 'a) -> impl fmt::Debug + 'a {
+        DebugWith {
+            stack: self,
+            render,
+        }
+    }
+
+    /// Helper function for the `Debug` impls, which never borrows any `RefCell`: the top is
+    /// printed through the `RefMut` the stack already holds.
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, top: &dyn fmt::Debug) -> fmt::Result {
+        let ancestors: Vec<Option<*const ()>> = self.cells[..self.cells.len() - 1]
+            .iter()
+            .map(|cell| cell.map(|cell| cell.cast::<()>()))
+            .collect();
+        f.debug_struct("RefCellRefMutStack")
+            .field("depth", &self.depth())
+            .field("ancestors", &ancestors)
+            .field("top", top)
+            .finish()
+    }
+}
+
+/// Returned by `RefCellRefMutStack::debug_with`.
+struct DebugWith<'a, 'root, T: ?Sized, F> {
+    stack: &'a RefCellRefMutStack<'root, T>,
+    render: F,
+}
+
+impl<T: ?Sized, F: Fn(&T) -> String> fmt::Debug for DebugWith<'_, '_, T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let top = (self.render)(self.stack.top());
+        self.stack.fmt_with(f, &format_args!("{top}"))
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RefCellRefMutStack<'_, T> {
+    /// Prints the depth, the addresses of the `RefCell`s below the top (None for a root adopted with `from_refmut`),
+    /// and the top itself. No `RefCell` is borrowed, so this never panics on the borrows held by the stack.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &self.top())
+    }
 }
 
 impl<'root, T: ?Sized> Drop for RefCellRefMutStack<'root, T> {
@@ -217,3 +1480,970 @@ impl<'root, T: ?Sized> Drop for RefCellRefMutStack<'root, T> {
         }
     }
 }
+
+/// A borrow of the top of a `RefCellRefMutStack`, returned by `RefCellRefMutStack::scoped_top`.
+/// Dropping it returns control to the stack, with every level still held.
+pub struct TopGuard<'a, 'root, T: ?Sized> {
+    stack: &'a mut RefCellRefMutStack<'root, T>,
+}
+
+impl<T: ?Sized> Deref for TopGuard<'_, '_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.stack.top()
+    }
+}
+
+impl<T: ?Sized> DerefMut for TopGuard<'_, '_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.stack.top_mut()
+    }
+}
+
+/// A cursor over a recursive data structure of `RefCell`s, holding a shared borrow of every node from the root to the top.
+///
+/// Unlike `RefCellRefMutStack`, this can coexist with other shared borrows of the same nodes, and can be cloned.
+/// Like `Ref`, this is neither `Send` nor `Sync`.
+pub struct RefCellRefStack<'root, T: ?Sized> {
+    /// Ensures this stack does not exceed the lifetime of its root.
+    lifetime: PhantomData<&'root T>,
+    /// The stack of borrows. Each one borrows from the one prior, except the first which is the `root` and may never be popped.
+    /// Note: the `'root` lifetime is a "lie", only used because there's no raw pointer counterpart for `Ref`.
+    /// The `Ref`s are not publicly accessible so this is fine.
+    data: Vec<Ref<'root, T>>,
+}
+
+/// Returned by `RefCellRefStack::move_with`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RefMoveError {
+    /// The closure asked to ascend, but the stack was already at its root.
+    AscendAtRoot,
+    /// The selected `RefCell` was already mutably borrowed.
+    BorrowError(BorrowError),
+}
+
+impl RefMoveError {
+    /// Did the closure ask to ascend at the root?
+    pub fn is_ascend_at_root(&self) -> bool {
+        matches!(self, RefMoveError::AscendAtRoot)
+    }
+}
+
+impl fmt::Display for RefMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefMoveError::AscendAtRoot => f.write_str("cannot ascend above the root"),
+            RefMoveError::BorrowError(_) => f.write_str("the new top is already mutably borrowed"),
+        }
+    }
+}
+
+impl Error for RefMoveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RefMoveError::AscendAtRoot => None,
+            RefMoveError::BorrowError(err) => Some(err),
+        }
+    }
+}
+
+impl<'root, T: ?Sized> RefCellRefStack<'root, T> {
+    /// Create a new RefCellRefStack from the root `RefCell` of a recursive data structure.
+    pub fn new(root: &'root RefCell<T>) -> Result<Self, BorrowError> {
+        Ok(Self::from_ref(root.try_borrow()?))
+    }
+
+    /// Create a new RefCellRefStack from an already-held shared borrow of the root of a recursive data structure.
+    /// The borrow becomes the root of the stack, and is never popped.
+    pub fn from_ref(borrow: Ref<'root, T>) -> Self {
+        Self {
+            lifetime: PhantomData,
+            data: vec![borrow],
+        }
+    }
+
+    /// Obtain a shared reference to the top of the stack.
+    pub fn top(&self) -> &T {
+        self.data.last().unwrap()
+    }
+
+    /// Obtain a raw pointer to the top of the stack, e.g. to compare node identities.
+    pub fn raw_top(&self) -> *const T {
+        self.top()
+    }
+
+    /// Is this RefCellRefStack currently at its root?
+    pub fn is_at_root(&self) -> bool {
+        self.data.len() == 1
+    }
+
+    /// How many levels above the root the top of the stack is.
+    pub fn depth(&self) -> usize {
+        self.data.len() - 1
+    }
+
+    /// Helper function to borrow `new_top` and push the borrow.
+    fn try_push(&mut self, new_top: *const RefCell<T>) -> Result<&T, BorrowError> {
+        let borrow = unsafe { (*new_top).try_borrow()? };
+        self.data.push(borrow);
+        Ok(self.top())
+    }
+
+    /// Inject a new reference to the top of the stack. The reference still must live
+    /// as long as the root of the stack.
+    pub fn inject_top(&mut self, new_top: &'root RefCell<T>) -> Result<&T, BorrowError> {
+        self.try_push(new_top)
+    }
+
+    /// Descend into the recursive data structure, returning a shared reference to the new top element.
+    /// Rust's borrow checker enforces that the closure cannot inject any lifetime (other than `'static`),
+    /// because the closure must work for any lifetime `'node`.
+    pub fn descend_with(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node T) -> Option<&'node RefCell<T>>,
+    ) -> Option<Result<&T, BorrowError>> {
+        let old_top: *const T = self.top();
+        let new_top: *const RefCell<T> = unsafe { f(&*old_top)? };
+        Some(self.try_push(new_top))
+    }
+
+    /// Ascend back up from the recursive data structure, returning a shared reference to the new top element, if it changed.
+    /// If we are not currently at the root, ascend and return a reference to the new top.
+    /// If we are already at the root, returns None (the top is the root and does not change).
+    pub fn ascend(&mut self) -> Option<&T> {
+        match self.data.len() {
+            0 => unreachable!("root pointer must always exist"),
+            1 => None,
+            _ => {
+                self.data.pop();
+                Some(self.top())
+            }
+        }
+    }
+
+    /// Ascend back up from the recursive data structure while the given closure returns `true`, returning a shared reference to the new top element.
+    /// If we are not currently at the root, and the predicate returns `true`, ascend and continue.
+    /// If we are already at the root, or if the predicate returned false, returns a reference to the top element.
+    pub fn ascend_while<P>(&mut self, mut predicate: P) -> &T
+    where
+        P: FnMut(&T) -> bool,
+    {
+        while !self.is_at_root() && predicate(self.top()) {
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        self.top()
+    }
+
+    /// Ascend from, descend from, inject a new stack top, or stay at the current node,
+    /// based on the return value of the closure.
+    pub fn move_with<F>(&mut self, f: F) -> Result<&T, RefMoveError>
+    where
+        F: for<'a> FnOnce(&'a T) -> MoveDecision<'root, 'a, T>,
+    {
+        let old_top: *const T = self.top();
+        let result = unsafe { f(&*old_top) };
+        match result {
+            MoveDecision::Ascend => self.ascend().ok_or(RefMoveError::AscendAtRoot),
+            MoveDecision::Stay | MoveDecision::Finish(()) => Ok(self.top()),
+            MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
+                let new_top: *const RefCell<T> = new_top;
+                self.try_push(new_top).map_err(RefMoveError::BorrowError)
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for RefCellRefStack<'_, T> {
+    /// Clone every `Ref` on the stack, so both stacks share the borrows.
+    fn clone(&self) -> Self {
+        Self {
+            lifetime: PhantomData,
+            data: self.data.iter().map(Ref::clone).collect(),
+        }
+    }
+}
+
+impl<'root, T: ?Sized> Drop for RefCellRefStack<'root, T> {
+    fn drop(&mut self) {
+        for _ in 0..self.data.len() {
+            // We need to drop the Ref's in the reverse order.
+            self.data.pop();
+        }
+    }
+}
+
+/// A set of cursors over one recursive data structure of `RefCell`s: any number of read cursors,
+/// or one write cursor.
+///
+/// Every cursor holds a borrow of the root, so, exactly like the `RefCell` borrow rules, the write cursor
+/// can only be opened while no read cursor is open, and read cursors can only be opened while the write cursor is closed.
+/// The set reports which cursors are in the way, instead of a bare `BorrowError` or `BorrowMutError`.
+pub struct CursorSet<'root, T: ?Sized> {
+    root: &'root RefCell<T>,
+    write: Option<RefCellRefMutStack<'root, T>>,
+    reads: Vec<RefCellRefStack<'root, T>>,
+}
+
+/// Returned by `CursorSet::read_cursor`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReadCursorError {
+    /// The write cursor is open, so nothing can be borrowed.
+    WriteCursorOpen,
+    /// The node at `depth` of the path has no child at the requested index.
+    OutOfBounds { depth: usize },
+    /// A node on the path was already mutably borrowed, outside of this set.
+    BorrowError(BorrowError),
+}
+
+impl fmt::Display for ReadCursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadCursorError::WriteCursorOpen => f.write_str("the write cursor is open"),
+            ReadCursorError::OutOfBounds { depth } => {
+                write!(f, "the node at depth {depth} of the path has no such child")
+            }
+            ReadCursorError::BorrowError(_) => {
+                f.write_str("a node on the path is already mutably borrowed")
+            }
+        }
+    }
+}
+
+impl Error for ReadCursorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReadCursorError::BorrowError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Returned by `CursorSet::write_cursor` when read cursors are open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteConflict {
+    /// The depth of every open read cursor, in the order they were opened.
+    pub read_depths: Vec<usize>,
+}
+
+impl fmt::Display for WriteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot open the write cursor while read cursors are open at depths {:?}",
+            self.read_depths
+        )
+    }
+}
+
+impl Error for WriteConflict {}
+
+impl<'root, T: ?Sized> CursorSet<'root, T> {
+    /// Create a new CursorSet over the root `RefCell` of a recursive data structure, with no cursors open.
+    pub fn new(root: &'root RefCell<T>) -> Self {
+        Self {
+            root,
+            write: None,
+            reads: Vec::new(),
+        }
+    }
+
+    /// The open read cursors, in the order they were opened.
+    pub fn read_cursors(&self) -> &[RefCellRefStack<'root, T>] {
+        &self.reads
+    }
+
+    /// Close the read cursor at `index` of `read_cursors`, returning false if there is none.
+    pub fn close_read_cursor(&mut self, index: usize) -> bool {
+        if index >= self.reads.len() {
+            return false;
+        }
+        self.reads.remove(index);
+        true
+    }
+
+    /// Obtain the write cursor, opening it at the root if it is not open yet.
+    /// Fails while any read cursor is open.
+    /// A write cursor that cannot borrow the root because of a borrow outside of this set panics, like `RefCell::borrow_mut`.
+    pub fn write_cursor(&mut self) -> Result<&mut RefCellRefMutStack<'root, T>, WriteConflict> {
+        if !self.reads.is_empty() {
+            return Err(WriteConflict {
+                read_depths: self.reads.iter().map(RefCellRefStack::depth).collect(),
+            });
+        }
+        Ok(self.write.get_or_insert_with(|| {
+            RefCellRefMutStack::new(self.root).expect("the root is already borrowed")
+        }))
+    }
+
+    /// Close the write cursor, returning false if it was not open.
+    pub fn close_write_cursor(&mut self) -> bool {
+        self.write.take().is_some()
+    }
+}
+
+impl<'root, T: RefCellChildren> CursorSet<'root, T> {
+    /// Open a read cursor at the node reached by descending from the root into the child at each index of `path`,
+    /// returning a reference to it. Fails while the write cursor is open.
+    pub fn read_cursor(
+        &mut self,
+        path: &[usize],
+    ) -> Result<&RefCellRefStack<'root, T>, ReadCursorError> {
+        if self.write.is_some() {
+            return Err(ReadCursorError::WriteCursorOpen);
+        }
+        let mut cursor = RefCellRefStack::new(self.root).map_err(ReadCursorError::BorrowError)?;
+        for (depth, &i) in path.iter().enumerate() {
+            match cursor.descend_with(|node| node.child_cell(i)) {
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(ReadCursorError::BorrowError(err)),
+                None => return Err(ReadCursorError::OutOfBounds { depth }),
+            }
+        }
+        self.reads.push(cursor);
+        Ok(self.reads.last().unwrap())
+    }
+}
+
+/// A cursor over a recursive data structure of `RefCell`s whose nodes may have a different type at every level,
+/// e.g. `Document` → `Section` → `Paragraph`.
+///
+/// Like `RefCellRefMutStack`, this holds a mutable borrow of every `RefCell` from the root to the top,
+/// and like `RefCellRefMutStack::descend_map_with`, a level may also be a plain field of its parent.
+/// The type of the top is checked at runtime, so every node type must be `'static`.
+/// This is neither `Send` nor `Sync`.
+pub struct AnyRefMutStack<'root> {
+    /// Ensures this stack does not exceed the lifetime of its root.
+    lifetime: PhantomData<&'root mut ()>,
+    /// The stack of levels. Each one borrows from the one prior, except the first which is the `root` and may never be popped.
+    levels: Vec<AnyLevel<'root>>,
+}
+
+/// One level of an `AnyRefMutStack`.
+struct AnyLevel<'root> {
+    /// The node, whose type is `type_id`.
+    /// All accesses to the node go through this pointer, not through `guard`.
+    node: *mut (),
+    type_id: TypeId,
+    type_name: &'static str,
+    /// The `RefMut` of the node, or None if the node is a plain field of its parent and shares its `RefMut`.
+    /// Note: the `'root` lifetime is a "lie", like in `RefCellRefMutStack`. It is only held to be dropped.
+    _guard: Option<Box<dyn ErasedGuard + 'root>>,
+}
+
+impl<'root> AnyLevel<'root> {
+    fn new<N: 'static>(node: *mut N, guard: Option<Box<dyn ErasedGuard + 'root>>) -> Self {
+        Self {
+            node: node.cast(),
+            type_id: TypeId::of::<N>(),
+            type_name: type_name::<N>(),
+            _guard: guard,
+        }
+    }
+}
+
+/// A type-erased `RefMut`, which is only ever dropped.
+trait ErasedGuard {}
+
+impl<T: ?Sized> ErasedGuard for RefMut<'_, T> {}
+
+/// Returned by `AnyRefMutStack` when the top of the stack is not of the requested type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeMismatch {
+    /// The name of the requested type.
+    pub expected: &'static str,
+    /// The name of the type of the top of the stack.
+    pub found: &'static str,
+}
+
+impl fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the top is a {}, not a {}", self.found, self.expected)
+    }
+}
+
+impl Error for TypeMismatch {}
+
+/// Returned by `AnyRefMutStack::descend_cell_with`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AnyDescendError {
+    /// The top of the stack was not of the requested type.
+    TypeMismatch(TypeMismatch),
+    /// The selected `RefCell` was already borrowed.
+    BorrowMutError(BorrowMutError),
+}
+
+impl fmt::Display for AnyDescendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnyDescendError::TypeMismatch(err) => err.fmt(f),
+            AnyDescendError::BorrowMutError(_) => f.write_str("the new top is already borrowed"),
+        }
+    }
+}
+
+impl Error for AnyDescendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AnyDescendError::TypeMismatch(err) => Some(err),
+            AnyDescendError::BorrowMutError(err) => Some(err),
+        }
+    }
+}
+
+impl<'root> AnyRefMutStack<'root> {
+    /// Create a new AnyRefMutStack from the root `RefCell` of a recursive data structure.
+    pub fn new<R: 'static>(root: &'root RefCell<R>) -> Result<Self, BorrowMutError> {
+        let mut borrow = root.try_borrow_mut()?;
+        let node: *mut R = &mut *borrow;
+        Ok(Self {
+            lifetime: PhantomData,
+            levels: vec![AnyLevel::new(node, Some(Box::new(borrow)))],
+        })
+    }
+
+    fn top_level(&self) -> &AnyLevel<'root> {
+        self.levels
+            .last()
+            .expect("root level should never be popped")
+    }
+
+    /// Helper function to get the top as a `C`.
+    fn raw_top<C: 'static>(&self) -> Result<*mut C, TypeMismatch> {
+        let top = self.top_level();
+        if top.type_id == TypeId::of::<C>() {
+            Ok(top.node.cast())
+        } else {
+            Err(TypeMismatch {
+                expected: type_name::<C>(),
+                found: top.type_name,
+            })
+        }
+    }
+
+    /// Is the top of the stack a `C`?
+    pub fn top_is<C: 'static>(&self) -> bool {
+        self.top_level().type_id == TypeId::of::<C>()
+    }
+
+    /// The `TypeId` of the top of the stack.
+    pub fn top_type_id(&self) -> TypeId {
+        self.top_level().type_id
+    }
+
+    /// Obtain a shared reference to the top of the stack, if it is a `C`.
+    pub fn top<C: 'static>(&self) -> Result<&C, TypeMismatch> {
+        let ptr = self.raw_top::<C>()?;
+        Ok(unsafe { &*ptr })
+    }
+
+    /// Obtain a mutable reference to the top of the stack, if it is a `C`.
+    pub fn top_mut<C: 'static>(&mut self) -> Result<&mut C, TypeMismatch> {
+        let ptr = self.raw_top::<C>()?;
+        Ok(unsafe { &mut *ptr })
+    }
+
+    /// Is this AnyRefMutStack currently at its root?
+    pub fn is_at_root(&self) -> bool {
+        self.levels.len() == 1
+    }
+
+    /// How many levels above the root the top of the stack is.
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// Descend into a node that is not in its own `RefCell`, such as a plain field of the top,
+    /// returning a mutable reference to the new top element.
+    /// The top must be a `C`, otherwise the closure is not called.
+    pub fn descend_map_with<C: 'static, N: 'static>(
+        &mut self,
+        f: impl FnOnce(&mut C) -> &mut N,
+    ) -> Result<&mut N, TypeMismatch> {
+        let old_top = self.raw_top::<C>()?;
+        let new_top: *mut N = f(unsafe { &mut *old_top });
+        self.levels.push(AnyLevel::new(new_top, None));
+        Ok(unsafe { &mut *new_top })
+    }
+
+    /// Descend into a `RefCell` selected by the closure, returning a mutable reference to the new top element.
+    /// Rust's borrow checker enforces that the closure cannot inject any lifetime (other than `'static`),
+    /// because the closure must work for any lifetime `'node`.
+    /// The top must be a `C`, otherwise the closure is not called.
+    pub fn descend_cell_with<C: 'static, N: 'static>(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut C) -> Option<&'node RefCell<N>>,
+    ) -> Option<Result<&mut N, AnyDescendError>> {
+        let old_top = match self.raw_top::<C>() {
+            Ok(old_top) => old_top,
+            Err(err) => return Some(Err(AnyDescendError::TypeMismatch(err))),
+        };
+        let new_top: *const RefCell<N> = unsafe { f(&mut *old_top)? };
+        let mut borrow = match unsafe { (*new_top).try_borrow_mut() } {
+            Ok(borrow) => borrow,
+            Err(err) => return Some(Err(AnyDescendError::BorrowMutError(err))),
+        };
+        let node: *mut N = &mut *borrow;
+        self.levels
+            .push(AnyLevel::new(node, Some(Box::new(borrow))));
+        Some(Ok(unsafe { &mut *node }))
+    }
+
+    /// Ascend back up from the recursive data structure, returning whether the top changed.
+    /// If we are already at the root, returns false (the top is the root and does not change).
+    pub fn ascend(&mut self) -> bool {
+        match self.levels.len() {
+            0 => unreachable!("root pointer must always exist"),
+            1 => false,
+            _ => {
+                self.levels.pop();
+                true
+            }
+        }
+    }
+
+    /// Pop every level off the stack and go back to the root, returning how many were popped
+    /// (the depth of the stack beforehand).
+    pub fn ascend_to_root(&mut self) -> usize {
+        let depth = self.depth();
+        while self.ascend() {}
+        depth
+    }
+}
+
+impl Drop for AnyRefMutStack<'_> {
+    fn drop(&mut self) {
+        for _ in 0..self.levels.len() {
+            // We need to drop the RefMut's in the reverse order.
+            self.levels.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Node {
+        value: u32,
+        next: Option<Rc<RefCell<Node>>>,
+    }
+
+    /// A list of `len` nodes with values `0..len`, returning every node from the head.
+    fn list(len: u32) -> Vec<Rc<RefCell<Node>>> {
+        let mut nodes: Vec<Rc<RefCell<Node>>> = vec![];
+        for value in (0..len).rev() {
+            let next = nodes.last().cloned();
+            nodes.push(Rc::new(RefCell::new(Node { value, next })));
+        }
+        nodes.reverse();
+        nodes
+    }
+
+    fn descend_all(stack: &mut RefCellRefMutStack<'_, Node>) {
+        while let Some(top) = stack.descend_with(|node| node.next.as_deref()) {
+            top.unwrap();
+        }
+    }
+
+    #[test]
+    fn into_root_releases_every_child() {
+        let nodes = list(4);
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        assert_eq!(stack.depth(), 3);
+        assert!(nodes.iter().all(|node| node.try_borrow().is_err()));
+        let root = stack.into_root();
+        assert_eq!(root.value, 0);
+        assert!(nodes[0].try_borrow().is_err());
+        for node in &nodes[1..] {
+            drop(node.try_borrow_mut().unwrap());
+        }
+        drop(root);
+        drop(nodes[0].try_borrow_mut().unwrap());
+    }
+
+    /// A node that records its id in a shared log when it is dropped.
+    #[derive(Debug)]
+    struct Logged {
+        id: usize,
+        log: Rc<RefCell<Vec<usize>>>,
+    }
+
+    impl Drop for Logged {
+        fn drop(&mut self) {
+            self.log.borrow_mut().push(self.id);
+        }
+    }
+
+    /// Inject the levels `1..=depth` as owned nodes, so each node is dropped as soon as its level is popped.
+    fn inject_logged(stack: &mut RefCellRefMutStack<'_, Logged>, depth: usize) {
+        for id in 1..=depth {
+            let log = stack.top().log.clone();
+            let node = Rc::new(RefCell::new(Logged { id, log }));
+            stack.inject_owned(node).unwrap();
+        }
+    }
+
+    #[test]
+    fn truncate_to_depth_releases_from_the_top_down() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let root = RefCell::new(Logged {
+            id: 0,
+            log: log.clone(),
+        });
+        let mut stack = RefCellRefMutStack::new(&root).unwrap();
+        inject_logged(&mut stack, 5);
+        assert_eq!(stack.depth(), 5);
+        assert_eq!(
+            stack.truncate_to_depth(6).unwrap_err(),
+            ToDepthError {
+                target: 6,
+                depth: 5
+            }
+        );
+        assert_eq!(stack.truncate_to_depth(2).unwrap().id, 2);
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(*log.borrow(), [5, 4, 3]);
+        assert_eq!(stack.truncate_to_depth(2).unwrap().id, 2);
+        assert_eq!(*log.borrow(), [5, 4, 3]);
+        stack.truncate_to_depth(0).unwrap();
+        assert_eq!(*log.borrow(), [5, 4, 3, 2, 1]);
+        assert!(stack.is_at_root());
+    }
+
+    #[test]
+    fn ascend_n_releases_from_the_top_down() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let root = RefCell::new(Logged {
+            id: 0,
+            log: log.clone(),
+        });
+        let mut stack = RefCellRefMutStack::new(&root).unwrap();
+        inject_logged(&mut stack, 4);
+        assert!(stack.ascend_n(5).is_none());
+        assert_eq!(stack.depth(), 4);
+        assert!(log.borrow().is_empty());
+        assert_eq!(stack.ascend_n(0).unwrap().id, 4);
+        assert_eq!(stack.ascend_n(3).unwrap().id, 1);
+        assert_eq!(*log.borrow(), [4, 3, 2]);
+        assert_eq!(stack.ascend_n(1).unwrap().id, 0);
+        assert_eq!(*log.borrow(), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn truncate_to_depth_releases_borrowed_children() {
+        let nodes = list(4);
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        assert_eq!(stack.truncate_to_depth(1).unwrap().value, 1);
+        assert!(nodes[..2].iter().all(|node| node.try_borrow().is_err()));
+        assert!(nodes[2..].iter().all(|node| node.try_borrow_mut().is_ok()));
+    }
+
+    #[test]
+    fn shrink_to_fit_after_ascending() {
+        let nodes = list(40);
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        stack.to_root();
+        assert!(stack.capacity() >= 40);
+        stack.shrink_to_fit();
+        assert_eq!(stack.depth(), 0);
+        assert!(stack.capacity() < 40);
+        assert!(stack.cells.capacity() < 40);
+        assert_eq!(stack.top().value, 0);
+        stack
+            .descend_with(|node| node.next.as_deref())
+            .unwrap()
+            .unwrap();
+        assert_eq!(stack.top().value, 1);
+    }
+
+    #[test]
+    fn shrink_to_fit_releases_owned_storage() {
+        let root = RefCell::new(Logged {
+            id: 0,
+            log: Rc::default(),
+        });
+        let mut stack = RefCellRefMutStack::new(&root).unwrap();
+        inject_logged(&mut stack, 40);
+        stack.to_root();
+        stack.shrink_to_fit();
+        assert!(stack.owned.capacity() < 40);
+        assert!(stack.cells.capacity() < 40);
+    }
+
+    #[test]
+    fn ascend_while_result_stops_at_the_error() {
+        let nodes = list(5);
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        let result = stack.ascend_while_result(|node| match node.value {
+            2 => Err(node.value),
+            _ => Ok(true),
+        });
+        assert_eq!(result.map(|top| top.value), Err(2));
+        assert_eq!(stack.depth(), 2);
+        assert!(nodes[2].try_borrow().is_err());
+        drop(nodes[3].try_borrow_mut().unwrap());
+    }
+
+    #[test]
+    fn ascend_while_result_reaches_the_root() {
+        let nodes = list(5);
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        descend_all(&mut stack);
+        let mut visited = vec![];
+        let top = stack.ascend_while_result(|node| {
+            visited.push(node.value);
+            Ok::<_, ()>(true)
+        });
+        assert_eq!(top.unwrap().value, 0);
+        assert!(stack.is_at_root());
+        assert_eq!(visited, [4, 3, 2, 1]);
+        drop(nodes[1].try_borrow_mut().unwrap());
+    }
+
+    #[test]
+    fn descend_into_a_cycle_reports_the_depth() {
+        let node = Rc::new(RefCell::new(Node {
+            value: 0,
+            next: None,
+        }));
+        node.borrow_mut().next = Some(node.clone());
+        let mut stack = RefCellRefMutStack::new(&node).unwrap();
+        let Some(Err(err)) = stack.descend_with(|node| node.next.as_deref()) else {
+            panic!("descending into the cycle should fail");
+        };
+        assert_eq!(err.at_depth, 1);
+        assert!(stack.is_at_root());
+        stack.top_mut().next = None;
+        drop(stack);
+
+        let nodes = list(3);
+        nodes[2].borrow_mut().next = Some(nodes[0].clone());
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        for _ in 0..2 {
+            stack
+                .descend_with(|node| node.next.as_deref())
+                .unwrap()
+                .unwrap();
+        }
+        let Some(Err(err)) = stack.descend_with(|node| node.next.as_deref()) else {
+            panic!("descending into the cycle should fail");
+        };
+        assert_eq!(err.at_depth, 3);
+        assert_eq!(
+            err.to_string(),
+            "the RefCell at depth 3 is already borrowed"
+        );
+        assert_eq!(stack.depth(), 2);
+        stack.top_mut().next = None;
+    }
+
+    #[test]
+    fn ref_stack_alongside_a_ref_mut() {
+        let nodes = list(3);
+        let mut held = nodes[2].borrow_mut();
+        let mut stack = RefCellRefStack::new(&nodes[0]).unwrap();
+        let top = stack.descend_with(|node| node.next.as_deref()).unwrap();
+        assert_eq!(top.unwrap().value, 1);
+        // The node below the stack can still be modified through the separate `RefMut`...
+        held.value = 20;
+        // ...but the stack cannot borrow it while the `RefMut` is alive.
+        assert!(stack
+            .descend_with(|node| node.next.as_deref())
+            .unwrap()
+            .is_err());
+        assert_eq!(stack.depth(), 1);
+        drop(held);
+
+        let top = stack.descend_with(|node| node.next.as_deref()).unwrap();
+        assert_eq!(top.unwrap().value, 20);
+        // The nodes on the stack can be shared, but not mutably borrowed, alongside it.
+        assert_eq!(nodes[1].borrow().value, 1);
+        assert!(nodes[1].try_borrow_mut().is_err());
+        stack.ascend().unwrap();
+        drop(nodes[2].try_borrow_mut().unwrap());
+    }
+
+    #[test]
+    fn with_top_shared_failures() {
+        let nodes = list(3);
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        let mut called = false;
+        let result = stack.with_top_shared(|_| called = true);
+        assert!(matches!(result, Err(WithTopSharedError::NoCell)));
+        assert!(!called);
+
+        descend_all(&mut stack);
+        // The closure keeps a shared borrow of the top alive, so it cannot be borrowed mutably again.
+        let result = stack.with_top_shared(|top| {
+            assert_eq!(top.value, 2);
+            nodes[2].borrow()
+        });
+        let Err(WithTopSharedError::Reborrow { result, .. }) = result else {
+            panic!("the top should still be borrowed");
+        };
+        assert_eq!(result.value, 2);
+        assert_eq!(stack.depth(), 1);
+        assert_eq!(stack.top().value, 1);
+        assert!(nodes[2].try_borrow_mut().is_err());
+        drop(result);
+        drop(nodes[2].try_borrow_mut().unwrap());
+        assert_eq!(stack.ascend_while(|_| true).value, 0);
+    }
+
+    #[test]
+    fn try_descend_outcomes() {
+        let nodes = list(3);
+        let mut stack = RefCellRefMutStack::new(&nodes[0]).unwrap();
+        let result = stack.try_descend(|node| node.next.as_deref());
+        assert!(matches!(result, DescendResult::Descended(top) if top.value == 1));
+        let held = nodes[2].borrow();
+        let result = stack.try_descend(|node| node.next.as_deref());
+        assert!(matches!(result, DescendResult::LockFailed(err) if err.at_depth == 2));
+        assert_eq!(stack.depth(), 1);
+        drop(held);
+        let result = stack.try_descend(|node| node.next.as_deref());
+        assert!(matches!(result, DescendResult::Descended(top) if top.value == 2));
+        let result = stack.try_descend(|node| node.next.as_deref());
+        assert!(matches!(result, DescendResult::NoChild));
+        assert_eq!(stack.depth(), 2);
+    }
+
+    #[derive(Debug)]
+    struct Branch {
+        value: u32,
+        children: Vec<Rc<RefCell<Branch>>>,
+    }
+
+    impl_refcell_children!(Branch, children);
+
+    /// ```text
+    /// 0
+    /// ├── 1
+    /// └── 2
+    ///     └── 3
+    /// ```
+    fn branches() -> Rc<RefCell<Branch>> {
+        let node = |value, children| Rc::new(RefCell::new(Branch { value, children }));
+        node(0, vec![node(1, vec![]), node(2, vec![node(3, vec![])])])
+    }
+
+    #[test]
+    fn descend_child_path_errors() {
+        let root = branches();
+        let two = root.borrow().children[1].clone();
+        let mut stack = RefCellRefMutStack::new(&root).unwrap();
+        assert_eq!(stack.descend_child_path(&[1, 0]).unwrap().value, 3);
+        assert_eq!(stack.descend_child_path(&[]).unwrap().value, 3);
+        stack.to_root();
+
+        // The levels before the failing step stay on the stack.
+        let err = stack.descend_child_path(&[1, 5, 0]).unwrap_err();
+        assert!(matches!(err, ChildError::OutOfBounds { index: 5, len: 1 }));
+        assert_eq!(
+            err.to_string(),
+            "child index 5 is out of bounds for 1 children"
+        );
+        assert_eq!(stack.top().value, 2);
+        stack.to_root();
+
+        let held = two.borrow_mut();
+        let err = stack.descend_child_path(&[1]).unwrap_err();
+        assert!(matches!(err, ChildError::BorrowMutError(_)));
+        assert!(stack.is_at_root());
+        drop(held);
+        assert_eq!(stack.descend_child(0).unwrap().value, 1);
+    }
+
+    #[derive(Debug, Default)]
+    struct Trie {
+        words: usize,
+        children: std::collections::BTreeMap<char, Rc<RefCell<Trie>>>,
+    }
+
+    impl KeyedRefCellChildren<char> for Trie {
+        fn child_cell(&self, key: &char) -> Option<&RefCell<Self>> {
+            self.children.get(key).map(|child| &**child)
+        }
+
+        fn insert_child_cell(&mut self, key: char, child: Rc<RefCell<Self>>) {
+            self.children.insert(key, child);
+        }
+    }
+
+    #[test]
+    fn descend_key_or_insert_builds_a_trie() {
+        let root = RefCell::new(Trie::default());
+        let mut stack = RefCellRefMutStack::new(&root).unwrap();
+        let mut made = 0;
+        for word in ["to", "tea", "ten", "to"] {
+            for key in word.chars() {
+                stack
+                    .descend_key_or_insert(key, || {
+                        made += 1;
+                        Rc::default()
+                    })
+                    .unwrap();
+            }
+            stack.top_mut().words += 1;
+            stack.to_root();
+        }
+        // t, o, e, a, n
+        assert_eq!(made, 5);
+        assert_eq!(stack.descend_key_path("to".chars()).unwrap().words, 2);
+        stack.to_root();
+        assert_eq!(stack.descend_key_path("te".chars()).unwrap().words, 0);
+        assert_eq!(stack.descend_key(&'n').unwrap().words, 1);
+    }
+
+    #[test]
+    fn descend_key_path_errors() {
+        let root = RefCell::new(Trie::default());
+        let mut stack = RefCellRefMutStack::new(&root).unwrap();
+        for key in "tea".chars() {
+            stack.descend_key_or_insert(key, Rc::default).unwrap();
+        }
+        stack.to_root();
+
+        // The levels before the missing key stay on the stack.
+        let err = stack.descend_key_path("tax".chars()).unwrap_err();
+        assert!(matches!(
+            err,
+            KeyPathError {
+                key: 'a',
+                index: 1,
+                error: KeyError::Missing
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "cannot descend into key 'a' at position 1 of the path: the top has no child with the key"
+        );
+        assert_eq!(stack.depth(), 1);
+
+        let e = stack.top().children[&'e'].clone();
+        let held = e.borrow_mut();
+        let err = stack.descend_key_path(['e', 'a']).unwrap_err();
+        assert!(matches!(
+            err,
+            KeyPathError {
+                key: 'e',
+                index: 0,
+                error: KeyError::BorrowMutError(_)
+            }
+        ));
+        assert_eq!(stack.depth(), 1);
+        drop(held);
+        assert_eq!(stack.descend_key_path(['e', 'a']).unwrap().words, 0);
+        assert_eq!(stack.depth(), 3);
+    }
+}
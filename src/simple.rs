@@ -1,23 +1,162 @@
-use std::{future::Future, marker::PhantomData, pin::Pin};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+};
 
+pub use crate::{AsyncMove, CyclePolicy, MoveDecisionFor};
+use crate::{DescendCtx, TopId};
+
+/// A cursor over a recursive data structure, holding a mutable reference to every node from the root to the top.
+///
+/// Like `&mut T`, this is `Send` when `T` is `Send`, and `Sync` when `T` is `Sync`.
 pub struct MutRefStack<'root, T: ?Sized> {
     /// Ensures this mutrefstack does not exceed the lifetime of its root.
     lifetime: PhantomData<&'root mut T>,
     /// The stack of pointers. Each one borrows from the one prior, except the first which is the `root` and may never be popped.
     data: Vec<*mut T>,
+    /// The pointers to nodes descended into by `descend_with_cycle_check`, including their metadata,
+    /// so that e.g. a slice and its own prefix are different nodes.
+    /// `HashSet::new` does not allocate, so this costs nothing until it is first used.
+    visited: HashSet<*const T>,
 }
 
+// SAFETY: The stack is semantically a list of `&'root mut T`, which is `Send` when `T` is `Send`.
+unsafe impl<T: ?Sized + Send> Send for MutRefStack<'_, T> {}
+// SAFETY: A shared `&MutRefStack` only gives out `&T` (and addresses), so it is `Sync` when `T` is `Sync`.
+unsafe impl<T: ?Sized + Sync> Sync for MutRefStack<'_, T> {}
+
 pub enum MoveDecision<'root, 'this, T: ?Sized> {
     Ascend,
     Stay,
     Descend(&'this mut T),
     Inject(&'root mut T),
+    /// Ascend, then descend into the node returned by the closure when it is called with the parent,
+    /// e.g. to move to a sibling of the current top.
+    /// The current top is popped before the closure is called, so the closure may not borrow from it
+    /// (it must outlive `'root`), but anything it needs can be copied into it.
+    /// If the closure returns None, the stack stays at the parent.
+    /// Fails with `MoveError::AscendAtRoot` at the root, which has no parent.
+    DescendFromParent(FromParent<'root, T>),
+}
+
+/// The closure of `MoveDecision::DescendFromParent`.
+pub type FromParent<'root, T> =
+    Box<dyn for<'parent> FnOnce(&'parent mut T) -> Option<&'parent mut T> + 'root>;
+
+impl<'root, 'this, T: ?Sized> MoveDecision<'root, 'this, T> {
+    /// Descend into `node`. Equivalent to `MoveDecision::Descend(node)`.
+    pub fn descend(node: &'this mut T) -> Self {
+        MoveDecision::Descend(node)
+    }
+
+    /// Inject `node` as the new top. Equivalent to `MoveDecision::Inject(node)`.
+    pub fn inject(node: &'root mut T) -> Self {
+        MoveDecision::Inject(node)
+    }
 }
 
+impl<T: ?Sized> crate::sealed::Decision for MoveDecision<'_, '_, T> {}
+
+impl<'root, 'a, T: ?Sized> MoveDecisionFor<'a, T> for MoveDecision<'root, 'root, T> {
+    type Decision = MoveDecision<'root, 'a, T>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum MoveError {
+    /// The closure asked to ascend, but the stack was already at its root.
     AscendAtRoot,
 }
 
+impl MoveError {
+    /// Did the closure ask to ascend at the root?
+    pub fn is_ascend_at_root(&self) -> bool {
+        matches!(self, MoveError::AscendAtRoot)
+    }
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::AscendAtRoot => f.write_str("cannot ascend above the root"),
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+/// Returned by `MutRefStack::try_move_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryMoveError<E> {
+    /// The closure failed, so the cursor did not move.
+    User(E),
+    /// The closure's decision could not be carried out.
+    Move(MoveError),
+}
+
+impl<E> From<MoveError> for TryMoveError<E> {
+    fn from(err: MoveError) -> Self {
+        TryMoveError::Move(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for TryMoveError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryMoveError::User(err) => err.fmt(f),
+            TryMoveError::Move(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for TryMoveError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TryMoveError::User(err) => Some(err),
+            TryMoveError::Move(err) => Some(err),
+        }
+    }
+}
+
+/// Returned by `MutRefStack::descend_with_cycle_check` when the descend target was already seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CycleDetected {
+    /// The depth the repeated node would have been pushed at (the root is at depth 0).
+    pub depth: usize,
+}
+
+/// Returned by `MutRefStack::replace_root` when the stack is not at its root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotAtRoot {
+    /// How many levels above the root the top of the stack is.
+    pub depth: usize,
+}
+
+impl fmt::Display for NotAtRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the stack is {} levels above its root", self.depth)
+    }
+}
+
+impl Error for NotAtRoot {}
+
+/// Returned by `MutRefStack::graft_with` when the sub-stack is not rooted at the top of the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootMismatch;
+
+impl fmt::Display for RootMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the sub-stack is not rooted at the top of the stack")
+    }
+}
+
+impl Error for RootMismatch {}
+
 impl<'root, T: ?Sized> MutRefStack<'root, T> {
     /// Create a new MutRefStack from a mutable reference to the root
     /// of a recursive data structure.
@@ -25,34 +164,134 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
         Self {
             lifetime: PhantomData,
             data: vec![root],
+            visited: HashSet::new(),
         }
     }
 
+    /// Create a MutRefStack from the pointers of every level, from the root to the top,
+    /// e.g. as returned by `into_raw_parts`.
+    ///
+    /// # Safety
+    ///
+    /// - `data` must not be empty.
+    /// - The first pointer must be valid for reads and writes for `'root`, and no other access to the node
+    ///   (or anything borrowed from it) may happen during `'root`, except through the stack, exactly as if it were a `&'root mut T`.
+    /// - Every other pointer must be derived from the one before it, as if it had been pushed by `descend_with`,
+    ///   or else satisfy the same requirements as the first pointer, as if it had been pushed by `inject_top`.
+    /// - The nodes must not have been moved or dropped since the pointers were obtained.
+    pub unsafe fn from_raw_parts(data: Vec<*mut T>) -> Self {
+        debug_assert!(!data.is_empty(), "the root pointer is required");
+        Self {
+            lifetime: PhantomData,
+            data,
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Return the pointers of every level, from the root to the top, forgetting about the stack entirely.
+    /// The nodes recorded by `descend_with_cycle_check` are forgotten.
+    /// The result always has `depth() + 1` elements, and can be turned back into a stack with `from_raw_parts`.
+    pub fn into_raw_parts(self) -> Vec<*mut T> {
+        self.data
+    }
+
     /// Helper function to get the raw top pointer.
-    fn raw_top(&self) -> *mut T {
+    fn top_ptr(&self) -> *mut T {
         self.data
             .last()
             .copied()
             .expect("root pointer should never be popped")
     }
 
+    /// Obtain a raw pointer to the top of the stack, e.g. to compare node identities.
+    pub fn raw_top(&self) -> *const T {
+        self.top_ptr()
+    }
+
     /// Obtain a shared reference to the top of the stack.
     pub fn top(&self) -> &T {
-        let ptr: *const T = self.raw_top();
+        let ptr: *const T = self.top_ptr();
         unsafe { &(*ptr) }
     }
 
     /// Obtain a mutable reference to the top of the stack.
     pub fn top_mut(&mut self) -> &mut T {
-        let ptr: *mut T = self.raw_top();
+        let ptr: *mut T = self.top_ptr();
         unsafe { &mut (*ptr) }
     }
 
+    /// Obtain a mutable reference to the top of the stack, like `top_mut`, without checking that the stack is non-empty.
+    ///
+    /// # Safety
+    ///
+    /// The stack must not be empty. The root is never popped, so this always holds;
+    /// this is only `unsafe` to signal that the check is skipped.
+    pub unsafe fn top_mut_unchecked(&mut self) -> &mut T {
+        let ptr: *mut T = unsafe { *self.data.get_unchecked(self.data.len() - 1) };
+        unsafe { &mut *ptr }
+    }
+
     /// Is this MutRefStack currently at its root?
     pub fn is_at_root(&self) -> bool {
         self.data.len() == 1
     }
 
+    /// How many pointers the stack can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+
+    /// Shrink the stack's storage to fit its current contents.
+    /// The stack always keeps at least the root.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+    }
+
+    /// How many levels above the root the top of the stack is.
+    pub fn depth(&self) -> usize {
+        self.data.len() - 1
+    }
+
+    /// Information about the current position, for the `*_with_ctx` methods.
+    fn ctx(&self) -> DescendCtx {
+        DescendCtx {
+            depth: self.depth(),
+            is_at_root: self.is_at_root(),
+            previous_step_blocked: false,
+        }
+    }
+
+    /// Obtain an identity token for the top of the stack.
+    /// The token is only meaningful while the top node is alive.
+    pub fn top_id(&self) -> TopId<T> {
+        TopId::new(self.top_ptr())
+    }
+
+    /// Is the top of the stack the node identified by `id`?
+    pub fn is_at(&self, id: TopId<T>) -> bool {
+        self.top_id() == id
+    }
+
+    /// Iterate over the identity tokens of every node on the stack, from the root to the top.
+    /// The tokens are only meaningful while their nodes are alive.
+    pub fn path_ids(&self) -> impl Iterator<Item = TopId<T>> + '_ {
+        self.data.iter().map(|&ptr| TopId::new(ptr))
+    }
+
+    /// Are both stacks at the same position, i.e. do they hold the same nodes, from the root to the top?
+    /// This only compares addresses, so nothing is dereferenced.
+    /// Two stacks cannot mutably borrow the same root at once, so to check that a stack returns to an earlier position,
+    /// compare `path_ids` from before and after instead.
+    pub fn same_position(&self, other: &MutRefStack<'root, T>) -> bool {
+        self.path_ids().eq(other.path_ids())
+    }
+
+    /// Copy the addresses of every node on the stack, from the root to the top.
+    /// The result always has `depth() + 1` elements.
+    pub fn path_pointers(&self) -> Vec<*const T> {
+        self.data.iter().map(|&ptr| ptr.cast_const()).collect()
+    }
+
     /// Inject a new reference to the top of the stack. The reference still must live
     /// as long as the root of the stack.
     pub fn inject_top(&mut self, new_top: &'root mut T) -> &mut T {
@@ -60,6 +299,50 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
         self.top_mut()
     }
 
+    /// Replace the root of the stack with `new_root`, e.g. to move on to another data structure,
+    /// returning a mutable reference to the new root.
+    /// The nodes recorded by `descend_with_cycle_check` are forgotten.
+    /// Fails if the stack is not at its root, rather than dropping the descended levels.
+    pub fn replace_root(&mut self, new_root: &'root mut T) -> Result<&mut T, NotAtRoot> {
+        if !self.is_at_root() {
+            return Err(NotAtRoot {
+                depth: self.depth(),
+            });
+        }
+        self.data[0] = new_root;
+        self.visited.clear();
+        Ok(self.top_mut())
+    }
+
+    /// Pass the top to the closure, which returns a sub-stack rooted at it (e.g. from a helper that descends on its own),
+    /// and push every level of the sub-stack above its root, returning a mutable reference to the new top.
+    /// The nodes the sub-stack recorded with `descend_with_cycle_check` are recorded on this stack too.
+    ///
+    /// The sub-stack cannot outlive the closure's borrow of the top, so it is handed back through the closure
+    /// instead of being passed in directly.
+    /// Fails if the root of the sub-stack is not the top (e.g. if it was rooted at a child of the top), in which case the stack does not move.
+    pub fn graft_with(
+        &mut self,
+        f: impl for<'sub> FnOnce(&'sub mut T) -> MutRefStack<'sub, T>,
+    ) -> Result<&mut T, RootMismatch> {
+        let old_top: *mut T = self.top_ptr();
+        let sub = unsafe { f(&mut *old_top) };
+        if !std::ptr::eq(sub.data[0], old_top) {
+            return Err(RootMismatch);
+        }
+        self.data.extend_from_slice(&sub.data[1..]);
+        self.visited.extend(sub.visited);
+        Ok(self.top_mut())
+    }
+
+    /// Inject several new references to the top of the stack, in order, returning the new top.
+    /// The references still must live as long as the root of the stack.
+    pub fn inject_path(&mut self, path: impl IntoIterator<Item = &'root mut T>) -> &mut T {
+        self.data
+            .extend(path.into_iter().map(|new_top| new_top as *mut T));
+        self.top_mut()
+    }
+
     /// Inject a new reference to the top of the stack. The reference still must live
     /// as long as the root of the stack.
     pub fn inject_with(
@@ -78,12 +361,81 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
         &mut self,
         f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node mut T>,
     ) -> Option<&mut T> {
-        let old_top: *mut T = self.raw_top();
+        let old_top: *mut T = self.top_ptr();
         let new_top: &mut T = unsafe { f(&mut *old_top)? };
         self.data.push(new_top);
         Some(new_top)
     }
 
+    /// Descend into the recursive data structure with the same closure until it returns None,
+    /// returning a mutable reference to the new top element.
+    /// The closure is `FnMut`, so state it captures (e.g. a counter) persists across levels.
+    pub fn descend_each(
+        &mut self,
+        mut f: impl for<'node> FnMut(&'node mut T) -> Option<&'node mut T>,
+    ) -> &mut T {
+        while self.descend_with(&mut f).is_some() {}
+        self.top_mut()
+    }
+
+    /// Descend into the `i`th element of the slice of children returned by the closure.
+    /// Returns None (without descending) if `i` is out of bounds.
+    pub fn descend_index(
+        &mut self,
+        i: usize,
+        children: impl for<'node> FnOnce(&'node mut T) -> &'node mut [T],
+    ) -> Option<&mut T>
+    where
+        T: Sized,
+    {
+        self.descend_with(|node| children(node).get_mut(i))
+    }
+
+    /// Descend into the recursive data structure like `descend_with`, additionally passing the closure
+    /// information about the current position.
+    pub fn descend_with_ctx(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T, DescendCtx) -> Option<&'node mut T>,
+    ) -> Option<&mut T> {
+        let ctx = self.ctx();
+        self.descend_with(|node| f(node, ctx))
+    }
+
+    /// Descend into the recursive data structure like `descend_with`, but refuse to descend into a node that
+    /// was already seen according to `policy`, returning `Err(CycleDetected)` instead and leaving the stack unchanged.
+    /// Every node descended into with this method is recorded, for use with `CyclePolicy::Anywhere`.
+    /// Nodes are compared with `ptr::eq`, so for unsized `T` the metadata must match too: a subslice starting at the
+    /// same address as the top (e.g. `&mut top[..1]`) is a different node.
+    pub fn descend_with_cycle_check(
+        &mut self,
+        f: impl for<'node> FnOnce(&'node mut T) -> Option<&'node mut T>,
+        policy: CyclePolicy,
+    ) -> Result<Option<&mut T>, CycleDetected> {
+        let old_top: *mut T = self.top_ptr();
+        let Some(new_top) = (unsafe { f(&mut *old_top) }) else {
+            return Ok(None);
+        };
+        let new_top: *mut T = new_top;
+        let on_path = self.data.iter().any(|&ptr| std::ptr::eq(ptr, new_top));
+        let seen = match policy {
+            CyclePolicy::OnPath => on_path,
+            CyclePolicy::Anywhere => on_path || self.visited.contains(&new_top.cast_const()),
+        };
+        if seen {
+            return Err(CycleDetected {
+                depth: self.data.len(),
+            });
+        }
+        self.visited.insert(new_top);
+        self.data.push(new_top);
+        Ok(Some(self.top_mut()))
+    }
+
+    /// Forget every node recorded by `descend_with_cycle_check`.
+    pub fn clear_visited(&mut self) {
+        self.visited.clear();
+    }
+
     /// Ascend back up from the recursive data structure, returning a mutable reference to the new top element, if it changed.
     /// If we are not currently at the root, ascend and return a reference to the new top.
     /// If we are already at the root, returns None (the top is the root and does not change).
@@ -101,6 +453,9 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
     /// Ascend back up from the recursive data structure while the given closure returns `true`, returning a mutable reference to the new top element.
     /// If we are not currently at the root, and the predicate returns `true`, ascend and continue.
     /// If we are already at the root, or if the predicate returned false, returns a reference to the top element.
+    ///
+    /// If the predicate panics, the stack is left at the level the predicate panicked on,
+    /// and is still usable if the panic is caught; the levels already ascended stay ascended.
     pub fn ascend_while<P>(&mut self, mut predicate: P) -> &mut T
     where
         P: FnMut(&mut T) -> bool,
@@ -113,15 +468,143 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
         self.top_mut()
     }
 
+    /// Ascend like `ascend_while`, but the predicate can fail.
+    /// If it returns an error, ascending stops and the stack is left at the level the predicate failed on.
+    pub fn ascend_while_result<E>(
+        &mut self,
+        mut predicate: impl FnMut(&mut T) -> Result<bool, E>,
+    ) -> Result<&mut T, E> {
+        while !self.is_at_root() && predicate(self.top_mut())? {
+            let Some(_) = self.ascend() else {
+                unreachable!();
+            };
+        }
+        Ok(self.top_mut())
+    }
+
+    /// Ascend like `ascend_while`, additionally passing the predicate the depth of the node it is called with.
+    /// For example, `ascend_while_indexed(|_, depth| depth > 2)` ascends until the top is at depth 2.
+    pub fn ascend_while_indexed<P>(&mut self, mut predicate: P) -> &mut T
+    where
+        P: FnMut(&mut T, usize) -> bool,
+    {
+        let mut depth = self.depth();
+        self.ascend_while(|top| {
+            let ascend = predicate(top, depth);
+            depth -= 1;
+            ascend
+        })
+    }
+
+    /// Ascend all the way back up to the root, calling the closure on each node as it is popped.
+    /// Returns the results of the closure in pop order, i.e. starting with the current top.
+    /// The root is never popped, so the closure is not called on it.
+    pub fn ascend_collect<R>(&mut self, mut f: impl FnMut(&mut T) -> R) -> Vec<R> {
+        let mut results = Vec::with_capacity(self.data.len() - 1);
+        while !self.is_at_root() {
+            results.push(f(self.top_mut()));
+            self.data.pop();
+        }
+        results
+    }
+
+    /// Call the closure on every level of the stack, one at a time, from the root to the top.
+    ///
+    /// # Safety
+    ///
+    /// Every level above a node borrows from it, and `f` gets a `&mut` to the whole node.
+    /// So no node may contain a later level of the stack in its own memory (e.g. as an inline field or array element);
+    /// later levels must only be reachable through indirection such as `Box` or `Vec`.
+    /// `f` must also not replace, drop or move any such indirection leading to a later level
+    /// (e.g. by replacing the `Box` that was descended into). Only the top may be mutated freely.
+    pub unsafe fn for_each_ancestor_mut(&mut self, mut f: impl FnMut(&mut T)) {
+        for &ptr in &self.data {
+            f(unsafe { &mut *ptr });
+        }
+    }
+
+    /// Remove the levels between the root and the top for which `keep` returns false, keeping the rest in order.
+    /// `keep` is called with the depth of each such level from the root upwards; the root and the top are always kept.
+    /// The nodes themselves are not passed to `keep`, since every level above them still borrows from them.
+    /// Afterwards, ascending from a level goes straight to the closest kept level below it.
+    pub fn retain_ancestors(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let top_depth = self.depth();
+        let mut depth = 0;
+        self.data.retain(|_| {
+            let current = depth;
+            depth += 1;
+            current == 0 || current == top_depth || keep(current)
+        });
+    }
+
+    /// Visit every node below the current top in preorder, without moving the stack.
+    /// `children(node, n)` should return the `n`th child of `node`, or None if there is no such child.
+    /// `visit` is called with each descendant and its depth relative to the current top (starting at 1).
+    /// The stack is restored to its original position on return, even if a closure panics.
+    pub fn for_each_descendant(
+        &mut self,
+        mut children: impl FnMut(&mut T, usize) -> Option<&mut T>,
+        mut visit: impl FnMut(&mut T, usize),
+    ) {
+        struct RestoreDepth<'a, 'root, T: ?Sized> {
+            stack: &'a mut MutRefStack<'root, T>,
+            len: usize,
+        }
+        impl<T: ?Sized> Drop for RestoreDepth<'_, '_, T> {
+            fn drop(&mut self) {
+                self.stack.data.truncate(self.len);
+            }
+        }
+        let len = self.data.len();
+        let guard = RestoreDepth { stack: self, len };
+        // The index of the next child to visit, for each level descended into since the start.
+        let mut next_child = vec![0];
+        while let Some(&index) = next_child.last() {
+            if guard
+                .stack
+                .descend_with(|node| children(node, index))
+                .is_some()
+            {
+                *next_child.last_mut().unwrap() += 1;
+                visit(guard.stack.top_mut(), next_child.len());
+                next_child.push(0);
+            } else {
+                next_child.pop();
+                if !next_child.is_empty() {
+                    guard.stack.ascend();
+                }
+            }
+        }
+    }
+
     /// Ascend from, descend from, inject a new stack top, or stay at the current node,
     /// based on the return value of the closure.
     pub fn move_with<F>(&mut self, f: F) -> Result<&mut T, MoveError>
     where
         F: for<'a> FnOnce(&'a mut T) -> MoveDecision<'root, 'a, T>,
     {
-        let top = self.top_mut();
-        let result = f(top);
-        match result {
+        let old_top: *mut T = self.top_ptr();
+        let result = unsafe { f(&mut *old_top) };
+        self.apply_decision(result)
+    }
+
+    /// Like `move_with`, but the closure may fail with its own error, aborting the move.
+    /// On a user error, the cursor does not move.
+    pub fn try_move_with<E, F>(&mut self, f: F) -> Result<&mut T, TryMoveError<E>>
+    where
+        F: for<'a> FnOnce(&'a mut T) -> Result<MoveDecision<'root, 'a, T>, E>,
+    {
+        let old_top: *mut T = self.top_ptr();
+        let result = unsafe { f(&mut *old_top) }.map_err(TryMoveError::User)?;
+        self.apply_decision(result).map_err(TryMoveError::Move)
+    }
+
+    /// Carry out the decision of a `move_with*` closure.
+    fn apply_decision(
+        &mut self,
+        decision: MoveDecision<'root, '_, T>,
+    ) -> Result<&mut T, MoveError> {
+        match decision {
             MoveDecision::Ascend => self.ascend().ok_or(MoveError::AscendAtRoot),
             MoveDecision::Stay => Ok(self.top_mut()),
             MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
@@ -129,9 +612,32 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
                 self.data.push(new_top);
                 Ok(self.top_mut())
             }
+            MoveDecision::DescendFromParent(f) => self.descend_from_parent(f),
+        }
+    }
+
+    /// Helper function for `MoveDecision::DescendFromParent`.
+    fn descend_from_parent(&mut self, f: FromParent<'root, T>) -> Result<&mut T, MoveError> {
+        let parent: *mut T = self.ascend().ok_or(MoveError::AscendAtRoot)?;
+        // SAFETY: The old top was popped, so `parent` is the only live reference to the new top.
+        if let Some(new_top) = f(unsafe { &mut *parent }) {
+            let new_top: *mut T = new_top;
+            self.data.push(new_top);
         }
+        Ok(self.top_mut())
     }
 
+    /// Like `move_with`, additionally passing the closure information about the current position.
+    pub fn move_with_ctx<F>(&mut self, f: F) -> Result<&mut T, MoveError>
+    where
+        F: for<'a> FnOnce(&'a mut T, DescendCtx) -> MoveDecision<'root, 'a, T>,
+    {
+        let ctx = self.ctx();
+        self.move_with(|node| f(node, ctx))
+    }
+
+    /// Like `move_with`, but the closure returns a boxed future.
+    /// To pass an async closure instead, without boxing its future, use `move_with_async_fn`.
     pub async fn move_with_async<F>(&mut self, f: F) -> Result<&mut T, MoveError>
     where
         F: for<'a> FnOnce(
@@ -139,16 +645,34 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
         )
             -> Pin<Box<dyn Future<Output = MoveDecision<'root, 'a, T>> + 'a>>,
     {
-        let top = self.top_mut();
-        let result = f(top).await;
-        match result {
-            MoveDecision::Ascend => self.ascend().ok_or(MoveError::AscendAtRoot),
-            MoveDecision::Stay => Ok(self.top_mut()),
-            MoveDecision::Inject(new_top) | MoveDecision::Descend(new_top) => {
-                let new_top: *mut T = new_top;
-                self.data.push(new_top);
-                Ok(self.top_mut())
-            }
+        let old_top: *mut T = self.top_ptr();
+        let result = unsafe { f(&mut *old_top) }.await;
+        self.apply_decision(result)
+    }
+
+    /// Like `move_with_async`, but takes an async closure, whose future does not need to be boxed.
+    /// See `AsyncMove`.
+    pub async fn move_with_async_fn<F>(&mut self, f: F) -> Result<&mut T, MoveError>
+    where
+        F: AsyncMove<T, MoveDecision<'root, 'root, T>>,
+    {
+        let old_top: *mut T = self.top_ptr();
+        let result = unsafe { f(&mut *old_top) }.await;
+        self.apply_decision(result)
+    }
+
+    /// Walk the current top and every node below it in preorder, consuming the stack.
+    /// `children(node, n)` should return the `n`th child of `node`, or None if there is no such child.
+    /// See `Preorder` for how the nodes are yielded.
+    pub fn into_preorder<F>(self, children: F) -> Preorder<'root, T, F>
+    where
+        F: for<'node> FnMut(&'node mut T, usize) -> Option<&'node mut T>,
+    {
+        Preorder {
+            stack: self,
+            next_child: Vec::new(),
+            children,
+            started: false,
         }
     }
 
@@ -158,9 +682,461 @@ impl<'root, T: ?Sized> MutRefStack<'root, T> {
         unsafe { &mut *ptr }
     }
 
+    /// Pop all references off the stack and go back to the root, returning how many were popped
+    /// (the depth of the stack beforehand).
+    pub fn ascend_to_root(&mut self) -> usize {
+        let depth = self.depth();
+        self.to_root();
+        depth
+    }
+
     /// Pop all references off the stack and go back to the root.
     pub fn to_root(&mut self) -> &mut T {
         self.data.truncate(1);
         self.top_mut()
     }
 }
+
+/// A depth-first (preorder) walk of a recursive data structure, created by `MutRefStack::into_preorder`.
+///
+/// This is a "lending" iterator: `next` returns a `NodeGuard` that borrows the walk, so it must be
+/// dropped before the next call to `next`. It cannot implement `Iterator`, since an `Iterator`'s items may
+/// all be kept alive at once, and a node's `&mut` would then alias with those of its descendants.
+/// The children of a node are only looked up once its `NodeGuard` has been dropped, so modifying a node's
+/// children while visiting it is fine.
+pub struct Preorder<'root, T: ?Sized, F> {
+    stack: MutRefStack<'root, T>,
+    /// The index of the next child to visit, for each level descended into since the start.
+    next_child: Vec<usize>,
+    children: F,
+    /// Whether the starting node has been yielded.
+    started: bool,
+}
+
+impl<'root, T: ?Sized, F> Preorder<'root, T, F>
+where
+    F: for<'node> FnMut(&'node mut T, usize) -> Option<&'node mut T>,
+{
+    /// Visit the next node in preorder, or return None if every node has been visited.
+    // This cannot be `Iterator::next`, since the returned guard must be dropped before the next call.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<NodeGuard<'_, T>> {
+        if !self.started {
+            self.started = true;
+            self.next_child.push(0);
+            return Some(NodeGuard {
+                node: self.stack.top_mut(),
+            });
+        }
+        while let Some(&index) = self.next_child.last() {
+            let children = &mut self.children;
+            if self
+                .stack
+                .descend_with(|node| children(node, index))
+                .is_some()
+            {
+                *self.next_child.last_mut().unwrap() += 1;
+                self.next_child.push(0);
+                return Some(NodeGuard {
+                    node: self.stack.top_mut(),
+                });
+            }
+            self.next_child.pop();
+            if !self.next_child.is_empty() {
+                self.stack.ascend();
+            }
+        }
+        None
+    }
+
+    /// Stop the walk and return the stack, positioned at the most recently visited node.
+    pub fn into_stack(self) -> MutRefStack<'root, T> {
+        self.stack
+    }
+}
+
+/// A node visited by `Preorder::next`, which derefs to the node.
+/// It borrows the walk, so it must be dropped before the next node is visited.
+pub struct NodeGuard<'a, T: ?Sized> {
+    node: &'a mut T,
+}
+
+impl<T: ?Sized> Deref for NodeGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.node
+    }
+}
+
+impl<T: ?Sized> DerefMut for NodeGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Node {
+        value: u32,
+        next: Option<Box<Node>>,
+    }
+
+    fn list(len: u32) -> Node {
+        (1..len).rev().fold(
+            Node {
+                value: len - 1,
+                next: None,
+            },
+            |next, value| Node {
+                value: value - 1,
+                next: Some(Box::new(next)),
+            },
+        )
+    }
+
+    fn descend_all(stack: &mut MutRefStack<'_, Node>) {
+        while stack
+            .descend_with(|node| node.next.as_deref_mut())
+            .is_some()
+        {}
+    }
+
+    fn values(stack: &MutRefStack<'_, Node>) -> Vec<u32> {
+        stack
+            .path_pointers()
+            .into_iter()
+            .map(|ptr| unsafe { (*ptr).value })
+            .collect()
+    }
+
+    #[test]
+    fn for_each_ancestor_mut_visits_path() {
+        let mut root = list(5);
+        let mut stack = MutRefStack::new(&mut root);
+        stack.descend_with(|node| node.next.as_deref_mut()).unwrap();
+        stack.descend_with(|node| node.next.as_deref_mut()).unwrap();
+        // SAFETY: Each level is behind the `Box` in `next`, which is not touched.
+        unsafe { stack.for_each_ancestor_mut(|node| node.value += 10) };
+        assert_eq!(values(&stack), [10, 11, 12]);
+        stack.to_root();
+        descend_all(&mut stack);
+        assert_eq!(values(&stack), [10, 11, 12, 3, 4]);
+    }
+
+    #[test]
+    fn retain_ancestors_keeps_root_and_top() {
+        let mut root = list(6);
+        let mut stack = MutRefStack::new(&mut root);
+        descend_all(&mut stack);
+        let mut asked = vec![];
+        stack.retain_ancestors(|depth| {
+            asked.push(depth);
+            depth % 2 == 0
+        });
+        assert_eq!(asked, [1, 2, 3, 4]);
+        assert_eq!(values(&stack), [0, 2, 4, 5]);
+        assert_eq!(stack.ascend().unwrap().value, 4);
+        assert_eq!(stack.ascend().unwrap().value, 2);
+        assert_eq!(stack.ascend().unwrap().value, 0);
+        assert!(stack.ascend().is_none());
+    }
+
+    #[test]
+    fn retain_ancestors_drop_all() {
+        let mut root = list(4);
+        let mut stack = MutRefStack::new(&mut root);
+        descend_all(&mut stack);
+        stack.retain_ancestors(|_| false);
+        assert_eq!(values(&stack), [0, 3]);
+
+        let mut root = list(1);
+        let mut stack = MutRefStack::new(&mut root);
+        stack.retain_ancestors(|_| panic!("there are no levels between the root and the top"));
+        assert_eq!(values(&stack), [0]);
+    }
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    #[test]
+    fn auto_traits() {
+        assert_send::<MutRefStack<'_, Node>>();
+        assert_sync::<MutRefStack<'_, Node>>();
+        assert_send::<MutRefStack<'_, [u8]>>();
+        assert_sync::<MutRefStack<'_, [u8]>>();
+    }
+
+    #[test]
+    fn send_to_another_thread() {
+        let mut root = list(4);
+        let mut stack = MutRefStack::new(&mut root);
+        stack.descend_with(|node| node.next.as_deref_mut()).unwrap();
+        let mut stack = std::thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    stack.descend_with(|node| node.next.as_deref_mut()).unwrap();
+                    stack.top_mut().value += 10;
+                    stack
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(stack.top().value, 12);
+        assert_eq!(stack.ascend().unwrap().value, 1);
+    }
+
+    #[test]
+    fn move_with_async_fn_takes_an_async_closure() {
+        let mut root = list(3);
+        let mut stack = MutRefStack::new(&mut root);
+        let mut visited = 0;
+        let value = {
+            let mut future = std::pin::pin!(stack.move_with_async_fn(async |node| {
+                visited += 1;
+                match node.next.as_deref_mut() {
+                    Some(next) => MoveDecision::Descend(next),
+                    None => MoveDecision::Stay,
+                }
+            }));
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            let std::task::Poll::Ready(top) = future.as_mut().poll(&mut cx) else {
+                panic!("the closure never waits");
+            };
+            top.unwrap().value
+        };
+        assert_eq!(value, 1);
+        assert_eq!(visited, 1);
+    }
+
+    #[test]
+    fn ascend_while_result_stops_at_the_error() {
+        let mut root = list(5);
+        let mut stack = MutRefStack::new(&mut root);
+        descend_all(&mut stack);
+        let result = stack.ascend_while_result(|node| match node.value {
+            2 => Err(node.value),
+            _ => Ok(true),
+        });
+        assert_eq!(result.map(|top| top.value), Err(2));
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(values(&stack), [0, 1, 2]);
+    }
+
+    #[test]
+    fn ascend_while_result_reaches_the_root() {
+        let mut root = list(5);
+        let mut stack = MutRefStack::new(&mut root);
+        descend_all(&mut stack);
+        let mut visited = vec![];
+        let top = stack.ascend_while_result(|node| {
+            visited.push(node.value);
+            Ok::<_, ()>(true)
+        });
+        assert_eq!(top.unwrap().value, 0);
+        assert!(stack.is_at_root());
+        assert_eq!(visited, [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn inject_path_round_trip() {
+        let mut injected: Vec<Node> = (10..13).map(|value| Node { value, next: None }).collect();
+        let mut root = list(2);
+        let mut stack = MutRefStack::new(&mut root);
+        stack.descend_with(|node| node.next.as_deref_mut()).unwrap();
+        assert_eq!(stack.inject_path([]).value, 1);
+        assert_eq!(stack.depth(), 1);
+        assert_eq!(stack.inject_path(&mut injected).value, 12);
+        assert_eq!(values(&stack), [0, 1, 10, 11, 12]);
+        let mut ascended = vec![];
+        while let Some(top) = stack.ascend() {
+            ascended.push(top.value);
+        }
+        assert_eq!(ascended, [11, 10, 1, 0]);
+        assert_eq!(values(&stack), [0]);
+    }
+
+    #[test]
+    fn descend_from_parent_visits_siblings() {
+        use crate::tree;
+
+        let mut root = tree::Node::new(0);
+        for value in 1..=3 {
+            root.push_child(tree::Node::new(value));
+        }
+        let mut stack = MutRefStack::new(&mut root);
+        stack
+            .descend_with(|node| node.children_mut().first_mut())
+            .unwrap();
+        let mut visited = vec![stack.top().data];
+        for next in 1.. {
+            let value = stack
+                .move_with(|_| {
+                    MoveDecision::DescendFromParent(Box::new(move |parent| {
+                        parent.children_mut().get_mut(next)
+                    }))
+                })
+                .unwrap()
+                .data;
+            if stack.is_at_root() {
+                break;
+            }
+            visited.push(value);
+        }
+        assert_eq!(visited, [1, 2, 3]);
+        assert_eq!(stack.top().data, 0);
+        let result = stack.move_with(|_| {
+            MoveDecision::DescendFromParent(Box::new(|parent| parent.children_mut().first_mut()))
+        });
+        assert_eq!(result.err(), Some(MoveError::AscendAtRoot));
+        assert!(stack.is_at_root());
+    }
+
+    #[test]
+    fn path_pointers_has_one_pointer_per_level() {
+        let mut root = list(4);
+        let mut stack = MutRefStack::new(&mut root);
+        loop {
+            let pointers = stack.path_pointers();
+            assert_eq!(pointers.len(), stack.depth() + 1);
+            assert!(std::ptr::eq(pointers[0], stack.data[0]));
+            assert!(std::ptr::eq(*pointers.last().unwrap(), stack.top()));
+            if stack
+                .descend_with(|node| node.next.as_deref_mut())
+                .is_none()
+            {
+                break;
+            }
+        }
+        assert_eq!(stack.depth(), 3);
+        assert_eq!(values(&stack), [0, 1, 2, 3]);
+        stack.to_root();
+        assert_eq!(stack.path_pointers().len(), 1);
+    }
+
+    #[test]
+    fn replace_root_only_at_the_root() {
+        // A rejected root stays borrowed by the stack, so each attempt gets its own.
+        let mut rejected = list(1);
+        let mut other = list(3);
+        let mut root = list(2);
+        let mut stack = MutRefStack::new(&mut root);
+        descend_all(&mut stack);
+        let result = stack.replace_root(&mut rejected).map(|top| top.value);
+        assert_eq!(result, Err(NotAtRoot { depth: 1 }));
+        assert_eq!(values(&stack), [0, 1]);
+
+        stack.to_root();
+        stack.top_mut().value = 10;
+        assert_eq!(stack.replace_root(&mut other).unwrap().value, 0);
+        descend_all(&mut stack);
+        assert_eq!(values(&stack), [0, 1, 2]);
+        drop(stack);
+        assert_eq!(root.value, 10);
+    }
+
+    #[test]
+    fn same_position_round_trip() {
+        let mut other_root = list(3);
+        let mut root = list(3);
+        let mut stack = MutRefStack::new(&mut root);
+        let mut other = MutRefStack::new(&mut other_root);
+        descend_all(&mut stack);
+        descend_all(&mut other);
+        assert!(stack.same_position(&stack));
+        // Same shape, but different nodes.
+        assert!(!stack.same_position(&other));
+
+        let before: Vec<_> = stack.path_ids().collect();
+        stack.ascend().unwrap();
+        assert!(!stack.path_ids().eq(before.iter().copied()));
+        stack.to_root();
+        descend_all(&mut stack);
+        assert!(stack.path_ids().eq(before.iter().copied()));
+        assert!(stack.same_position(&stack));
+    }
+
+    #[test]
+    fn ascend_while_indexed_to_a_target_depth() {
+        let mut root = list(6);
+        let mut stack = MutRefStack::new(&mut root);
+        descend_all(&mut stack);
+        let mut asked = vec![];
+        let top = stack.ascend_while_indexed(|node, depth| {
+            asked.push((node.value, depth));
+            depth > 2
+        });
+        assert_eq!(top.value, 2);
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(asked, [(5, 5), (4, 4), (3, 3), (2, 2)]);
+        // A target deeper than the top does not ascend at all.
+        stack.ascend_while_indexed(|_, depth| depth > 4);
+        assert_eq!(stack.depth(), 2);
+    }
+
+    #[test]
+    fn descend_each_with_a_captured_counter() {
+        fn descend_counting(stack: &mut MutRefStack<'_, Node>, calls: &mut usize) -> u32 {
+            stack
+                .descend_each(|node| {
+                    *calls += 1;
+                    node.next.as_deref_mut()
+                })
+                .value
+        }
+
+        let mut root = list(4);
+        let mut stack = MutRefStack::new(&mut root);
+        let mut calls = 0;
+        assert_eq!(descend_counting(&mut stack, &mut calls), 3);
+        // Once per level, plus the final call that returned None.
+        assert_eq!(calls, 4);
+        assert_eq!(stack.depth(), 3);
+        stack.ascend_while_indexed(|_, depth| depth > 1);
+        assert_eq!(descend_counting(&mut stack, &mut calls), 3);
+        assert_eq!(calls, 7);
+    }
+
+    #[test]
+    fn cycle_check_detects_the_top_itself() {
+        let mut root = list(3);
+        let mut stack = MutRefStack::new(&mut root);
+        stack
+            .descend_with_cycle_check(|node| node.next.as_deref_mut(), CyclePolicy::OnPath)
+            .unwrap()
+            .unwrap();
+        let result = stack.descend_with_cycle_check(|node| Some(node), CyclePolicy::OnPath);
+        assert_eq!(result.err(), Some(CycleDetected { depth: 2 }));
+        assert_eq!(values(&stack), [0, 1]);
+
+        // A node descended into before is only a repeat with `CyclePolicy::Anywhere` once it is off the path.
+        stack.ascend().unwrap();
+        let result =
+            stack.descend_with_cycle_check(|node| node.next.as_deref_mut(), CyclePolicy::Anywhere);
+        assert_eq!(result.err(), Some(CycleDetected { depth: 1 }));
+        let top =
+            stack.descend_with_cycle_check(|node| node.next.as_deref_mut(), CyclePolicy::OnPath);
+        assert_eq!(top.unwrap().unwrap().value, 1);
+    }
+
+    #[test]
+    fn cycle_check_tells_a_subslice_from_the_top() {
+        let mut root = [1, 2, 3, 4];
+        let mut stack = MutRefStack::new(&mut root[..]);
+        // Each prefix starts at the same address as the top, but is a different node.
+        for len in (1..4).rev() {
+            let top =
+                stack.descend_with_cycle_check(|top| top.get_mut(..len), CyclePolicy::Anywhere);
+            assert_eq!(top.unwrap().unwrap().len(), len);
+        }
+        assert_eq!(stack.depth(), 3);
+        let result = stack.descend_with_cycle_check(|top| top.get_mut(..), CyclePolicy::OnPath);
+        assert_eq!(result.err(), Some(CycleDetected { depth: 4 }));
+        stack.ascend().unwrap();
+        let result = stack.descend_with_cycle_check(|top| top.get_mut(..1), CyclePolicy::Anywhere);
+        assert_eq!(result.err(), Some(CycleDetected { depth: 3 }));
+    }
+}
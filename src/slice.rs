@@ -0,0 +1,143 @@
+use std::{error::Error, fmt};
+
+use crate::simple::MutRefStack;
+
+/// A node whose children are a slice of nodes of the same type, for `SliceCursor::descend_element`.
+pub trait SliceChildren: Sized {
+    /// The children of this node.
+    fn children_mut(&mut self) -> &mut [Self];
+}
+
+/// Returned by `SliceCursor::descend_element` when the top has no element at `index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    /// The requested index.
+    pub index: usize,
+    /// How many elements the top has.
+    pub len: usize,
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "element index {} is out of bounds for {} elements",
+            self.index, self.len
+        )
+    }
+}
+
+impl Error for OutOfBounds {}
+
+/// A cursor over a forest, i.e. a slice of nodes whose children are slices of nodes.
+///
+/// Each level of the stack is a slice of siblings. Descending moves into the children of one element
+/// of the top, and the index of that element is remembered, so that e.g. the next sibling can be found after ascending.
+pub struct SliceCursor<'root, T> {
+    stack: MutRefStack<'root, [T]>,
+    /// For each level below the top, the index of the element that was descended into.
+    path: Vec<usize>,
+}
+
+impl<'root, T> SliceCursor<'root, T> {
+    /// Create a new SliceCursor from a mutable reference to the root slice of a forest.
+    pub fn new(root: &'root mut [T]) -> Self {
+        Self {
+            stack: MutRefStack::new(root),
+            path: Vec::new(),
+        }
+    }
+
+    /// Obtain a shared reference to the top slice of the stack.
+    pub fn top(&self) -> &[T] {
+        self.stack.top()
+    }
+
+    /// Obtain a mutable reference to the top slice of the stack.
+    pub fn top_mut(&mut self) -> &mut [T] {
+        self.stack.top_mut()
+    }
+
+    /// Is this SliceCursor currently at its root?
+    pub fn is_at_root(&self) -> bool {
+        self.stack.is_at_root()
+    }
+
+    /// How many levels above the root the top of the stack is.
+    pub fn depth(&self) -> usize {
+        self.stack.depth()
+    }
+
+    /// The index of the element descended into at each level, from the root to the parent of the top.
+    /// The result always has `depth()` elements.
+    pub fn path(&self) -> &[usize] {
+        &self.path
+    }
+
+    /// Descend into the children of element `i` of the top, as returned by the closure,
+    /// returning a mutable reference to the new top slice.
+    /// If `i` is out of bounds, the closure is not called and the stack does not move.
+    pub fn descend_element_with(
+        &mut self,
+        i: usize,
+        children: impl for<'node> FnOnce(&'node mut T) -> &'node mut [T],
+    ) -> Result<&mut [T], OutOfBounds> {
+        let len = self.top().len();
+        if i >= len {
+            return Err(OutOfBounds { index: i, len });
+        }
+        self.stack
+            .descend_with(|siblings| Some(children(&mut siblings[i])));
+        self.path.push(i);
+        Ok(self.top_mut())
+    }
+
+    /// Descend through element `path[0]` of the top, then element `path[1]` of the new top, and so on,
+    /// returning a mutable reference to the new top slice.
+    /// This replays a path recorded with `path`, e.g. from another cursor over the same forest.
+    /// If an index is out of bounds, the levels descended before it stay on the stack.
+    pub fn descend_path_with(
+        &mut self,
+        path: &[usize],
+        mut children: impl for<'node> FnMut(&'node mut T) -> &'node mut [T],
+    ) -> Result<&mut [T], OutOfBounds> {
+        for &i in path {
+            self.descend_element_with(i, &mut children)?;
+        }
+        Ok(self.top_mut())
+    }
+
+    /// Ascend back up to the parent slice, returning the index of the element the top was the children of,
+    /// and a mutable reference to the new top slice.
+    /// If we are already at the root, returns None (the top is the root and does not change).
+    pub fn ascend(&mut self) -> Option<(usize, &mut [T])> {
+        let index = self.path.pop()?;
+        let top = self.stack.ascend()?;
+        Some((index, top))
+    }
+
+    /// Ascend to the root, returning how many levels were ascended.
+    pub fn ascend_to_root(&mut self) -> usize {
+        self.path.clear();
+        self.stack.ascend_to_root()
+    }
+
+    /// Return reference to the top slice of this stack, forgetting about the stack entirely.
+    pub fn into_top(self) -> &'root mut [T] {
+        self.stack.into_top()
+    }
+}
+
+impl<'root, T: SliceChildren> SliceCursor<'root, T> {
+    /// Descend into the children of element `i` of the top, returning a mutable reference to the new top slice.
+    /// If `i` is out of bounds, the stack does not move.
+    pub fn descend_element(&mut self, i: usize) -> Result<&mut [T], OutOfBounds> {
+        self.descend_element_with(i, T::children_mut)
+    }
+
+    /// Descend along `path` like `descend_path_with`, returning a mutable reference to the new top slice.
+    /// If an index is out of bounds, the levels descended before it stay on the stack.
+    pub fn descend_path(&mut self, path: &[usize]) -> Result<&mut [T], OutOfBounds> {
+        self.descend_path_with(path, T::children_mut)
+    }
+}
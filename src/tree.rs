@@ -0,0 +1,90 @@
+use crate::{slice::SliceChildren, walk, with_data::MutRefStackWithData};
+
+/// A general tree, or rather a forest: any number of root nodes, each with any number of children.
+///
+/// This is only a minimal container for trying out the cursors. Its nodes implement `SliceChildren`,
+/// so it also works with `slice::SliceCursor`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Tree<T> {
+    roots: Vec<Node<T>>,
+}
+
+/// A node of a `Tree`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Node<T> {
+    /// The value of this node.
+    pub data: T,
+    children: Vec<Node<T>>,
+}
+
+impl<T> Node<T> {
+    /// Create a new node without children.
+    pub fn new(data: T) -> Self {
+        Self {
+            data,
+            children: Vec::new(),
+        }
+    }
+
+    /// The children of this node.
+    pub fn children(&self) -> &[Node<T>] {
+        &self.children
+    }
+
+    /// The children of this node.
+    pub fn children_mut(&mut self) -> &mut [Node<T>] {
+        &mut self.children
+    }
+
+    /// Add a child after the existing children, returning a mutable reference to it.
+    pub fn push_child(&mut self, child: Node<T>) -> &mut Node<T> {
+        self.children.push(child);
+        self.children.last_mut().unwrap()
+    }
+}
+
+impl<T> SliceChildren for Node<T> {
+    fn children_mut(&mut self) -> &mut [Self] {
+        &mut self.children
+    }
+}
+
+impl<T> Tree<T> {
+    /// Create a new empty tree.
+    pub fn new() -> Self {
+        Self { roots: Vec::new() }
+    }
+
+    /// The root nodes of this tree.
+    pub fn roots(&self) -> &[Node<T>] {
+        &self.roots
+    }
+
+    /// The root nodes of this tree.
+    pub fn roots_mut(&mut self) -> &mut [Node<T>] {
+        &mut self.roots
+    }
+
+    /// Add a root after the existing roots, returning a mutable reference to it.
+    pub fn push_root(&mut self, root: Node<T>) -> &mut Node<T> {
+        self.roots.push(root);
+        self.roots.last_mut().unwrap()
+    }
+
+    /// Create a cursor over this tree, whose root is the slice of root nodes with the additional data `root_data`.
+    /// Each level of the cursor is a slice of siblings, like in `slice::SliceCursor`.
+    pub fn cursor<U>(&mut self, root_data: U) -> MutRefStackWithData<'_, [Node<T>], U> {
+        MutRefStackWithData::new(&mut *self.roots, root_data)
+    }
+
+    /// Visit the value of every node in preorder, along with its depth (the roots are at depth 0).
+    pub fn preorder(&mut self, mut visit: impl FnMut(&mut T, usize)) {
+        for root in &mut self.roots {
+            walk::visit_subtree(
+                root,
+                |node| &mut node.children,
+                |node, depth| visit(&mut node.data, depth),
+            );
+        }
+    }
+}
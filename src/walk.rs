@@ -0,0 +1,111 @@
+use std::slice;
+
+use crate::with_data::{MoveDecision, MutRefStackWithData};
+
+/// Visit `root` and every node below it exactly once, in preorder, along with its depth (`root` is at depth 0).
+/// The children of a node are obtained after it has been visited, so `visit` may modify them.
+pub fn visit_subtree<T>(
+    root: &mut T,
+    mut children: impl for<'n> FnMut(&'n mut T) -> &'n mut [T],
+    mut visit: impl FnMut(&mut T, usize),
+) {
+    /// The additional data of each level, which is a slice of siblings.
+    struct State {
+        /// The index of the next sibling to visit.
+        next_index: usize,
+        /// The depth of the siblings.
+        depth: usize,
+    }
+    let mut cursor = MutRefStackWithData::new(
+        slice::from_mut(root),
+        State {
+            next_index: 0,
+            depth: 0,
+        },
+    );
+    while cursor
+        .move_with(|siblings, state| match siblings.get_mut(state.next_index) {
+            None => MoveDecision::Ascend,
+            Some(node) => {
+                visit(node, state.depth);
+                state.next_index += 1;
+                MoveDecision::Descend(
+                    children(node),
+                    State {
+                        next_index: 0,
+                        depth: state.depth + 1,
+                    },
+                )
+            }
+        })
+        .is_ok()
+    {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::Node;
+
+    /// A tree where each node's value is `10 * depth + n` for its `n`th position in preorder, counting from 0:
+    ///
+    /// ```text
+    /// 0
+    /// ├── 11
+    /// │   ├── 22
+    /// │   └── 23
+    /// │       └── 34
+    /// ├── 15
+    /// └── 16
+    ///     └── 27
+    /// ```
+    fn sample() -> Node<u32> {
+        let mut root = Node::new(0);
+        let a = root.push_child(Node::new(11));
+        a.push_child(Node::new(22));
+        a.push_child(Node::new(23)).push_child(Node::new(34));
+        root.push_child(Node::new(15));
+        root.push_child(Node::new(16)).push_child(Node::new(27));
+        root
+    }
+
+    #[test]
+    fn visits_every_node_once_with_depth() {
+        let mut root = sample();
+        let mut visited = vec![];
+        visit_subtree(
+            &mut root,
+            |node| node.children_mut(),
+            |node, depth| visited.push((node.data, depth)),
+        );
+        let mut values: Vec<u32> = visited.iter().map(|&(value, _)| value).collect();
+        assert_eq!(values, [0, 11, 22, 23, 34, 15, 16, 27]);
+        values.sort_unstable();
+        values.dedup();
+        assert_eq!(values.len(), 8);
+        for (value, depth) in visited {
+            assert_eq!(value as usize / 10, depth);
+        }
+    }
+
+    #[test]
+    fn visit_may_modify_children() {
+        let mut root = Node::new(0);
+        let mut visited = vec![];
+        visit_subtree(
+            &mut root,
+            |node| node.children_mut(),
+            |node, depth| {
+                visited.push((node.data, depth));
+                if depth < 2 {
+                    node.push_child(Node::new(node.data * 2 + 1));
+                    node.push_child(Node::new(node.data * 2 + 2));
+                }
+            },
+        );
+        assert_eq!(
+            visited,
+            [(0, 0), (1, 1), (3, 2), (4, 2), (2, 1), (5, 2), (6, 2)]
+        );
+    }
+}
@@ -1,5 +1,9 @@
-use std::{collections::VecDeque, marker::PhantomData};
+use std::{error::Error, fmt, iter::FusedIterator, marker::PhantomData};
 
+/// A cursor over a recursive data structure, holding a mutable reference to every node from the root to the top,
+/// each with some additional data.
+///
+/// Like `(&mut T, U)`, this is `Send` when `T` and `U` are `Send`, and `Sync` when `T` and `U` are `Sync`.
 pub struct MutRefStackWithData<'root, T: ?Sized, U: 'root> {
     /// Ensures this mutrefstack does not exceed the lifetime of its root.
     lifetime: PhantomData<(&'root mut T, U)>,
@@ -7,17 +11,119 @@ pub struct MutRefStackWithData<'root, T: ?Sized, U: 'root> {
     data: Vec<(*mut T, U)>,
 }
 
+// SAFETY: The stack is semantically a list of `(&'root mut T, U)`, which is `Send` when `T` and `U` are `Send`.
+unsafe impl<T: ?Sized + Send, U: Send> Send for MutRefStackWithData<'_, T, U> {}
+// SAFETY: A shared `&MutRefStackWithData` only gives out `&T` and `&U`, so it is `Sync` when `T` and `U` are `Sync`.
+unsafe impl<T: ?Sized + Sync, U: Sync> Sync for MutRefStackWithData<'_, T, U> {}
+
 pub enum MoveDecision<'root, 'this, T: ?Sized, U: 'root> {
     Ascend,
     Stay,
     Descend(&'this mut T, U),
     Inject(&'root mut T, U),
+    /// Ascend, folding the old top's additional data into the new top's with the closure,
+    /// like `MutRefStackWithData::ascend_fold`.
+    AscendFold(Fold<'this, U>),
+}
+
+/// The closure of `MoveDecision::AscendFold`.
+pub type Fold<'this, U> = Box<dyn FnOnce(U, &mut U) + 'this>;
+
+impl<'root, 'this, T: ?Sized, U: 'root> MoveDecision<'root, 'this, T, U> {
+    /// Descend into `node` with the additional data `data`. Equivalent to `MoveDecision::Descend(node, data)`.
+    pub fn descend_with_data(node: &'this mut T, data: U) -> Self {
+        MoveDecision::Descend(node, data)
+    }
+
+    /// Inject `node` as the new top with the additional data `data`. Equivalent to `MoveDecision::Inject(node, data)`.
+    pub fn inject_with_data(node: &'root mut T, data: U) -> Self {
+        MoveDecision::Inject(node, data)
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum MoveError {
+    /// The closure asked to ascend, but the stack was already at its root.
     AscendAtRoot,
 }
 
+impl MoveError {
+    /// Did the closure ask to ascend at the root?
+    pub fn is_ascend_at_root(&self) -> bool {
+        matches!(self, MoveError::AscendAtRoot)
+    }
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::AscendAtRoot => f.write_str("cannot ascend above the root"),
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+/// The additional data of the levels ascended out of by `MutRefStackWithData::ascend_while`,
+/// ordered from the new top's child to the old top.
+#[derive(Debug, Clone)]
+pub struct AscendedData<U> {
+    inner: std::vec::IntoIter<U>,
+}
+
+impl<U> AscendedData<U> {
+    pub(crate) fn new(data: Vec<U>) -> Self {
+        Self {
+            inner: data.into_iter(),
+        }
+    }
+
+    /// Collect the remaining additional data into a `Vec`, in the same order.
+    pub fn into_vec(self) -> Vec<U> {
+        self.inner.collect()
+    }
+}
+
+impl<U> Iterator for AscendedData<U> {
+    type Item = U;
+
+    fn next(&mut self) -> Option<U> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<U> DoubleEndedIterator for AscendedData<U> {
+    fn next_back(&mut self) -> Option<U> {
+        self.inner.next_back()
+    }
+}
+
+impl<U> ExactSizeIterator for AscendedData<U> {}
+
+impl<U> FusedIterator for AscendedData<U> {}
+
+/// Returned by `MutRefStackWithData::reset_root_data` when the stack is not at its root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotAtRoot<U> {
+    /// How many levels above the root the top of the stack is.
+    pub depth: usize,
+    /// The additional data that was not used.
+    pub data: U,
+}
+
+impl<U> fmt::Display for NotAtRoot<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the stack is {} levels above its root", self.depth)
+    }
+}
+
+impl<U: fmt::Debug> Error for NotAtRoot<U> {}
+
 impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
     /// Create a new MutRefStack from a mutable reference to the root
     /// of a recursive data structure.
@@ -38,6 +144,11 @@ impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
         (unsafe { &(*ptr) }, additional_data)
     }
 
+    /// Obtain a raw pointer to the top of the stack, e.g. to compare node identities.
+    pub fn raw_top(&self) -> *const T {
+        self.top_ptr()
+    }
+
     /// Obtain a mutable reference to the top of the stack.
     pub fn top_mut(&mut self) -> (&mut T, &mut U) {
         let &mut (ptr, ref mut additional_data) = self
@@ -47,11 +158,136 @@ impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
         (unsafe { &mut (*ptr) }, additional_data)
     }
 
+    /// Obtain a mutable reference to the top of the stack, and to the additional data of its parent, if any.
+    /// The parent node itself cannot be borrowed at the same time, because the top is borrowed from it.
+    pub fn top_mut_with_parent_data(&mut self) -> ((&mut T, &mut U), Option<&mut U>) {
+        let (top, ancestors) = self
+            .data
+            .split_last_mut()
+            .expect("root pointer should never be popped");
+        let &mut (ptr, ref mut addl) = top;
+        let parent_addl = ancestors.last_mut().map(|(_ptr, addl)| addl);
+        ((unsafe { &mut *ptr }, addl), parent_addl)
+    }
+
+    /// Obtain a raw mutable pointer to the top of the stack.
+    pub fn raw_top_mut(&mut self) -> *mut T {
+        self.top_ptr()
+    }
+
+    /// The stored pointer to the top of the stack, without reborrowing it.
+    fn top_ptr(&self) -> *mut T {
+        self.data
+            .last()
+            .expect("root pointer should never be popped")
+            .0
+    }
+
+    /// Obtain a shared reference to the top node of the stack, without its additional data.
+    pub fn top_node(&self) -> &T {
+        self.top().0
+    }
+
+    /// Obtain a mutable reference to the top node of the stack, without its additional data.
+    pub fn top_node_mut(&mut self) -> &mut T {
+        self.top_mut().0
+    }
+
+    /// Obtain a shared reference to the additional data of the top of the stack.
+    pub fn top_data(&self) -> &U {
+        self.top().1
+    }
+
+    /// Obtain a mutable reference to the additional data of the top of the stack.
+    pub fn top_data_mut(&mut self) -> &mut U {
+        self.top_mut().1
+    }
+
     /// Is this MutRefStack currently at its root?
     pub fn is_at_root(&self) -> bool {
         self.data.len() == 1
     }
 
+    /// How many levels above the root the top of the stack is.
+    pub fn depth(&self) -> usize {
+        self.data.len() - 1
+    }
+
+    /// Iterate over the additional data of every level, from the root's to the top's.
+    pub fn data_iter(&self) -> impl DoubleEndedIterator<Item = &U> + ExactSizeIterator {
+        self.data.iter().map(|(_ptr, addl)| addl)
+    }
+
+    /// Iterate mutably over the additional data of every level, from the root's to the top's.
+    /// Unlike the nodes, the additional data of different levels never aliases.
+    pub fn data_iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut U> + ExactSizeIterator {
+        self.data.iter_mut().map(|(_ptr, addl)| addl)
+    }
+
+    /// Obtain a shared reference to the additional data of the level at `depth` (the root is at depth 0),
+    /// or None if `depth` is above the top.
+    ///
+    /// For example, with the length in bytes of every node of a parse tree (including its children) as the additional data,
+    /// the lengths of the ancestors can be corrected from deep in the tree without ascending:
+    ///
+    /// ```
+    /// # use generic_cursors::{tree::Node, with_data::MutRefStackWithData};
+    /// fn text_len(node: &Node<String>) -> usize {
+    ///     node.data.len() + node.children().iter().map(text_len).sum::<usize>()
+    /// }
+    ///
+    /// let mut root = Node::new("fn ".to_string());
+    /// root.push_child(Node::new("main".to_string()))
+    ///     .push_child(Node::new("()".to_string()));
+    /// let root_len = text_len(&root);
+    /// let mut stack = MutRefStackWithData::new(&mut root, root_len);
+    /// while stack
+    ///     .descend_with(|node, _| {
+    ///         let child = node.children_mut().first_mut()?;
+    ///         let len = text_len(child);
+    ///         Some((child, len))
+    ///     })
+    ///     .is_some()
+    /// {}
+    /// assert_eq!(stack.data_at(1), Some(&6));
+    ///
+    /// let (node, len, depth) = stack.top_full();
+    /// node.data.insert_str(1, "x: u32");
+    /// let inserted = "x: u32".len();
+    /// *len += inserted;
+    /// for ancestor in 0..depth {
+    ///     *stack.data_at_mut(ancestor).unwrap() += inserted;
+    /// }
+    /// assert!(stack.data_iter().copied().eq([15, 12, 8]));
+    /// assert_eq!(stack.data_at(depth + 1), None);
+    /// ```
+    pub fn data_at(&self, depth: usize) -> Option<&U> {
+        self.data.get(depth).map(|(_ptr, addl)| addl)
+    }
+
+    /// Obtain a mutable reference to the additional data of the level at `depth` (the root is at depth 0),
+    /// or None if `depth` is above the top.
+    pub fn data_at_mut(&mut self, depth: usize) -> Option<&mut U> {
+        self.data.get_mut(depth).map(|(_ptr, addl)| addl)
+    }
+
+    /// Obtain a shared reference to the additional data of the root.
+    pub fn root_data(&self) -> &U {
+        &self.data[0].1
+    }
+
+    /// Obtain a mutable reference to the additional data of the root.
+    pub fn root_data_mut(&mut self) -> &mut U {
+        &mut self.data[0].1
+    }
+
+    /// Obtain a mutable reference to the top of the stack and its additional data, along with its depth.
+    pub fn top_full(&mut self) -> (&mut T, &mut U, usize) {
+        let depth = self.depth();
+        let (top, addl) = self.top_mut();
+        (top, addl, depth)
+    }
+
     /// Descend into the recursive data structure, returning a mutable reference to the new top element.
     /// Rust's borrow checker enforces that the closure cannot inject any lifetime (other than `'static`),
     /// because the closure must work for any lifetime `'node`.
@@ -71,6 +307,15 @@ impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
         Some(self.top_mut())
     }
 
+    /// Descend like `descend_with`, but the closure only gets a shared reference to the top's additional data,
+    /// from which the new top's additional data is derived (e.g. a depth of one more than the parent's).
+    pub fn descend_deriving(
+        &mut self,
+        f: impl for<'node, 'addl> FnOnce(&'node mut T, &'addl U) -> Option<(&'node mut T, U)>,
+    ) -> Option<(&mut T, &mut U)> {
+        self.descend_with(|node, addl| f(node, addl))
+    }
+
     /// Inject a new reference to the top of the stack. The reference still must live
     /// as long as the root of the stack.
     pub fn inject_with(
@@ -83,6 +328,27 @@ impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
         Some(self.top_mut())
     }
 
+    /// Inject a new reference to the top of the stack, with the additional data `data`.
+    /// The reference still must live as long as the root of the stack.
+    pub fn inject_top(&mut self, new_top: &'root mut T, data: U) -> (&mut T, &mut U) {
+        self.data.push((new_top, data));
+        self.top_mut()
+    }
+
+    /// Inject every reference and its additional data in order, like calling `inject_top` with each,
+    /// returning a mutable reference to the new top.
+    pub fn inject_iter(
+        &mut self,
+        items: impl IntoIterator<Item = (&'root mut T, U)>,
+    ) -> (&mut T, &mut U) {
+        self.data.extend(
+            items
+                .into_iter()
+                .map(|(new_top, data)| (new_top as *mut T, data)),
+        );
+        self.top_mut()
+    }
+
     /// Ascend back up from the recursive data structure, returning a mutable reference to the new top element, if it changed.
     /// If we are not currently at the root, ascend and return a reference to the new top, a reference to the new top's additional data, and the old top's additional data.
     /// If we are already the root, returns None (the top is the root and does not change).
@@ -91,34 +357,169 @@ impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
             0 => unreachable!("root pointer must always exist"),
             1 => None,
             _ => {
-                let Some((_ptr, addl)) = self.data.pop() else { unreachable!() };
+                let Some((_ptr, addl)) = self.data.pop() else {
+                    unreachable!()
+                };
                 Some((self.top_mut(), addl))
             }
         }
     }
 
+    /// Ascend like `ascend`, but drop the old top's additional data in place instead of returning it.
+    /// If we are already the root, returns None (the top is the root and does not change).
+    pub fn ascend_drop(&mut self) -> Option<(&mut T, &mut U)> {
+        if self.is_at_root() {
+            return None;
+        }
+        self.data.truncate(self.data.len() - 1);
+        Some(self.top_mut())
+    }
+
+    /// Ascend like `ascend`, but instead of returning the old top's additional data,
+    /// pass it to the closure together with the new top's additional data, e.g. to accumulate a result bottom-up.
+    /// If we are already the root, returns None without calling the closure.
+    pub fn ascend_fold(&mut self, f: impl FnOnce(U, &mut U)) -> Option<(&mut T, &mut U)> {
+        if self.is_at_root() {
+            return None;
+        }
+        let (_ptr, addl) = self.data.pop().unwrap();
+        f(addl, &mut self.data.last_mut().unwrap().1);
+        Some(self.top_mut())
+    }
+
+    /// Ascend to the root, returning the additional data of every level above it, ordered from the root's child to the old top.
+    /// The root's additional data stays in place.
+    pub fn to_root(&mut self) -> Vec<U> {
+        self.data.drain(1..).map(|(_ptr, addl)| addl).collect()
+    }
+
+    /// Replace the root's additional data with `data`, returning the old value, e.g. to reuse the stack for another traversal.
+    /// Fails if the stack is not at its root, giving `data` back.
+    pub fn reset_root_data(&mut self, data: U) -> Result<U, NotAtRoot<U>> {
+        if !self.is_at_root() {
+            return Err(NotAtRoot {
+                depth: self.depth(),
+                data,
+            });
+        }
+        Ok(std::mem::replace(&mut self.data[0].1, data))
+    }
+
+    /// Ascend to the root like `to_root`, but drop the additional data of every level above it in place,
+    /// from the top down.
+    pub fn clear_to_root_discarding(&mut self) {
+        while self.ascend_drop().is_some() {}
+    }
+
+    /// Ascend like `ascend_fold` while the predicate returns `true`, folding the additional data of every level ascended out of
+    /// into its parent's with `combine`, returning a mutable reference to the new top element and its additional data.
+    /// For example, `ascend_while_fold(|_, _| true, combine)` ascends to the root, folding every level into it.
+    pub fn ascend_while_fold<P>(
+        &mut self,
+        mut predicate: P,
+        mut combine: impl FnMut(U, &mut U),
+    ) -> (&mut T, &mut U)
+    where
+        P: FnMut(&mut T, &mut U) -> bool,
+    {
+        while !self.is_at_root() {
+            let (top, addl) = self.top_mut();
+            if !predicate(top, addl) {
+                break;
+            }
+            let Some(_) = self.ascend_fold(&mut combine) else {
+                unreachable!()
+            };
+        }
+        self.top_mut()
+    }
+
     /// Ascend back up from the recursive data structure while the given closure returns `true`, returning a mutable reference to the new top element.
     /// If we are not currently at the root, and the predicate returns `true`, ascend and continue.
     /// If we are already at the root, or if the predicate returned false, returns a reference to the top element.
-    pub fn ascend_while<P>(
+    /// Also returns the additional data of every level ascended out of, ordered from the new top's child to the old top.
+    ///
+    /// Each level is popped before the predicate is called on its parent, so the predicate may freely modify the node it is given.
+    /// If the predicate panics, the stack is left at the level the predicate panicked on,
+    /// and is still usable if the panic is caught; the levels already ascended stay ascended (and their additional data is dropped).
+    pub fn ascend_while<P>(&mut self, mut predicate: P) -> ((&mut T, &mut U), AscendedData<U>)
+    where
+        P: FnMut(&mut T, &mut U) -> bool,
+    {
+        self.ascend_while_indexed(|top, addl, _depth| predicate(top, addl))
+    }
+
+    /// Ascend like `ascend_while`, additionally passing the predicate the depth of the node it is called with.
+    /// For example, `ascend_while_indexed(|_, _, depth| depth > 2)` ascends until the top is at depth 2.
+    pub fn ascend_while_indexed<P>(
         &mut self,
         mut predicate: P,
-    ) -> ((&mut T, &mut U), impl IntoIterator<Item = U>)
+    ) -> ((&mut T, &mut U), AscendedData<U>)
     where
-        P: FnMut(&mut T, &mut U) -> bool,
+        P: FnMut(&mut T, &mut U, usize) -> bool,
     {
-        let mut items = VecDeque::new();
+        let mut ascended = Vec::new();
         while !self.is_at_root() {
+            let depth = self.depth();
             let (top, addl) = self.top_mut();
-            if !predicate(top, addl) {
+            if !predicate(top, addl, depth) {
                 break;
             }
-            let Some((_top, addl)) = self.ascend() else {
+            let Some((_ptr, addl)) = self.data.pop() else {
                 unreachable!()
             };
-            items.push_front(addl);
+            ascended.push(addl);
+        }
+        // Popped from the old top down, but returned from the new top's child up.
+        ascended.reverse();
+        (self.top_mut(), AscendedData::new(ascended))
+    }
+
+    /// Visit every node below the current top in preorder, without moving the stack.
+    /// `children(node, data, n)` should return the `n`th child of `node` and its additional data, or None if there is no such child.
+    /// `visit` is called with each descendant, its additional data, and its depth relative to the current top (starting at 1).
+    /// The stack is restored to its original position on return, even if a closure panics.
+    pub fn for_each_descendant(
+        &mut self,
+        mut children: impl for<'node, 'addl> FnMut(
+            &'node mut T,
+            &'addl mut U,
+            usize,
+        ) -> Option<(&'node mut T, U)>,
+        mut visit: impl FnMut(&mut T, &mut U, usize),
+    ) {
+        struct RestoreDepth<'a, 'root, T: ?Sized, U> {
+            stack: &'a mut MutRefStackWithData<'root, T, U>,
+            len: usize,
+        }
+        impl<T: ?Sized, U> Drop for RestoreDepth<'_, '_, T, U> {
+            fn drop(&mut self) {
+                while self.stack.data.len() > self.len {
+                    self.stack.data.pop();
+                }
+            }
+        }
+        let len = self.data.len();
+        let guard = RestoreDepth { stack: self, len };
+        // The index of the next child to visit, for each level descended into since the start.
+        let mut next_child = vec![0];
+        while let Some(&index) = next_child.last() {
+            if guard
+                .stack
+                .descend_with(|node, addl| children(node, addl, index))
+                .is_some()
+            {
+                *next_child.last_mut().unwrap() += 1;
+                let (node, addl) = guard.stack.top_mut();
+                visit(node, addl, next_child.len());
+                next_child.push(0);
+            } else {
+                next_child.pop();
+                if !next_child.is_empty() {
+                    guard.stack.ascend();
+                }
+            }
         }
-        (self.top_mut(), items)
     }
 
     /// Ascend from, descend from, inject a new top, or stay at the current node,
@@ -127,14 +528,22 @@ impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
         &mut self,
         f: impl for<'node, 'addl> FnOnce(&'node mut T, &'addl mut U) -> MoveDecision<'root, 'node, T, U>,
     ) -> Result<((&mut T, &mut U), Option<U>), MoveError> {
-        let top = self.top_mut();
-        let result = f(top.0, top.1);
+        let (top, addl) = self.top_mut();
+        let (top, addl): (*mut T, *mut U) = (top, addl);
+        // SAFETY: `MoveDecision::AscendFold` may borrow from the old top, so the decision cannot be tied to
+        // the borrow of `self`. The old top's node is not accessed again until the decision has been used,
+        // and the closure cannot return a borrow of `addl`.
+        let result = unsafe { f(&mut *top, &mut *addl) };
         match result {
             MoveDecision::Ascend => {
                 let (top, old_addl) = self.ascend().ok_or(MoveError::AscendAtRoot)?;
                 Ok((top, Some(old_addl)))
             }
             MoveDecision::Stay => Ok((self.top_mut(), None)),
+            MoveDecision::AscendFold(fold) => {
+                let top = self.ascend_fold(fold).ok_or(MoveError::AscendAtRoot)?;
+                Ok((top, None))
+            }
             MoveDecision::Inject(new_top, new_addl) | MoveDecision::Descend(new_top, new_addl) => {
                 let new_top: *mut T = new_top;
                 self.data.push((new_top, new_addl));
@@ -143,9 +552,617 @@ impl<'root, T: ?Sized, U> MutRefStackWithData<'root, T, U> {
         }
     }
 
+    /// Replace the additional data of every level with the result of the closure, called with its depth and the old data,
+    /// from the root to the top. The nodes on the stack do not change.
+    pub fn map_data<V>(self, mut f: impl FnMut(usize, U) -> V) -> MutRefStackWithData<'root, T, V> {
+        MutRefStackWithData {
+            lifetime: PhantomData,
+            data: self
+                .data
+                .into_iter()
+                .enumerate()
+                .map(|(depth, (ptr, addl))| (ptr, f(depth, addl)))
+                .collect(),
+        }
+    }
+
+    /// Replace the additional data of every level like `map_data`, but the closure only borrows the old data, and may fail.
+    /// If it fails, the new data computed so far is dropped, and the stack is returned unchanged along with the error.
+    pub fn try_map_data<V, E>(
+        self,
+        mut f: impl FnMut(usize, &U) -> Result<V, E>,
+    ) -> Result<MutRefStackWithData<'root, T, V>, (Self, E)> {
+        let mut new_data = Vec::with_capacity(self.data.len());
+        for (depth, (_ptr, addl)) in self.data.iter().enumerate() {
+            match f(depth, addl) {
+                Ok(new_addl) => new_data.push(new_addl),
+                Err(err) => return Err((self, err)),
+            }
+        }
+        Ok(MutRefStackWithData {
+            lifetime: PhantomData,
+            data: self
+                .data
+                .into_iter()
+                .zip(new_data)
+                .map(|((ptr, _addl), new_addl)| (ptr, new_addl))
+                .collect(),
+        })
+    }
+
     /// Return reference to the top element of this stack, forgetting about the stack entirely.
-    pub fn into_top(self) -> &'root mut T {
+    /// The additional data of every level is dropped from the top down, ending with the root's.
+    pub fn into_top(mut self) -> &'root mut T {
         let ptr = self.data.last().unwrap().0;
+        // Dropping the `Vec` would drop the additional data from the root up.
+        while self.data.pop().is_some() {}
         unsafe { &mut *ptr }
     }
+
+    /// Return reference to the top element of this stack and its additional data, forgetting about the stack entirely.
+    /// The additional data of the other levels is dropped from the top down, like in `into_top`.
+    pub fn into_top_with_data(mut self) -> (&'root mut T, U) {
+        let (ptr, addl) = self.data.pop().unwrap();
+        while self.data.pop().is_some() {}
+        (unsafe { &mut *ptr }, addl)
+    }
+
+    /// Return reference to the top element of this stack and the additional data of every level,
+    /// ordered from the root's to the top's, forgetting about the stack entirely.
+    pub fn into_parts(self) -> (&'root mut T, Vec<U>) {
+        let ptr = self.data.last().unwrap().0;
+        let data = self.data.into_iter().map(|(_ptr, addl)| addl).collect();
+        (unsafe { &mut *ptr }, data)
+    }
+
+    /// Ascend to the root and return it and its additional data, consuming the stack.
+    /// The additional data of the other levels is dropped from the top down.
+    pub fn into_root(mut self) -> (&'root mut T, U) {
+        self.clear_to_root_discarding();
+        let (ptr, addl) = self.data.pop().unwrap();
+        (unsafe { &mut *ptr }, addl)
+    }
+}
+
+/// Traverse the recursive data structure below (and including) `root` in postorder.
+/// `children(node, n)` should return the `n`th child of `node`, or None if there is no such child.
+/// `visit` is called with each node and its depth (the root is at depth 0) when the traversal ascends out of it,
+/// i.e. after all of its children have been visited.
+pub fn traverse_postorder<T: ?Sized, C, F>(root: &mut T, mut children: C, mut visit: F)
+where
+    C: for<'node> FnMut(&'node mut T, usize) -> Option<&'node mut T>,
+    F: FnMut(&mut T, usize),
+{
+    struct TraversalState {
+        next_index: usize,
+        depth: usize,
+    }
+    let mut cursor = MutRefStackWithData::new(
+        root,
+        TraversalState {
+            next_index: 0,
+            depth: 0,
+        },
+    );
+    while cursor
+        .move_with(|node, state| {
+            let node: *mut T = node;
+            // SAFETY: `node` is only reborrowed once at a time: the reborrow passed to `children`
+            // is either returned in the decision, or has ended by the time `visit` is called.
+            match children(unsafe { &mut *node }, state.next_index) {
+                Some(child) => {
+                    state.next_index += 1;
+                    MoveDecision::Descend(
+                        child,
+                        TraversalState {
+                            next_index: 0,
+                            depth: state.depth + 1,
+                        },
+                    )
+                }
+                None => {
+                    visit(unsafe { &mut *node }, state.depth);
+                    MoveDecision::Ascend
+                }
+            }
+        })
+        .is_ok()
+    {}
+}
+
+/// Count the nodes in the recursive data structure below (and including) `root`.
+/// `children(node)` should return the children of `node`.
+pub fn count_subtree<T>(
+    root: &mut T,
+    mut children: impl for<'node> FnMut(&'node mut T) -> &'node mut [T],
+) -> usize {
+    let mut count = 0;
+    let mut cursor = MutRefStackWithData::new(std::slice::from_mut(root), 0);
+    while cursor
+        .move_with(|siblings, next_index| {
+            if *next_index >= siblings.len() {
+                MoveDecision::Ascend
+            } else {
+                count += 1;
+                let decision = MoveDecision::Descend(children(&mut siblings[*next_index]), 0);
+                *next_index += 1;
+                decision
+            }
+        })
+        .is_ok()
+    {}
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::tree::{Node, Tree};
+
+    /// ```text
+    /// 0
+    /// ├── 1
+    /// │   ├── 3
+    /// │   └── 4
+    /// └── 2
+    ///     └── 5
+    /// ```
+    fn small_tree() -> Node<u32> {
+        let mut root = Node::new(0);
+        let one = root.push_child(Node::new(1));
+        one.push_child(Node::new(3));
+        one.push_child(Node::new(4));
+        root.push_child(Node::new(2)).push_child(Node::new(5));
+        root
+    }
+
+    #[test]
+    fn traverse_postorder_order() {
+        let mut root = small_tree();
+        let mut visited = vec![];
+        traverse_postorder(
+            &mut root,
+            |node, n| node.children_mut().get_mut(n),
+            |node, depth| visited.push((node.data, depth)),
+        );
+        assert_eq!(visited, [(3, 2), (4, 2), (1, 1), (5, 2), (2, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn traverse_postorder_leaf() {
+        let mut root = Node::new(7);
+        let mut visited = vec![];
+        traverse_postorder(
+            &mut root,
+            |node, n| node.children_mut().get_mut(n),
+            |node, depth| visited.push((node.data, depth)),
+        );
+        assert_eq!(visited, [(7, 0)]);
+    }
+
+    /// The forest from the `forest` example.
+    fn forest() -> Tree<u32> {
+        let mut tree = Tree::new();
+        let zero = tree.push_root(Node::new(0));
+        for value in 1..=3 {
+            zero.push_child(Node::new(value));
+        }
+        tree.push_root(Node::new(4));
+        tree.push_root(Node::new(5));
+        tree.push_root(Node::new(6))
+            .push_child(Node::new(7))
+            .push_child(Node::new(8))
+            .push_child(Node::new(9));
+        tree
+    }
+
+    #[test]
+    fn count_subtree_forest() {
+        let mut tree = forest();
+        let counts: Vec<usize> = tree
+            .roots_mut()
+            .iter_mut()
+            .map(|root| count_subtree(root, |node| node.children_mut()))
+            .collect();
+        assert_eq!(counts, [4, 1, 1, 4]);
+        assert_eq!(counts.iter().sum::<usize>(), 10);
+    }
+
+    /// The additional data of a level of sibling nodes while summing subtrees.
+    struct Partial {
+        /// The value of the parent of the siblings, or None for the roots.
+        parent: Option<u32>,
+        /// The index of the next sibling to visit.
+        next: usize,
+        /// The sum of the parent and of the subtrees of the siblings visited so far.
+        sum: u32,
+        /// The value and subtree sum of every node below the parent whose subtree has been summed.
+        sums: Vec<(u32, u32)>,
+    }
+
+    impl Partial {
+        fn new(parent: Option<u32>) -> Self {
+            let sum = parent.unwrap_or(0);
+            let sums = vec![];
+            Self {
+                parent,
+                next: 0,
+                sum,
+                sums,
+            }
+        }
+
+        /// Fold the finished level `child` into its parent level.
+        fn fold(child: Partial, parent: &mut Partial) {
+            parent.sums.extend(child.sums);
+            parent.sums.push((child.parent.unwrap(), child.sum));
+            parent.sum += child.sum;
+        }
+
+        /// Descend from the siblings into the children of the next one, if any.
+        fn descend_next<'node>(
+            siblings: &'node mut [Node<u32>],
+            partial: &mut Partial,
+        ) -> Option<(&'node mut [Node<u32>], Partial)> {
+            let node = siblings.get_mut(partial.next)?;
+            partial.next += 1;
+            let parent = Partial::new(Some(node.data));
+            Some((node.children_mut(), parent))
+        }
+
+        /// Check the sums collected at the root of `forest()`.
+        fn assert_forest_sums(mut self) {
+            assert_eq!(self.sum, 45);
+            self.sums.sort_unstable();
+            assert_eq!(
+                self.sums,
+                [
+                    (0, 6),
+                    (1, 1),
+                    (2, 2),
+                    (3, 3),
+                    (4, 4),
+                    (5, 5),
+                    (6, 30),
+                    (7, 24),
+                    (8, 17),
+                    (9, 9),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn subtree_sums_with_ascend_fold_decision() {
+        let mut tree = forest();
+        let mut cursor = tree.cursor(Partial::new(None));
+        while cursor
+            .move_with(
+                |siblings, partial| match Partial::descend_next(siblings, partial) {
+                    Some((children, parent)) => MoveDecision::Descend(children, parent),
+                    None => MoveDecision::AscendFold(Box::new(Partial::fold)),
+                },
+            )
+            .is_ok()
+        {}
+        assert!(cursor.is_at_root());
+        cursor.into_root().1.assert_forest_sums();
+    }
+
+    #[test]
+    fn subtree_sums_with_ascend_while_fold() {
+        let mut tree = forest();
+        let mut cursor = tree.cursor(Partial::new(None));
+        loop {
+            if cursor.descend_with(Partial::descend_next).is_some() {
+                continue;
+            }
+            if cursor.is_at_root() {
+                break;
+            }
+            // Fold every finished level at once, stopping at the first one with siblings left to visit.
+            let (siblings, partial) = cursor.ascend_while_fold(
+                |siblings, partial| partial.next >= siblings.len(),
+                Partial::fold,
+            );
+            assert!(partial.next < siblings.len() || partial.parent.is_none());
+        }
+        cursor.into_root().1.assert_forest_sums();
+    }
+
+    /// Additional data that records its id in a shared log when it is dropped.
+    struct DropLog<'a> {
+        id: usize,
+        log: &'a RefCell<Vec<usize>>,
+    }
+
+    impl Drop for DropLog<'_> {
+        fn drop(&mut self) {
+            self.log.borrow_mut().push(self.id);
+        }
+    }
+
+    /// A chain of `len` nodes with the values `0..len`.
+    fn chain(len: u32) -> Node<u32> {
+        (0..len - 1).rev().fold(Node::new(len - 1), |child, value| {
+            let mut node = Node::new(value);
+            node.push_child(child);
+            node
+        })
+    }
+
+    /// Make a stack over `root` whose additional data is a `DropLog` with the depth of each level,
+    /// and descend to the bottom of the chain.
+    fn descend_logged<'root, 'a>(
+        root: &'root mut Node<u32>,
+        log: &'a RefCell<Vec<usize>>,
+    ) -> MutRefStackWithData<'root, Node<u32>, DropLog<'a>> {
+        let mut stack = MutRefStackWithData::new(root, DropLog { id: 0, log });
+        while stack
+            .descend_with(|node, data| {
+                let id = data.id + 1;
+                Some((node.children_mut().first_mut()?, DropLog { id, log }))
+            })
+            .is_some()
+        {}
+        stack
+    }
+
+    #[test]
+    fn into_top_drops_data_from_the_top_down() {
+        let mut root = chain(4);
+        let log = RefCell::new(vec![]);
+        let stack = descend_logged(&mut root, &log);
+        let top = stack.into_top();
+        assert_eq!(top.data, 3);
+        assert_eq!(*log.borrow(), [3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn into_top_with_data_drops_the_rest_from_the_top_down() {
+        let mut root = chain(4);
+        let log = RefCell::new(vec![]);
+        let stack = descend_logged(&mut root, &log);
+        let (top, data) = stack.into_top_with_data();
+        assert_eq!((top.data, data.id), (3, 3));
+        assert_eq!(*log.borrow(), [2, 1, 0]);
+        drop(data);
+        assert_eq!(*log.borrow(), [2, 1, 0, 3]);
+    }
+
+    #[test]
+    fn into_root_drops_the_rest_from_the_top_down() {
+        let mut root = chain(4);
+        let log = RefCell::new(vec![]);
+        let stack = descend_logged(&mut root, &log);
+        let (root, data) = stack.into_root();
+        assert_eq!((root.data, data.id), (0, 0));
+        assert_eq!(*log.borrow(), [3, 2, 1]);
+        drop(data);
+        assert_eq!(*log.borrow(), [3, 2, 1, 0]);
+    }
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    #[test]
+    fn auto_traits() {
+        assert_send::<MutRefStackWithData<'_, Node<u32>, usize>>();
+        assert_sync::<MutRefStackWithData<'_, Node<u32>, usize>>();
+        assert_send::<MutRefStackWithData<'_, [Node<u32>], String>>();
+        assert_sync::<MutRefStackWithData<'_, [Node<u32>], String>>();
+    }
+
+    #[test]
+    fn send_to_another_thread() {
+        let mut root = chain(4);
+        let mut stack = MutRefStackWithData::new(&mut root, 0);
+        stack
+            .descend_with(|node, &mut depth| Some((node.children_mut().first_mut()?, depth + 1)))
+            .unwrap();
+        let stack = std::thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    let (top, depth) = stack
+                        .descend_with(|node, &mut depth| {
+                            Some((node.children_mut().first_mut()?, depth + 1))
+                        })
+                        .unwrap();
+                    top.data += 10;
+                    *depth += 10;
+                    stack
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(stack.depth(), 2);
+        let (top, depth) = stack.top();
+        assert_eq!((top.data, *depth), (12, 12));
+    }
+
+    /// Make a stack over `root` whose additional data is the depth of each level, and descend to the bottom of the chain.
+    fn descend_counting(root: &mut Node<u32>) -> MutRefStackWithData<'_, Node<u32>, usize> {
+        let mut stack = MutRefStackWithData::new(root, 0);
+        while stack
+            .descend_with(|node, &mut depth| Some((node.children_mut().first_mut()?, depth + 1)))
+            .is_some()
+        {}
+        stack
+    }
+
+    #[test]
+    fn ascend_while_returns_data_from_the_new_tops_child_up() {
+        let mut root = chain(5);
+        let mut stack = descend_counting(&mut root);
+        let ((top, depth), mut ascended) = stack.ascend_while(|node, _| node.data > 1);
+        assert_eq!((top.data, *depth), (1, 1));
+        assert_eq!(ascended.len(), 3);
+        assert_eq!(ascended.next_back(), Some(4));
+        assert_eq!(ascended.next(), Some(2));
+        assert_eq!(ascended.len(), 1);
+        assert_eq!(ascended.into_vec(), [3]);
+        assert_eq!(stack.depth(), 1);
+    }
+
+    #[test]
+    fn ascend_while_stops_at_the_root() {
+        let mut root = chain(3);
+        let mut stack = descend_counting(&mut root);
+        let ((top, _), ascended) = stack.ascend_while(|_, _| true);
+        assert_eq!(top.data, 0);
+        assert_eq!(ascended.into_vec(), [1, 2]);
+        assert!(stack.is_at_root());
+    }
+
+    #[test]
+    fn ascend_while_panic_after_modifying_an_ancestor() {
+        let mut root = chain(4);
+        let mut stack = descend_counting(&mut root);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            stack.ascend_while(|node, _| {
+                if node.data == 1 {
+                    // Drop the children. The levels above were already popped, so this does not invalidate the stack.
+                    *node = Node::new(node.data);
+                    panic!("predicate panicked");
+                }
+                true
+            });
+        }));
+        assert!(result.is_err());
+        assert_eq!(stack.depth(), 1);
+        let (top, depth) = stack.top_mut();
+        assert_eq!((top.data, *depth), (1, 1));
+        assert!(top.children().is_empty());
+        assert!(stack
+            .descend_with(|node, &mut depth| Some((node.children_mut().first_mut()?, depth + 1)))
+            .is_none());
+        assert_eq!(
+            stack.ascend().map(|((top, _), data)| (top.data, data)),
+            Some((0, 1))
+        );
+    }
+
+    #[test]
+    fn raw_top_is_the_stored_pointer() {
+        let mut root = chain(3);
+        let mut stack = MutRefStackWithData::new(&mut root, ());
+        stack
+            .descend_with(|node, _| Some((node.children_mut().first_mut()?, ())))
+            .unwrap();
+        let top = stack.raw_top_mut();
+        assert_eq!(stack.raw_top(), top.cast_const());
+        assert_eq!(stack.top_node().data, 1);
+        unsafe { (*top).data = 10 };
+        assert_eq!(stack.top_node().data, 10);
+        assert_eq!(stack.raw_top_mut(), top);
+    }
+
+    #[test]
+    fn descend_deriving_depth_from_the_parent() {
+        let mut root = chain(4);
+        let mut stack = MutRefStackWithData::new(&mut root, 0);
+        while let Some((top, depth)) = stack
+            .descend_deriving(|node, &depth| Some((node.children_mut().first_mut()?, depth + 1)))
+        {
+            assert_eq!(top.data, *depth);
+        }
+        assert_eq!(stack.depth(), 3);
+        assert!(stack.data_iter().copied().eq(0..=3));
+    }
+
+    #[test]
+    fn to_root_returns_data_from_the_roots_child_up() {
+        let mut root = chain(4);
+        let root_ptr: *const Node<u32> = &root;
+        let mut stack = descend_counting(&mut root);
+        let top_ptr = stack.raw_top();
+        assert_eq!(stack.to_root(), [1, 2, 3]);
+        assert!(stack.is_at_root());
+        assert_eq!(stack.raw_top(), root_ptr);
+        assert_eq!(stack.root_data(), &0);
+        assert!(stack.to_root().is_empty());
+        drop(stack);
+        // Only the stack was unwound; the nodes themselves are untouched.
+        let mut node = &root;
+        let mut values = vec![node.data];
+        while let Some(child) = node.children().first() {
+            node = child;
+            values.push(node.data);
+        }
+        assert_eq!(values, [0, 1, 2, 3]);
+        assert!(std::ptr::eq(node, top_ptr));
+    }
+
+    #[test]
+    fn injected_data_is_returned_on_ascend() {
+        let mut injected: Vec<Node<u32>> = (10..14).map(Node::new).collect();
+        let mut root = Node::new(0);
+        let mut stack = MutRefStackWithData::new(&mut root, "root");
+        let (first, rest) = injected.split_first_mut().unwrap();
+        let (top, data) = stack.inject_top(first, "first");
+        assert_eq!((top.data, *data), (10, "first"));
+        let (top, data) = stack.inject_iter(rest.iter_mut().zip(["a", "b", "c"]));
+        assert_eq!((top.data, *data), (13, "c"));
+        let (top, data) = stack.inject_iter([]);
+        assert_eq!((top.data, *data), (13, "c"));
+
+        let mut ascended = vec![];
+        while let Some((_new_top, data)) = stack.ascend() {
+            ascended.push(data);
+        }
+        assert_eq!(ascended, ["c", "b", "a", "first"]);
+        assert_eq!(stack.root_data(), &"root");
+    }
+
+    #[test]
+    fn pooled_cursor_after_reset_root_data() {
+        /// Sum the chain below the top into the root's additional data.
+        fn sum(stack: &mut MutRefStackWithData<'_, Node<u32>, u32>) -> u32 {
+            while stack
+                .descend_with(|node, _| {
+                    let child = node.children_mut().first_mut()?;
+                    let value = child.data;
+                    Some((child, value))
+                })
+                .is_some()
+            {}
+            stack.ascend_while_fold(|_, _| true, |child, parent| *parent += child);
+            *stack.root_data()
+        }
+
+        let mut root = chain(5);
+        let fresh = sum(&mut MutRefStackWithData::new(&mut root, 0));
+        assert_eq!(fresh, 10);
+
+        let mut stack = MutRefStackWithData::new(&mut root, 0);
+        assert_eq!(sum(&mut stack), fresh);
+        stack
+            .descend_with(|node, _| Some((node.children_mut().first_mut()?, 0)))
+            .unwrap();
+        let err = stack.reset_root_data(7).unwrap_err();
+        assert_eq!((err.depth, err.data), (1, 7));
+        stack.to_root();
+        assert_eq!(stack.reset_root_data(0), Ok(fresh));
+        assert_eq!(sum(&mut stack), fresh);
+        assert_eq!(stack.reset_root_data(0), Ok(fresh));
+    }
+
+    #[test]
+    fn top_mut_with_parent_data_at_the_root() {
+        let mut root = chain(3);
+        let mut stack = MutRefStackWithData::new(&mut root, 0);
+        let ((top, data), parent) = stack.top_mut_with_parent_data();
+        assert_eq!((top.data, *data), (0, 0));
+        assert!(parent.is_none());
+
+        let mut stack = descend_counting(&mut root);
+        let ((top, data), parent) = stack.top_mut_with_parent_data();
+        assert_eq!((top.data, *data), (2, 2));
+        let parent = parent.unwrap();
+        assert_eq!(*parent, 1);
+        *parent = 10;
+        assert_eq!(stack.ascend().map(|((_, data), _)| *data), Some(10));
+    }
 }